@@ -0,0 +1,394 @@
+// 追加缓冲区
+//
+// 职责：以不可变块保存所有插入过的内容，只追加、不修改，
+//       同一文档的所有 PieceTable 快照共享同一份存储
+//
+// 块可以引用其他缓冲区的内容（跨文档粘贴），引用只增加计数、不复制字节
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::core::buffer::byte_slice::ByteSlice;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::buffer::mmap::{MmapBuffer, MmapSlices};
+
+/// 第一个分段的块槽数量，后续分段依次翻倍
+const FIRST_SEGMENT_LEN: usize = 64;
+/// 分段数量（64 * (2^40 - 1) 个块，足够任何会话使用）
+const SEGMENT_COUNT: usize = 40;
+
+/// 不可变字节块
+#[derive(Debug)]
+struct AddBlock {
+    /// 块在追加地址空间中的起始位置
+    start: usize,
+    /// 块内容
+    data: BlockData,
+}
+
+/// 块内容：共享字节或内存映射中的一段
+#[derive(Debug, Clone)]
+pub(super) enum BlockData {
+    /// 共享字节中的一段（插入的文本、复制的字节、内存中的原始内容）
+    Bytes { bytes: Arc<[u8]>, range: Range<usize> },
+
+    /// 内存映射缓冲区中的一段（引用其他文档原始文件的私有副本）
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped { mmap: Arc<MmapBuffer>, range: Range<usize> },
+}
+
+impl BlockData {
+    /// 复制字节得到的块
+    pub(super) fn owned(bytes: &[u8]) -> Self {
+        BlockData::Bytes {
+            bytes: Arc::from(bytes),
+            range: 0..bytes.len(),
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        match self {
+            BlockData::Bytes { range, .. } => range.len(),
+            #[cfg(not(target_arch = "wasm32"))]
+            BlockData::Mapped { range, .. } => range.len(),
+        }
+    }
+
+    /// 子范围（相对于本段起点）
+    pub(super) fn slice(&self, sub: Range<usize>) -> Self {
+        match self {
+            BlockData::Bytes { bytes, range } => BlockData::Bytes {
+                bytes: bytes.clone(),
+                range: range.start + sub.start..range.start + sub.end,
+            },
+            #[cfg(not(target_arch = "wasm32"))]
+            BlockData::Mapped { mmap, range } => BlockData::Mapped {
+                mmap: mmap.clone(),
+                range: range.start + sub.start..range.start + sub.end,
+            },
+        }
+    }
+}
+
+/// 共享存储：分段的只追加块数组
+///
+/// 块一旦写入就不再改变，因此读取无需加锁，
+/// 只有追加时通过互斥锁串行分配地址
+#[derive(Debug)]
+struct AddStore {
+    segments: [OnceLock<Box<[OnceLock<AddBlock>]>>; SEGMENT_COUNT],
+    /// 已发布（可读）的块数量
+    block_count: AtomicUsize,
+    /// 追加锁，保护地址空间的总长度
+    append_lock: Mutex<usize>,
+}
+
+impl AddStore {
+    fn new() -> Self {
+        Self {
+            segments: std::array::from_fn(|_| OnceLock::new()),
+            block_count: AtomicUsize::new(0),
+            append_lock: Mutex::new(0),
+        }
+    }
+
+    /// 块下标 → (分段, 段内下标)
+    fn locate(index: usize) -> (usize, usize) {
+        let scaled = index / FIRST_SEGMENT_LEN + 1;
+        let segment = (usize::BITS - 1 - scaled.leading_zeros()) as usize;
+        let segment_start = FIRST_SEGMENT_LEN * ((1 << segment) - 1);
+        (segment, index - segment_start)
+    }
+
+    fn block(&self, index: usize) -> &AddBlock {
+        let (segment, slot) = Self::locate(index);
+        self.segments[segment]
+            .get()
+            .and_then(|slots| slots[slot].get())
+            .expect("追加块尚未写入")
+    }
+
+    /// 依次追加多个块，返回它们占用的连续地址范围
+    fn push(&self, blocks: impl IntoIterator<Item = BlockData>) -> Range<usize> {
+        let mut total = self.append_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let start = *total;
+
+        for data in blocks {
+            if data.len() == 0 {
+                continue;
+            }
+
+            let index = self.block_count.load(Ordering::Acquire);
+            let (segment, slot) = Self::locate(index);
+            let slots = self.segments[segment].get_or_init(|| {
+                (0..FIRST_SEGMENT_LEN << segment).map(|_| OnceLock::new()).collect()
+            });
+
+            let len = data.len();
+            if slots[slot].set(AddBlock { start: *total, data }).is_err() {
+                unreachable!("追加块槽位被重复写入");
+            }
+
+            *total += len;
+            self.block_count.store(index + 1, Ordering::Release);
+        }
+
+        start..*total
+    }
+
+    /// 查找包含追加地址的块下标
+    fn find_block(&self, address: usize) -> usize {
+        let count = self.block_count.load(Ordering::Acquire);
+
+        // 二分查找最后一个 start <= address 的块
+        let (mut low, mut high) = (0, count);
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            if self.block(mid).start <= address {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        low
+    }
+}
+
+/// 追加缓冲区（克隆只增加引用计数）
+#[derive(Debug, Clone)]
+pub struct AddBuffer {
+    store: Arc<AddStore>,
+}
+
+impl AddBuffer {
+    pub fn new() -> Self {
+        Self {
+            store: Arc::new(AddStore::new()),
+        }
+    }
+
+    /// 追加文本，返回其在追加地址空间中的范围（O(插入字节数)）
+    pub fn append(&self, text: &str) -> Range<usize> {
+        self.append_bytes(text.as_bytes())
+    }
+
+    /// 追加任意字节（可以不是合法UTF-8），返回其地址范围
+    pub fn append_bytes(&self, bytes: &[u8]) -> Range<usize> {
+        if bytes.is_empty() {
+            let end = self.len();
+            return end..end;
+        }
+
+        self.store.push([BlockData::owned(bytes)])
+    }
+
+    /// 追加对其他缓冲区内容的引用（不复制字节），返回连续的地址范围
+    pub(super) fn append_shared(&self, blocks: Vec<BlockData>) -> Range<usize> {
+        self.store.push(blocks)
+    }
+
+    /// 地址范围内容的共享引用（按块分段，不复制字节）
+    pub(super) fn share_range(&self, range: Range<usize>) -> Vec<BlockData> {
+        let mut shared = Vec::new();
+        if range.start >= range.end {
+            return shared;
+        }
+
+        let count = self.store.block_count.load(Ordering::Acquire);
+        let mut index = self.store.find_block(range.start);
+        let mut start = range.start;
+        while start < range.end && index < count {
+            let block = self.store.block(index);
+            let from = start - block.start;
+            let to = (range.end - block.start).min(block.data.len());
+            shared.push(block.data.slice(from..to));
+
+            start = block.start + to;
+            index += 1;
+        }
+
+        shared
+    }
+
+    /// 已追加的总字节数
+    pub fn len(&self) -> usize {
+        *self.store.append_lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 已追加的块数量
+    pub fn block_count(&self) -> usize {
+        self.store.block_count.load(Ordering::Acquire)
+    }
+
+    /// 两个缓冲区是否共享同一份存储
+    pub fn shares_storage_with(&self, other: &AddBuffer) -> bool {
+        Arc::ptr_eq(&self.store, &other.store)
+    }
+
+    /// 按块遍历地址范围内的字节（零拷贝，引用的映射可能再按窗口分段）
+    pub fn chunks(&self, range: Range<usize>) -> AddChunks<'_> {
+        let index = if range.start < range.end {
+            self.store.find_block(range.start)
+        } else {
+            0
+        };
+
+        AddChunks {
+            store: &self.store,
+            index,
+            range,
+            #[cfg(not(target_arch = "wasm32"))]
+            mapped: None,
+        }
+    }
+
+    /// 范围内包含 address 的那段连续字节，返回 (切片起始地址, 切片)
+    pub fn chunk_containing(&self, address: usize, range: Range<usize>) -> Option<(usize, ByteSlice<'_>)> {
+        if address < range.start || address >= range.end || address >= self.len() {
+            return None;
+        }
+
+        let block = self.store.block(self.store.find_block(address));
+        let from = range.start.max(block.start) - block.start;
+        let to = (range.end - block.start).min(block.data.len());
+        match &block.data {
+            BlockData::Bytes { bytes, range } => Some((
+                block.start + from,
+                ByteSlice::Borrowed(&bytes[range.start + from..range.start + to]),
+            )),
+            #[cfg(not(target_arch = "wasm32"))]
+            BlockData::Mapped { mmap, range } => {
+                let offset = range.start + address - block.start;
+                let (start, slice) = mmap.slice_containing(offset, range.start + from..range.start + to)?;
+                Some((block.start + start - range.start, slice))
+            }
+        }
+    }
+
+    /// 获取追加地址处的字节
+    pub fn byte_at(&self, address: usize) -> Option<u8> {
+        self.chunks(address..address + 1)
+            .next()
+            .and_then(|chunk| chunk.first().copied())
+    }
+}
+
+impl Default for AddBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 地址范围的分块迭代器，每项是单个块内的连续字节切片
+pub struct AddChunks<'a> {
+    store: &'a AddStore,
+    index: usize,
+    range: Range<usize>,
+    /// 当前引用映射的块内剩余的分段
+    #[cfg(not(target_arch = "wasm32"))]
+    mapped: Option<MmapSlices<'a>>,
+}
+
+impl<'a> Iterator for AddChunks<'a> {
+    type Item = ByteSlice<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(slices) = &mut self.mapped {
+                if let Some(slice) = slices.next() {
+                    return Some(slice);
+                }
+                self.mapped = None;
+            }
+
+            if self.range.start >= self.range.end
+                || self.index >= self.store.block_count.load(Ordering::Acquire)
+            {
+                return None;
+            }
+
+            let block = self.store.block(self.index);
+            let from = self.range.start - block.start;
+            let to = (self.range.end - block.start).min(block.data.len());
+
+            self.index += 1;
+            self.range.start = block.start + to;
+
+            match &block.data {
+                BlockData::Bytes { bytes, range } => {
+                    return Some(ByteSlice::Borrowed(&bytes[range.start + from..range.start + to]));
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                BlockData::Mapped { mmap, range } => {
+                    self.mapped = Some(mmap.slices(range.start + from..range.start + to));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(buffer: &AddBuffer, range: Range<usize>) -> String {
+        let bytes: Vec<u8> = buffer.chunks(range).flat_map(|chunk| chunk.to_vec()).collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_append_and_read_across_blocks() {
+        let buffer = AddBuffer::new();
+        assert_eq!(buffer.append("Hello"), 0..5);
+        assert_eq!(buffer.append(", "), 5..7);
+        assert_eq!(buffer.append("world"), 7..12);
+
+        assert_eq!(buffer.len(), 12);
+        assert_eq!(buffer.block_count(), 3);
+        assert_eq!(read(&buffer, 0..12), "Hello, world");
+        assert_eq!(read(&buffer, 3..9), "lo, wo");
+        assert_eq!(buffer.byte_at(7), Some(b'w'));
+        assert_eq!(buffer.byte_at(12), None);
+    }
+
+    #[test]
+    fn test_append_raw_bytes() {
+        let buffer = AddBuffer::new();
+        buffer.append("ok");
+        assert_eq!(buffer.append_bytes(b"\xFF\x80"), 2..4);
+        assert_eq!(buffer.append_bytes(b""), 4..4);
+
+        let bytes: Vec<u8> = buffer.chunks(0..4).flat_map(|chunk| chunk.to_vec()).collect();
+        assert_eq!(bytes, b"ok\xFF\x80");
+    }
+
+    #[test]
+    fn test_clones_share_storage() {
+        let buffer = AddBuffer::new();
+        let snapshot = buffer.clone();
+        let range = buffer.append("shared");
+
+        assert!(snapshot.shares_storage_with(&buffer));
+        assert_eq!(read(&snapshot, range), "shared");
+    }
+
+    #[test]
+    fn test_many_blocks_span_segments() {
+        let buffer = AddBuffer::new();
+        for i in 0..1000 {
+            buffer.append(&format!("{:03}", i));
+        }
+
+        assert_eq!(buffer.len(), 3000);
+        assert_eq!(read(&buffer, 1497..1503), "499500");
+        assert_eq!(read(&buffer, 2997..3000), "999");
+    }
+}
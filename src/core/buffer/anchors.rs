@@ -0,0 +1,500 @@
+// 锚点
+//
+// 职责：记录随编辑移动的位置（光标、选区、书签、搜索结果），
+//       插入和删除时自动调整偏移
+//
+// 锚点按 (偏移, 方向) 排序保存在跨度树中，偏移记为与前一个锚点的距离：
+// 插入只修改插入点之后第一个锚点的距离，之后的锚点不需要平移；
+// 删除时范围内的锚点收缩到删除起点，只需重排这一段。
+// 锚点集合随 PieceTable 快照一起持久化，撤销回到旧版本时锚点也回到当时的位置
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::core::buffer::{
+    lines::Replacement,
+    piece_table::PieceTable,
+    span_tree::{Span, SpanTree},
+};
+
+/// 全局锚点编号（不同文档、不同分支的锚点也不会重复）
+static NEXT_ANCHOR_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 锚点编号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AnchorId(u64);
+
+/// 在锚点位置插入文本时锚点的去向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Gravity {
+    /// 留在插入文本之前（选区起点、书签）
+    Left,
+    /// 移到插入文本之后（光标、选区终点）
+    Right,
+}
+
+/// 锚点的当前状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub id: AnchorId,
+    /// 当前字节偏移
+    pub offset: usize,
+    pub gravity: Gravity,
+    /// 锚点原来所在的文本是否被删除（删除后锚点收缩到删除起点）
+    pub collapsed: bool,
+}
+
+/// 树中保存的锚点：gap 为与前一个锚点（第一个为文档开头）的距离
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    id: AnchorId,
+    gap: usize,
+    gravity: Gravity,
+    collapsed: bool,
+}
+
+impl Entry {
+    fn new(anchor: &Anchor, gap: usize) -> Self {
+        Self {
+            id: anchor.id,
+            gap,
+            gravity: anchor.gravity,
+            collapsed: anchor.collapsed,
+        }
+    }
+
+    fn anchor(&self, offset: usize) -> Anchor {
+        Anchor {
+            id: self.id,
+            offset,
+            gravity: self.gravity,
+            collapsed: self.collapsed,
+        }
+    }
+}
+
+impl Span for Entry {
+    fn bytes(&self) -> usize {
+        self.gap
+    }
+
+    fn line_feeds(&self) -> usize {
+        0
+    }
+}
+
+/// 锚点集合（克隆为O(1)，编辑只复制被修改的路径）
+#[derive(Debug, Clone)]
+pub(super) struct AnchorSet {
+    /// 按 (偏移, 方向) 排序
+    entries: SpanTree<Entry>,
+    /// 编号 → entries 中的下标（编辑不改变下标，只有重排的锚点需要更新）
+    index: Arc<HashMap<AnchorId, usize>>,
+}
+
+impl Default for AnchorSet {
+    fn default() -> Self {
+        Self {
+            entries: SpanTree::from_spans(Vec::new()),
+            index: Arc::default(),
+        }
+    }
+}
+
+impl AnchorSet {
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(super) fn get(&self, id: AnchorId) -> Option<Anchor> {
+        self.index.get(&id).and_then(|&i| self.anchor_at(i))
+    }
+
+    /// 添加锚点（编号已存在时替换）
+    pub(super) fn insert(&mut self, anchor: Anchor) {
+        self.remove(anchor.id);
+
+        let position = self.position_of(anchor.offset, anchor.gravity);
+        let mut replaced = vec![Entry::new(&anchor, anchor.offset - self.offset_before(position))];
+        if let Some(next) = self.anchor_at(position) {
+            replaced.push(Entry::new(&next, next.offset - anchor.offset));
+        }
+        self.entries.splice(position..position + replaced.len() - 1, replaced);
+        self.reindex_from(position);
+    }
+
+    /// 删除锚点，返回是否存在
+    pub(super) fn remove(&mut self, id: AnchorId) -> bool {
+        let Some(&position) = self.index.get(&id) else {
+            return false;
+        };
+        let Some((removed, _)) = self.entries.get(position) else {
+            return false;
+        };
+
+        // 距离并入下一个锚点
+        let mut replaced = Vec::new();
+        if let Some((next, _)) = self.entries.get(position + 1) {
+            replaced.push(Entry { gap: next.gap + removed.gap, ..next });
+        }
+        self.entries.splice(position..position + 1 + replaced.len(), replaced);
+        Arc::make_mut(&mut self.index).remove(&id);
+        self.reindex_from(position);
+        true
+    }
+
+    /// 偏移在范围内的锚点（按偏移排序，O(log n + k)）
+    pub(super) fn range(&self, range: Range<usize>) -> Vec<Anchor> {
+        let start = self.first_at(range.start);
+        self.anchors_from(start).take_while(|a| a.offset < range.end).collect()
+    }
+
+    /// 在 offset 处插入 len 字节：之后的锚点后移，恰在插入点的按方向决定
+    ///
+    /// 只需加大插入点之后第一个锚点的距离（O(log n + 同一位置的锚点数)）
+    pub(super) fn handle_insert(&mut self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+
+        let first = self.position_of(offset, Gravity::Left);
+        if let Some((entry, _)) = self.entries.get(first) {
+            self.entries.splice(first..first + 1, vec![Entry { gap: entry.gap + len, ..entry }]);
+        }
+    }
+
+    /// 删除字节范围：范围内的锚点收缩到起点，之后的锚点前移
+    ///
+    /// 只改写起点处到范围后第一个锚点的这一段（O(log n + k)）
+    pub(super) fn handle_delete(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let first = self.first_after(range.start);
+        let last = self.first_after(range.end);
+        if first == self.len() {
+            return;
+        }
+
+        // 起点处原有的锚点、范围内的锚点和范围后第一个锚点
+        let start = self.first_at(range.start);
+        let end = (last + 1).min(self.len());
+        let mut anchors: Vec<Anchor> = self.anchors_from(start).take(end - start).collect();
+        let (group, rest) = anchors.split_at_mut(last - start);
+        for anchor in &mut group[first - start..] {
+            anchor.collapsed |= anchor.offset < range.end;
+            anchor.offset = range.start;
+        }
+        for anchor in rest {
+            anchor.offset -= range.len();
+        }
+
+        // 收缩后与起点处原有的锚点重新按方向排序
+        group.sort_by_key(|a| a.gravity);
+        let mut previous = self.offset_before(start);
+        let entries: Vec<Entry> = anchors
+            .iter()
+            .map(|a| {
+                let gap = a.offset - previous;
+                previous = a.offset;
+                Entry::new(a, gap)
+            })
+            .collect();
+        self.entries.splice(start..end, entries);
+        self.reindex(start, anchors.iter().map(|a| a.id));
+    }
+
+    /// 批量替换（按位置排序、互不重叠）：从后往前逐处删除再插入
+    ///
+    /// 后面的替换不改变前面的偏移，结果与依次执行各处的删除和插入相同：
+    /// 被替换范围内（含两端）的锚点按方向停在插入内容之前或之后；
+    /// 每处只改写替换点附近的锚点
+    pub(super) fn handle_replacements(&mut self, replacements: &[Replacement]) {
+        if self.len() == 0 {
+            return;
+        }
+
+        for r in replacements.iter().rev() {
+            self.handle_delete(r.range.clone());
+            self.handle_insert(r.range.start, r.text.len());
+        }
+    }
+
+    /// 下标处的锚点
+    fn anchor_at(&self, index: usize) -> Option<Anchor> {
+        self.entries
+            .get(index)
+            .map(|(entry, before)| entry.anchor(before.bytes + entry.gap))
+    }
+
+    /// 从下标开始按顺序遍历锚点
+    fn anchors_from(&self, index: usize) -> impl Iterator<Item = Anchor> + '_ {
+        let mut offset = self.offset_before(index);
+        self.entries.iter_from(index).map(move |entry| {
+            offset += entry.gap;
+            entry.anchor(offset)
+        })
+    }
+
+    /// 下标之前最后一个锚点的偏移（没有时为0）
+    fn offset_before(&self, index: usize) -> usize {
+        match self.entries.get(index) {
+            Some((_, before)) => before.bytes,
+            None => self.entries.summary().bytes,
+        }
+    }
+
+    /// 第一个偏移大于 offset 的锚点下标（没有时为锚点数）
+    fn first_after(&self, offset: usize) -> usize {
+        match self.entries.find_byte(offset) {
+            Some((index, entry, before)) if before.bytes + entry.gap > offset => index,
+            _ => self.len(),
+        }
+    }
+
+    /// 第一个偏移不小于 offset 的锚点下标
+    fn first_at(&self, offset: usize) -> usize {
+        offset.checked_sub(1).map_or(0, |offset| self.first_after(offset))
+    }
+
+    /// 按 (偏移, 方向) 排序时新锚点的位置：排在同一偏移处同方向的锚点之后
+    fn position_of(&self, offset: usize, gravity: Gravity) -> usize {
+        let start = self.first_at(offset);
+        let end = self.first_after(offset);
+        match gravity {
+            Gravity::Left => {
+                start
+                    + self
+                        .entries
+                        .iter_from(start)
+                        .take(end - start)
+                        .take_while(|entry| entry.gravity == Gravity::Left)
+                        .count()
+            }
+            Gravity::Right => end,
+        }
+    }
+
+    /// 更新从 position 开始的下标映射
+    fn reindex_from(&mut self, position: usize) {
+        let ids: Vec<AnchorId> = self.entries.iter_from(position).map(|entry| entry.id).collect();
+        self.reindex(position, ids);
+    }
+
+    /// 更新下标映射（下标未变时不复制映射）
+    fn reindex(&mut self, start: usize, ids: impl IntoIterator<Item = AnchorId>) {
+        for (position, id) in (start..).zip(ids) {
+            if self.index.get(&id) != Some(&position) {
+                Arc::make_mut(&mut self.index).insert(id, position);
+            }
+        }
+    }
+}
+
+// ========== 锚点操作 ==========
+
+impl PieceTable {
+    /// 在字节偏移处添加锚点（偏移调整到字符边界）
+    pub fn add_anchor(&mut self, byte_offset: usize, gravity: Gravity) -> AnchorId {
+        let id = AnchorId(NEXT_ANCHOR_ID.fetch_add(1, Ordering::Relaxed));
+        self.set_anchor(id, byte_offset, gravity);
+        id
+    }
+
+    /// 移动锚点到新的偏移（不存在时按该编号添加），清除收缩标记
+    pub fn set_anchor(&mut self, id: AnchorId, byte_offset: usize, gravity: Gravity) {
+        let offset = self.ensure_char_boundary(byte_offset.min(self.total_bytes()));
+        self.anchor_set_mut().insert(Anchor {
+            id,
+            offset,
+            gravity,
+            collapsed: false,
+        });
+    }
+
+    /// 删除锚点，返回是否存在
+    pub fn remove_anchor(&mut self, id: AnchorId) -> bool {
+        self.anchor_set_mut().remove(id)
+    }
+
+    /// 锚点的当前状态（O(log n)）
+    pub fn anchor(&self, id: AnchorId) -> Option<Anchor> {
+        self.anchor_set().get(id)
+    }
+
+    /// 锚点的当前偏移
+    pub fn anchor_offset(&self, id: AnchorId) -> Option<usize> {
+        self.anchor(id).map(|anchor| anchor.offset)
+    }
+
+    /// 偏移在范围内的锚点（按偏移排序）
+    pub fn anchors_in(&self, range: Range<usize>) -> Vec<Anchor> {
+        self.anchor_set().range(range)
+    }
+
+    /// 锚点数量
+    pub fn anchor_count(&self) -> usize {
+        self.anchor_set().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gravity_at_insertion_point() {
+        let mut table = PieceTable::from_text("hello world");
+        let left = table.add_anchor(5, Gravity::Left);
+        let right = table.add_anchor(5, Gravity::Right);
+        let after = table.add_anchor(8, Gravity::Left);
+
+        let (mut table, _) = table.insert_char_safe(5, ", dear");
+        assert_eq!(table.anchor_offset(left), Some(5));
+        assert_eq!(table.anchor_offset(right), Some(11));
+        assert_eq!(table.anchor_offset(after), Some(14));
+
+        // 插入在锚点之后不影响它
+        table = table.insert_char_safe(table.total_bytes(), "!").0;
+        assert_eq!(table.anchor_offset(after), Some(14));
+
+        let offsets: Vec<usize> = table.anchors_in(0..table.total_bytes()).iter().map(|a| a.offset).collect();
+        assert_eq!(offsets, vec![5, 11, 14]);
+    }
+
+    #[test]
+    fn test_deleted_range_collapses_to_start() {
+        let mut table = PieceTable::from_text("0123456789");
+        let before = table.add_anchor(2, Gravity::Right);
+        let inside_right = table.add_anchor(4, Gravity::Right);
+        let inside_left = table.add_anchor(5, Gravity::Left);
+        let at_end = table.add_anchor(7, Gravity::Left);
+        let after = table.add_anchor(9, Gravity::Right);
+
+        let (table, _) = table.delete_char_safe(2..7);
+        assert_eq!(table.get_all_text(), "01789");
+
+        let anchor = |id| table.anchor(id).unwrap();
+        assert_eq!((anchor(before).offset, anchor(before).collapsed), (2, false));
+        assert_eq!((anchor(inside_right).offset, anchor(inside_right).collapsed), (2, true));
+        assert_eq!((anchor(inside_left).offset, anchor(inside_left).collapsed), (2, true));
+        assert_eq!((anchor(at_end).offset, anchor(at_end).collapsed), (2, false));
+        assert_eq!(anchor(after).offset, 4);
+
+        // 收缩后仍按方向排序：再在起点插入时左向的留下、右向的后移
+        let (table, _) = table.insert_char_safe(2, "ab");
+        let at: Vec<(usize, Gravity)> = table.anchors_in(0..10).iter().map(|a| (a.offset, a.gravity)).collect();
+        assert_eq!(
+            at,
+            vec![(2, Gravity::Left), (2, Gravity::Left), (4, Gravity::Right), (4, Gravity::Right), (6, Gravity::Right)]
+        );
+        assert_eq!(table.anchor_offset(inside_left), Some(2));
+        assert_eq!(table.anchor_offset(inside_right), Some(4));
+    }
+
+    #[test]
+    fn test_snapshots_keep_their_own_anchors() {
+        let mut table = PieceTable::from_text("abc");
+        let cursor = table.add_anchor(1, Gravity::Right);
+
+        let (mut edited, _) = table.insert_char_safe(0, "xyz");
+        assert_eq!(edited.anchor_offset(cursor), Some(4));
+        assert_eq!(table.anchor_offset(cursor), Some(1));
+
+        // 移动和删除只影响当前版本
+        edited.set_anchor(cursor, 0, Gravity::Left);
+        assert!(table.remove_anchor(cursor));
+        assert_eq!(edited.anchor_offset(cursor), Some(0));
+        assert_eq!(table.anchor(cursor), None);
+        assert_eq!(edited.anchor_count(), 1);
+    }
+
+    #[test]
+    fn test_edits_keep_anchor_index_shared() {
+        let mut table = PieceTable::from_text(&"0123456789".repeat(100));
+        let ids: Vec<AnchorId> = (0..1000).step_by(2).map(|offset| table.add_anchor(offset, Gravity::Right)).collect();
+
+        let (edited, _) = table.insert_char_safe(10, "abc");
+        let (edited, _) = edited.delete_char_safe(500..600);
+        let (edited, _) = edited.apply_edits(vec![(100..120, "x".to_string()), (800..800, "yz".to_string())]).unwrap();
+
+        // 没有锚点重排，编辑不复制下标映射
+        assert!(Arc::ptr_eq(&table.anchor_set().index, &edited.anchor_set().index));
+        assert_eq!(edited.anchor_offset(ids[3]), Some(6));
+        assert_eq!(edited.anchor_offset(ids[6]), Some(15));
+        assert_eq!(table.anchor_offset(ids[6]), Some(12));
+        assert_eq!(edited.anchor_offset(ids[54]), Some(101));
+        assert_eq!(edited.anchor_offset(ids[60]), Some(104));
+        assert_eq!(edited.anchors_in(0..edited.total_bytes() + 1).len(), ids.len());
+    }
+
+    #[test]
+    fn test_bulk_edits_move_anchors_like_single_edits() {
+        let text = "0123456789".repeat(20);
+        let mut table = PieceTable::from_text(&text);
+        let gravities = [Gravity::Left, Gravity::Right];
+        let ids: Vec<AnchorId> = (0..=text.len()).map(|offset| table.add_anchor(offset, gravities[offset % 2])).collect();
+        let edits: Vec<(Range<usize>, String)> = (0..text.len() - 10)
+            .step_by(10)
+            .flat_map(|start| [(start..start, "<".to_string()), (start..start + start % 7, "ab".to_string())])
+            .collect();
+
+        // 从后往前逐个删除再插入，前面的偏移不受影响
+        let mut sequential = table.clone();
+        for (range, text) in edits.iter().rev() {
+            sequential = sequential.delete_char_safe(range.clone()).0;
+            sequential = sequential.insert_char_safe(range.start, text).0;
+        }
+
+        let (bulk, _) = table.apply_edits(edits).unwrap();
+        assert_eq!(bulk.get_all_text(), sequential.get_all_text());
+        for id in ids {
+            assert_eq!(bulk.anchor(id), sequential.anchor(id));
+        }
+        assert_eq!(bulk.anchors_in(0..bulk.total_bytes() + 1), sequential.anchors_in(0..sequential.total_bytes() + 1));
+    }
+
+    #[test]
+    fn test_many_anchors_follow_random_edits() {
+        let text = "anchor ".repeat(1000);
+        let mut table = PieceTable::from_text(&text);
+        let mut model: Vec<(AnchorId, usize)> = (0..text.len())
+            .step_by(3)
+            .map(|offset| (table.add_anchor(offset, Gravity::Right), offset))
+            .collect();
+
+        let mut seed: u64 = 7;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as usize) % bound.max(1)
+        };
+
+        for _ in 0..500 {
+            if next(2) == 0 {
+                let offset = next(table.total_bytes() + 1);
+                table = table.insert_char_safe(offset, "++").0;
+                for (_, position) in &mut model {
+                    if *position >= offset {
+                        *position += 2;
+                    }
+                }
+            } else {
+                let start = next(table.total_bytes());
+                let end = (start + next(20) + 1).min(table.total_bytes());
+                table = table.delete_char_safe(start..end).0;
+                for (_, position) in &mut model {
+                    if *position > start {
+                        *position = start.max(position.saturating_sub(end - start));
+                    }
+                }
+            }
+        }
+
+        for (id, position) in model {
+            assert_eq!(table.anchor_offset(id), Some(position));
+        }
+    }
+}
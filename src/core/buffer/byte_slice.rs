@@ -0,0 +1,98 @@
+// 底层字节切片
+//
+// 职责：遍历 PieceTable 时产生的零拷贝切片。
+//       内存中的缓冲区直接借用；滑动窗口映射的切片持有窗口引用，
+//       窗口被淘汰后切片仍然有效，释放切片后才解除映射；
+//       跨两个切片的字符（窗口边缘、Piece中间切开）拼接为少量自有字节
+
+use std::ops::{Deref, Range};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::core::buffer::mmap::MappedSlice;
+
+/// 连续的底层字节（不复制内容）
+#[derive(Clone)]
+pub enum ByteSlice<'a> {
+    /// 借用的内存（原始缓冲区、追加缓冲区、整体映射）
+    Borrowed(&'a [u8]),
+
+    /// 滑动窗口映射中的一段
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(MappedSlice),
+
+    /// 从相邻切片拼接的字节（不超过一个字符）
+    Joined(Box<[u8]>),
+}
+
+impl<'a> ByteSlice<'a> {
+    /// 子切片（零拷贝）
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        match self {
+            ByteSlice::Borrowed(bytes) => ByteSlice::Borrowed(&bytes[range]),
+            #[cfg(not(target_arch = "wasm32"))]
+            ByteSlice::Mapped(mapped) => ByteSlice::Mapped(mapped.slice(range)),
+            ByteSlice::Joined(bytes) => ByteSlice::Joined(bytes[range].into()),
+        }
+    }
+
+    /// 以调用方生命周期借用的切片（窗口映射和拼接的字节返回None）
+    pub fn as_borrowed(&self) -> Option<&'a [u8]> {
+        match self {
+            ByteSlice::Borrowed(bytes) => Some(bytes),
+            #[cfg(not(target_arch = "wasm32"))]
+            ByteSlice::Mapped(_) => None,
+            ByteSlice::Joined(_) => None,
+        }
+    }
+}
+
+impl Deref for ByteSlice<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            ByteSlice::Borrowed(bytes) => bytes,
+            #[cfg(not(target_arch = "wasm32"))]
+            ByteSlice::Mapped(mapped) => mapped,
+            ByteSlice::Joined(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for ByteSlice<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<'a> From<&'a [u8]> for ByteSlice<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        ByteSlice::Borrowed(bytes)
+    }
+}
+
+impl std::fmt::Debug for ByteSlice<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ByteSlice").field(&&**self).finish()
+    }
+}
+
+impl PartialEq for ByteSlice<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for ByteSlice<'_> {}
+
+impl PartialEq<[u8]> for ByteSlice<'_> {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<&[u8]> for ByteSlice<'_> {
+    fn eq(&self, other: &&[u8]) -> bool {
+        **self == **other
+    }
+}
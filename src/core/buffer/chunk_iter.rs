@@ -36,7 +36,11 @@ impl<'a> Iterator for ChunkIter<'a> {
             return None;
         }
 
-        let end = (self.current_pos + self.chunk_size).min(self.total_bytes);
+        // 块边界向后对齐到字符边界，避免截断多字节字符
+        let mut end = (self.current_pos + self.chunk_size.max(1)).min(self.total_bytes);
+        while end < self.total_bytes && !self.piece_table.is_char_boundary(end) {
+            end += 1;
+        }
         let chunk = self.piece_table.get_text_range(self.current_pos..end);
         self.current_pos = end;
 
@@ -45,7 +49,7 @@ impl<'a> Iterator for ChunkIter<'a> {
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         let remaining = self.total_bytes.saturating_sub(self.current_pos);
-        let chunks = (remaining + self.chunk_size - 1) / self.chunk_size;
+        let chunks = remaining.div_ceil(self.chunk_size);
         (chunks, Some(chunks))
     }
 }
@@ -3,13 +3,22 @@
 // 职责：支持延迟加载删除的文本内容，优化大文件性能
 
 use std::ops::Range;
-use crate::core::buffer::PieceType;
+use crate::core::buffer::{PieceType, OriginalBuffer, AddBuffer};
 
 /// 被删除的Piece信息
 #[derive(Debug, Clone)]
 pub struct DeletionPiece {
     pub piece_type: PieceType,
     pub range: Range<usize>,
+    /// 这段内容中的换行数（恢复时无需重新扫描）
+    pub line_feeds: usize,
+}
+
+/// 删除时Piece所引用的缓冲区（持有引用，保证恢复时仍可读取）
+#[derive(Debug, Clone)]
+pub(super) struct DeletionSource {
+    pub(super) original: OriginalBuffer,
+    pub(super) additions: AddBuffer,
 }
 
 /// 删除操作的信息（支持延迟加载）
@@ -21,6 +30,8 @@ pub struct DeletionInfo {
     pub pieces: Vec<DeletionPiece>,
     /// 缓存的删除文本（延迟加载）
     cached_text: Option<String>,
+    /// Piece引用的缓冲区（由 delete_lazy 记录）
+    source: Option<DeletionSource>,
 }
 
 impl DeletionInfo {
@@ -29,9 +40,26 @@ impl DeletionInfo {
             byte_range,
             pieces,
             cached_text: None,
+            source: None,
         }
     }
 
+    /// 记录Piece引用的缓冲区
+    pub(super) fn with_source(mut self, original: OriginalBuffer, additions: AddBuffer) -> Self {
+        self.source = Some(DeletionSource { original, additions });
+        self
+    }
+
+    /// Piece引用的缓冲区（手动构造时为None，视为与恢复目标相同）
+    pub(super) fn source(&self) -> Option<&DeletionSource> {
+        self.source.as_ref()
+    }
+
+    /// 被删除内容中的换行数
+    pub fn line_feeds(&self) -> usize {
+        self.pieces.iter().map(|piece| piece.line_feeds).sum()
+    }
+
     /// 获取删除的文本（延迟加载）
     pub fn get_text<F>(&mut self, loader: F) -> String
     where
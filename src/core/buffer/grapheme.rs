@@ -0,0 +1,206 @@
+// 字素簇编辑
+//
+// 职责：按扩展字素簇（UAX #29，用户感知的字符）定位和删除，
+//       避免退格后留下半个emoji、国旗或组合字符
+//
+// 调整规则与字符边界一致：偏移落在簇内部时取簇起点；
+// 无效UTF-8的每个字节单独成为一个簇
+
+use std::ops::Range;
+
+use crate::core::buffer::{
+    piece_table::PieceTable,
+    text_cursor::CursorUnit,
+};
+
+// ========== 边界查询 ==========
+
+impl PieceTable {
+    /// 字节偏移是否位于字素簇边界（开头和末尾都是边界）
+    pub fn is_grapheme_boundary(&self, byte_offset: usize) -> bool {
+        if byte_offset == 0 || byte_offset == self.total_bytes() {
+            return true;
+        }
+        if !self.is_char_boundary(byte_offset) {
+            return false;
+        }
+
+        // 从前一个簇起点再前进一个簇，恰好回到偏移处说明是边界
+        let mut cursor = self.cursor_at(byte_offset);
+        cursor.move_backward(CursorUnit::Grapheme);
+        cursor.move_forward(CursorUnit::Grapheme);
+        cursor.offset() == byte_offset
+    }
+
+    /// 偏移之前最近的字素簇边界（已在开头时返回0）
+    pub fn prev_grapheme_boundary(&self, byte_offset: usize) -> usize {
+        let offset = byte_offset.min(self.total_bytes());
+        if !self.is_char_boundary(offset) {
+            return self.ensure_grapheme_boundary(offset);
+        }
+
+        let mut cursor = self.cursor_at(offset);
+        cursor.move_backward(CursorUnit::Grapheme);
+        cursor.offset()
+    }
+
+    /// 偏移之后最近的字素簇边界（已在末尾时返回文档长度）
+    pub fn next_grapheme_boundary(&self, byte_offset: usize) -> usize {
+        let offset = self.char_boundary_before(byte_offset.min(self.total_bytes()));
+
+        let mut cursor = self.cursor_at(offset);
+        cursor.move_forward(CursorUnit::Grapheme);
+        cursor.offset()
+    }
+
+    /// 将字节偏移向前调整到所在字素簇的起点
+    pub fn ensure_grapheme_boundary(&self, byte_offset: usize) -> usize {
+        let offset = self.char_boundary_before(byte_offset.min(self.total_bytes()));
+        if self.is_grapheme_boundary(offset) {
+            return offset;
+        }
+
+        let mut cursor = self.cursor_at(offset);
+        cursor.move_backward(CursorUnit::Grapheme);
+        cursor.offset()
+    }
+
+    /// 把范围扩展到完整的字素簇（起点向前、终点向后）
+    pub fn expand_to_grapheme_boundaries(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.ensure_grapheme_boundary(range.start);
+        let end = range.end.min(self.total_bytes()).max(start);
+        let end = if self.is_grapheme_boundary(end) {
+            end
+        } else {
+            self.next_grapheme_boundary(end)
+        };
+
+        start..end
+    }
+
+    /// 偏移所在多字节字符的起点（已在边界时不变）
+    fn char_boundary_before(&self, byte_offset: usize) -> usize {
+        let mut offset = byte_offset;
+        while !self.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+// ========== 字素簇删除 ==========
+
+impl PieceTable {
+    /// 删除偏移之前的一个字素簇（退格），返回新表和被删除的文本
+    ///
+    /// 偏移落在簇内部时删除整个簇；删除后光标应位于被删除范围的起点
+    pub fn delete_grapheme_before(&self, byte_offset: usize) -> (Self, String) {
+        let offset = byte_offset.min(self.total_bytes());
+        let end = if self.is_grapheme_boundary(offset) {
+            offset
+        } else {
+            self.next_grapheme_boundary(offset)
+        };
+        let start = self.prev_grapheme_boundary(end);
+
+        self.delete_char_safe(start..end)
+    }
+
+    /// 删除偏移之后的一个字素簇（Delete键），返回新表和被删除的文本
+    pub fn delete_grapheme_after(&self, byte_offset: usize) -> (Self, String) {
+        let start = self.ensure_grapheme_boundary(byte_offset);
+        let end = self.next_grapheme_boundary(start);
+
+        self.delete_char_safe(start..end)
+    }
+
+    /// 字素簇安全的删除（范围扩展到完整的簇）
+    pub fn delete_grapheme_safe(&self, range: Range<usize>) -> (Self, String) {
+        self.delete_char_safe(self.expand_to_grapheme_boundaries(range))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boundaries(table: &PieceTable) -> Vec<usize> {
+        (0..=table.total_bytes()).filter(|&o| table.is_grapheme_boundary(o)).collect()
+    }
+
+    /// 每个字符单独成为一个Piece，簇跨越Piece边缘
+    fn fragmented(text: &str) -> PieceTable {
+        let mut table = PieceTable::new();
+        table.suspend_auto_merge();
+        for c in text.chars() {
+            let end = table.total_bytes();
+            table = table.insert_char_safe(end, c.encode_utf8(&mut [0; 4])).0;
+        }
+        assert_eq!(table.piece_count(), text.chars().count());
+        table
+    }
+
+    #[test]
+    fn test_insert_inside_zwj_sequence_joins_across_pieces() {
+        let table = PieceTable::from_text("a👨👧b");
+        assert_eq!(boundaries(&table), [0, 1, 5, 9, 10]);
+
+        // 在两个emoji之间插入ZWJ，簇跨越三个Piece
+        let (table, _) = table.insert_char_safe(5, "\u{200D}");
+        assert_eq!(table.piece_count(), 3);
+        assert_eq!(boundaries(&table), [0, 1, 12, 13]);
+        assert_eq!(table.prev_grapheme_boundary(12), 1);
+        for inside in 2..12 {
+            assert_eq!(table.ensure_grapheme_boundary(inside), 1);
+            assert_eq!(table.next_grapheme_boundary(inside), 12);
+        }
+
+        // 单独插入的组合重音并入前一个字符
+        let (accented, _) = table.insert_char_safe(1, "\u{301}");
+        assert_eq!(boundaries(&accented), [0, 3, 14, 15]);
+
+        // 光标落在ZWJ序列内部
+        let (table, deleted) = table.delete_grapheme_before(9);
+        assert_eq!(deleted, "👨\u{200D}👧");
+        assert_eq!(table.get_all_text(), "ab");
+    }
+
+    #[test]
+    fn test_backspace_removes_whole_cluster() {
+        let table = fragmented("a🇨🇳🇯🇵e\u{301}");
+
+        let (table, deleted) = table.delete_grapheme_before(20);
+        assert_eq!(deleted, "e\u{301}");
+        let (table, deleted) = table.delete_grapheme_before(17);
+        assert_eq!(deleted, "🇯🇵");
+        // 光标落在国旗内部
+        let (table, deleted) = table.delete_grapheme_before(5);
+        assert_eq!(deleted, "🇨🇳");
+        assert_eq!(table.get_all_text(), "a");
+    }
+
+    #[test]
+    fn test_delete_after_and_range_snapping() {
+        let table = PieceTable::from_text("x🇨🇳e\u{301}\u{1100}\u{1161}\u{11A8}");
+
+        let (after, deleted) = table.delete_grapheme_after(1);
+        assert_eq!(deleted, "🇨🇳");
+        assert_eq!(after.total_bytes(), 13);
+
+        assert_eq!(table.expand_to_grapheme_boundaries(5..10), 1..12);
+        let (snapped, deleted) = table.delete_grapheme_safe(10..14);
+        assert_eq!(deleted, "e\u{301}\u{1100}\u{1161}\u{11A8}");
+        assert_eq!(snapped.total_bytes(), 9);
+    }
+
+    #[test]
+    fn test_invalid_bytes_are_single_clusters() {
+        let table = PieceTable::from_bytes(b"e\xCC\x81\xFF\x80x");
+
+        assert_eq!(table.next_grapheme_boundary(0), 3);
+        assert_eq!(table.next_grapheme_boundary(3), 4);
+        assert_eq!(table.prev_grapheme_boundary(5), 4);
+        let (table, _) = table.delete_grapheme_before(5);
+        assert_eq!(table.get_bytes_range(0..table.total_bytes()), b"e\xCC\x81\xFFx");
+    }
+}
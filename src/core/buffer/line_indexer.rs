@@ -0,0 +1,253 @@
+// 后台行索引
+//
+// 职责：在后台线程扫描整个文档（包括内存映射的大文件）建立行索引，
+//       报告进度、支持取消，完成后整体发布到对应版本的 PieceTable
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+
+use crate::core::buffer::{
+    piece_table::PieceTable,
+    piece_tree::PieceTree,
+    lines::{Lines, LineScanner},
+    mode::BufferMode,
+    sparse_lines::{SparseLineConfig, SparseLineScanner},
+};
+
+/// 每次扫描的字节数（扫描之间检查取消并更新进度）
+const SCAN_STEP: usize = 1024 * 1024; // 1MB
+
+/// 扫描进度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineIndexProgress {
+    pub scanned_bytes: usize,
+    pub total_bytes: usize,
+}
+
+impl LineIndexProgress {
+    /// 完成比例（0.0 ~ 1.0）
+    pub fn fraction(&self) -> f64 {
+        if self.total_bytes == 0 {
+            1.0
+        } else {
+            self.scanned_bytes as f64 / self.total_bytes as f64
+        }
+    }
+}
+
+/// 扫描完成的行索引，只能安装到被扫描的文档版本上
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    lines: Lines,
+    /// 被扫描版本的Piece树（用于版本校验）
+    pieces: PieceTree,
+}
+
+impl LineIndex {
+    pub fn lines(&self) -> &Lines {
+        &self.lines
+    }
+
+    /// 是否由该文档版本扫描得到
+    pub fn matches(&self, table: &PieceTable) -> bool {
+        self.pieces.ptr_eq(table.piece_tree())
+    }
+
+    pub fn into_lines(self) -> Lines {
+        self.lines
+    }
+}
+
+/// 扫描线程与任务句柄共享的状态
+#[derive(Debug, Default)]
+pub(super) struct ScanState {
+    scanned: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+/// 后台行索引任务（丢弃句柄会取消扫描）
+#[derive(Debug)]
+pub struct LineIndexTask {
+    state: Arc<ScanState>,
+    total_bytes: usize,
+    handle: Option<JoinHandle<Option<LineIndex>>>,
+}
+
+impl LineIndexTask {
+    /// 在后台线程扫描文档快照
+    pub fn spawn(table: &PieceTable) -> Self {
+        let state = Arc::new(ScanState::default());
+        let snapshot = table.clone();
+        let thread_state = state.clone();
+
+        let handle = std::thread::Builder::new()
+            .name("line-indexer".into())
+            .spawn(move || {
+                let lines = scan(&snapshot, Some(&thread_state))?;
+                Some(LineIndex {
+                    lines,
+                    pieces: snapshot.piece_tree().clone(),
+                })
+            })
+            .expect("无法创建行索引线程");
+
+        Self {
+            state,
+            total_bytes: table.total_bytes(),
+            handle: Some(handle),
+        }
+    }
+
+    /// 当前进度
+    pub fn progress(&self) -> LineIndexProgress {
+        LineIndexProgress {
+            scanned_bytes: self.state.scanned.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes,
+        }
+    }
+
+    /// 请求取消（扫描线程在下一个扫描步骤退出）
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// 扫描线程是否已结束
+    pub fn is_finished(&self) -> bool {
+        self.handle.as_ref().map_or(true, |h| h.is_finished())
+    }
+
+    /// 非阻塞获取结果：未完成时返回None，结果只能取出一次
+    pub fn try_take(&mut self) -> Option<LineIndex> {
+        if !self.is_finished() {
+            return None;
+        }
+        self.handle.take()?.join().ok().flatten()
+    }
+
+    /// 阻塞等待扫描结束，被取消时返回None
+    pub fn wait(mut self) -> Option<LineIndex> {
+        self.handle.take()?.join().ok().flatten()
+    }
+}
+
+impl Drop for LineIndexTask {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.cancel();
+        }
+    }
+}
+
+/// 扫描器：超大文件使用稀疏检查点，其余逐行记录
+#[derive(Debug)]
+pub(super) enum Scanner {
+    Dense(LineScanner),
+    Sparse(SparseLineScanner),
+}
+
+impl Scanner {
+    pub(super) fn for_mode(mode: &BufferMode) -> Self {
+        match mode {
+            BufferMode::Restricted { .. } => {
+                Scanner::Sparse(SparseLineScanner::new(SparseLineConfig::default()))
+            }
+            _ => Scanner::Dense(LineScanner::new()),
+        }
+    }
+
+    pub(super) fn feed(&mut self, bytes: &[u8]) {
+        match self {
+            Scanner::Dense(scanner) => scanner.feed(bytes),
+            Scanner::Sparse(scanner) => scanner.feed(bytes),
+        }
+    }
+
+    pub(super) fn scanned_bytes(&self) -> usize {
+        match self {
+            Scanner::Dense(scanner) => scanner.scanned_bytes(),
+            Scanner::Sparse(scanner) => scanner.scanned_bytes(),
+        }
+    }
+
+    pub(super) fn finish(self, table: &PieceTable) -> Lines {
+        match self {
+            Scanner::Dense(scanner) => scanner.finish(),
+            Scanner::Sparse(scanner) => {
+                Lines::from_sparse(scanner.finish(Arc::new(table.without_lines())))
+            }
+        }
+    }
+}
+
+/// 扫描整个文档建立行索引，被取消时返回None
+pub(super) fn scan(table: &PieceTable, state: Option<&ScanState>) -> Option<Lines> {
+    let mut scanner = Scanner::for_mode(table.mode());
+
+    for slice in table.byte_slices(0..table.total_bytes()) {
+        // 内存映射的原始内容可能是一个很大的切片，分步扫描
+        for step in slice.chunks(SCAN_STEP) {
+            if let Some(state) = state {
+                if state.cancelled.load(Ordering::Relaxed) {
+                    return None;
+                }
+                scanner.feed(step);
+                state.scanned.store(scanner.scanned_bytes(), Ordering::Relaxed);
+            } else {
+                scanner.feed(step);
+            }
+        }
+    }
+
+    Some(scanner.finish(table))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_background_index_installs_on_same_version() {
+        let text = "line\n".repeat(10_000);
+        let mut table = PieceTable::from_text(&text);
+
+        let task = table.spawn_line_indexing();
+        let index = task.wait().unwrap();
+        assert!(index.matches(&table));
+        assert!(table.install_line_index(index));
+
+        let lines = table.lines().unwrap();
+        assert_eq!(lines.total_lines(), 10_000);
+        assert_eq!(table.get_line(9_999).as_deref(), Some("line"));
+    }
+
+    #[test]
+    fn test_stale_index_is_rejected() {
+        let table = PieceTable::from_text("a\nb\n");
+        let index = table.spawn_line_indexing().wait().unwrap();
+
+        let (mut edited, _) = table.insert_char_safe(0, "x\n");
+        assert!(!edited.install_line_index(index));
+        assert!(edited.lines().is_none());
+    }
+
+    #[test]
+    fn test_cancelled_scan_returns_nothing() {
+        let text = "x".repeat(8 * SCAN_STEP);
+        let table = PieceTable::from_text(&text);
+
+        let task = table.spawn_line_indexing();
+        task.cancel();
+        let progress = task.progress();
+        assert!(progress.scanned_bytes <= progress.total_bytes);
+
+        // 取消可能晚于扫描完成，两种结果都合法，但取消后不会再扫描新的步骤
+        if let Some(index) = task.wait() {
+            assert_eq!(index.lines().total_bytes(), text.len());
+        }
+    }
+}
@@ -1,8 +1,29 @@
 // 行索引管理
 //
-// 职责：维护文本行号与字节偏移的映射关系，支持快速行查找
+// 职责：维护文本行号与字节偏移的映射关系，支持快速行查找，
+//       插入/删除时只更新受影响的行，不重新扫描全文
 
 use std::ops::Range;
+use std::sync::Arc;
+
+use crate::core::buffer::{
+    piece_table::PieceTable,
+    sparse_lines::SparseLines,
+    span_tree::{Span, SpanTree},
+};
+
+/// 批量替换不超过此数量时逐个增量更新，否则一次线性重建
+const INCREMENTAL_REPLACEMENTS: usize = 32;
+
+/// 批量编辑中的一处替换（范围为编辑前文档中的偏移）
+#[derive(Debug, Clone)]
+pub(super) struct Replacement<'a> {
+    pub(super) range: Range<usize>,
+    /// 被删除内容中的换行数
+    pub(super) removed_line_feeds: usize,
+    /// 插入的内容
+    pub(super) text: &'a [u8],
+}
 
 /// 行信息
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -15,102 +36,255 @@ pub struct LineInfo {
     pub ends_with_newline: bool,
 }
 
+/// 行内容长度（不含换行符）
+///
+/// 每行在文档中占用 `长度 + 1` 个字节（含换行符，最后一行的换行符是虚拟的）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineLen(usize);
+
+impl Span for LineLen {
+    fn bytes(&self) -> usize {
+        self.0 + 1
+    }
+
+    fn line_feeds(&self) -> usize {
+        1
+    }
+}
+
 /// 行索引管理器
-#[derive(Debug, Clone, Default)]
+///
+/// 行长度保存在跨度树中，克隆为O(1)；编辑只复制到被修改行的路径，
+/// 行的起始位置由前缀汇总得到，之后的行不需要平移；
+/// 超大文件使用稀疏检查点索引，查询接口相同
+#[derive(Debug, Clone)]
 pub struct Lines {
-    /// 所有行的信息（按行号排序）
-    lines: Vec<LineInfo>,
+    /// 各行内容长度（行数 = 换行数 + 1，含末尾空行）
+    lens: SpanTree<LineLen>,
     /// 总字节数
     total_bytes: usize,
     /// 是否脏（需要重建）
     dirty: bool,
+    /// 稀疏索引（存在时代替逐行索引）
+    sparse: Option<SparseLines>,
 }
 
 impl Lines {
     pub fn new() -> Self {
         Self {
-            lines: Vec::new(),
+            lens: SpanTree::from_spans(Vec::new()),
             total_bytes: 0,
             dirty: true,
+            sparse: None,
         }
     }
 
+    /// 使用稀疏检查点索引
+    pub fn from_sparse(sparse: SparseLines) -> Self {
+        Self {
+            total_bytes: sparse.total_bytes(),
+            dirty: false,
+            sparse: Some(sparse),
+            ..Self::new()
+        }
+    }
+
+    /// 是否为稀疏索引
+    pub fn is_sparse(&self) -> bool {
+        self.sparse.is_some()
+    }
+
+    /// 稀疏索引（如果是）
+    pub fn sparse(&self) -> Option<&SparseLines> {
+        self.sparse.as_ref()
+    }
+
     /// 从文本构建行索引
     pub fn build_from_text(&mut self, text: &str) {
-        self.lines.clear();
-
-        let mut line_start = 0;
-        let mut line_number = 0;
-
-        for (i, c) in text.char_indices() {
-            if c == '\n' {
-                self.lines.push(LineInfo {
-                    byte_range: line_start..i,
-                    line_number,
-                    ends_with_newline: true,
-                });
-                line_start = i + 1;
-                line_number += 1;
-            }
+        let lens: Vec<usize> = text.split('\n').map(str::len).collect();
+        self.build_from_lens(lens, text.len());
+    }
+
+    /// 从各行内容长度构建行索引（行数 = 换行数 + 1）
+    fn build_from_lens(&mut self, lens: Vec<usize>, total_bytes: usize) {
+        self.lens = SpanTree::from_spans(lens.into_iter().map(LineLen).collect());
+        self.total_bytes = total_bytes;
+        self.dirty = false;
+        self.sparse = None;
+    }
+
+    /// 增量更新：处理插入
+    pub fn handle_insert(&mut self, offset: usize, text: &str) {
+        self.handle_insert_bytes(offset, text.as_bytes());
+    }
+
+    /// 增量更新：处理插入的原始字节（可以不是合法UTF-8）
+    pub fn handle_insert_bytes(&mut self, offset: usize, bytes: &[u8]) {
+        if let Some(sparse) = &mut self.sparse {
+            let line_feeds = bytes.iter().filter(|&&b| b == b'\n').count();
+            sparse.handle_insert_span(offset, bytes.len(), line_feeds);
+            self.total_bytes = sparse.total_bytes();
+            return;
+        }
+        if self.dirty || offset > self.total_bytes {
+            self.dirty = true;
+            self.total_bytes += bytes.len();
+            return;
+        }
+        if bytes.is_empty() {
+            return;
         }
 
-        // 最后一行（如果没有以换行符结束）
-        if line_start < text.len() {
-            self.lines.push(LineInfo {
-                byte_range: line_start..text.len(),
-                line_number,
-                ends_with_newline: false,
-            });
+        let (line, line_start, len) = self.locate(offset);
+        let column = offset - line_start;
+
+        // 插入点把所在行分成两半，新内容的各段依次接在中间
+        let mut new_lens: Vec<usize> = bytes.split(|&b| b == b'\n').map(<[u8]>::len).collect();
+        if let Some(first) = new_lens.first_mut() {
+            *first += column;
+        }
+        if let Some(last) = new_lens.last_mut() {
+            *last += len - column;
         }
 
-        self.total_bytes = text.len();
-        self.dirty = false;
+        self.splice(line..line + 1, new_lens);
+        self.total_bytes += bytes.len();
     }
 
-    /// 增量更新：处理插入
-    pub fn handle_insert(&mut self, _offset: usize, text: &str) {
-        // 简化实现：有插入就标记为脏
-        // 未来可以优化为真正的增量更新
-        self.dirty = true;
-        self.total_bytes += text.len();
+    /// 增量更新：只知道插入长度和换行数时（如恢复延迟删除的内容）
+    ///
+    /// 稀疏索引和不含换行的插入可以直接更新，逐行索引插入多行时标记为脏
+    pub fn handle_insert_span(&mut self, offset: usize, len: usize, line_feeds: usize) {
+        if let Some(sparse) = &mut self.sparse {
+            sparse.handle_insert_span(offset, len, line_feeds);
+            self.total_bytes = sparse.total_bytes();
+            return;
+        }
+        if self.dirty || offset > self.total_bytes || line_feeds > 0 {
+            self.dirty = true;
+            self.total_bytes += len;
+            return;
+        }
+        if len == 0 {
+            return;
+        }
+
+        let (line, _, line_len) = self.locate(offset);
+        self.splice(line..line + 1, vec![line_len + len]);
+        self.total_bytes += len;
     }
 
     /// 增量更新：处理删除
     pub fn handle_delete(&mut self, range: Range<usize>) {
-        // 简化实现：有删除就标记为脏
-        self.dirty = true;
+        if let Some(sparse) = &mut self.sparse {
+            sparse.handle_delete(range);
+            self.total_bytes = sparse.total_bytes();
+            return;
+        }
+        if self.dirty || range.end > self.total_bytes {
+            self.dirty = true;
+            self.total_bytes = self.total_bytes.saturating_sub(range.len());
+            return;
+        }
+        if range.is_empty() {
+            return;
+        }
+
+        // 删除范围首尾所在的行合并为一行
+        let (first, first_start, _) = self.locate(range.start);
+        let (last, last_start, last_len) = self.locate(range.end);
+        let merged = (range.start - first_start) + (last_start + last_len - range.end);
+
+        self.splice(first..last + 1, vec![merged]);
         self.total_bytes -= range.len();
     }
 
-    /// 查找包含指定字节偏移的行
+    /// 增量更新：批量替换（按位置排序、互不重叠）
+    ///
+    /// 数量较少时从后往前逐个更新（前面的偏移不受影响），
+    /// 较多时按新内容一次线性重建行长度，避免逐个更新的平方复杂度
+    pub(super) fn handle_replacements(&mut self, replacements: &[Replacement]) {
+        if let Some(sparse) = &mut self.sparse {
+            sparse.handle_replacements(replacements);
+            self.total_bytes = sparse.total_bytes();
+            return;
+        }
+
+        let end = replacements.last().map_or(0, |r| r.range.end);
+        if self.dirty || end > self.total_bytes {
+            let removed: usize = replacements.iter().map(|r| r.range.len()).sum();
+            let inserted: usize = replacements.iter().map(|r| r.text.len()).sum();
+            self.dirty = true;
+            self.total_bytes = (self.total_bytes + inserted).saturating_sub(removed);
+            return;
+        }
+
+        if replacements.len() <= INCREMENTAL_REPLACEMENTS {
+            for replacement in replacements.iter().rev() {
+                self.handle_delete(replacement.range.clone());
+                self.handle_insert_bytes(replacement.range.start, replacement.text);
+            }
+            return;
+        }
+
+        self.rebuild_with_replacements(replacements);
+    }
+
+    /// 查找包含指定字节偏移的行（行尾换行符的位置属于该行）
     pub fn find_line_by_offset(&self, offset: usize) -> Option<usize> {
-        if self.dirty {
+        if let Some(sparse) = &self.sparse {
+            return sparse.find_line_by_offset(offset);
+        }
+        if self.dirty || offset > self.total_bytes {
             return None;
         }
 
-        self.lines
-            .binary_search_by(|line| {
-                if offset < line.byte_range.start {
-                    std::cmp::Ordering::Greater
-                } else if offset >= line.byte_range.end {
-                    std::cmp::Ordering::Less
-                } else {
-                    std::cmp::Ordering::Equal
-                }
-            })
-            .ok()
+        let (line, _, _) = self.locate(offset);
+        (line < self.total_lines()).then_some(line)
     }
 
-    /// 获取指定行的字节范围
+    /// 获取指定行的字节范围（不含换行符）
     pub fn get_line_range(&self, line_number: usize) -> Option<Range<usize>> {
-        self.lines
-            .get(line_number)
-            .map(|line| line.byte_range.clone())
+        if let Some(sparse) = &self.sparse {
+            return sparse.get_line_range(line_number);
+        }
+        if line_number >= self.total_lines() {
+            return None;
+        }
+
+        let (LineLen(len), before) = self.lens.get(line_number)?;
+        Some(before.bytes..before.bytes + len)
+    }
+
+    /// 获取指定行的信息
+    pub fn line_info(&self, line_number: usize) -> Option<LineInfo> {
+        self.get_line_range(line_number).map(|byte_range| LineInfo {
+            byte_range,
+            line_number,
+            ends_with_newline: match &self.sparse {
+                Some(sparse) => sparse.ends_with_newline(line_number),
+                None => line_number + 1 < self.lens.len(),
+            },
+        })
     }
 
-    /// 总行数
+    /// 总行数（以换行符结尾的文本不计末尾空行）
     pub fn total_lines(&self) -> usize {
-        self.lines.len()
+        if let Some(sparse) = &self.sparse {
+            return sparse.total_lines();
+        }
+
+        let trailing_empty = self.lens.last().is_some_and(|LineLen(len)| len == 0);
+        if trailing_empty {
+            self.lens.len() - 1
+        } else {
+            self.lens.len()
+        }
+    }
+
+    /// 已索引的总字节数
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
     }
 
     /// 是否脏（需要重建）
@@ -122,4 +296,261 @@ impl Lines {
     pub fn mark_dirty(&mut self) {
         self.dirty = true;
     }
+
+    /// 编辑后更新稀疏索引的数据源（逐行索引不需要）
+    pub(super) fn set_source(&mut self, source: &PieceTable) {
+        if let Some(sparse) = &mut self.sparse {
+            sparse.set_source(Arc::new(source.without_lines()));
+        }
+    }
+}
+
+// ========== 内部辅助 ==========
+
+impl Lines {
+    /// 定位偏移所在的行，返回 (行号, 行起始偏移, 行内容长度)
+    ///
+    /// 调用方保证索引已构建且 offset <= total_bytes
+    fn locate(&self, offset: usize) -> (usize, usize, usize) {
+        let (line, LineLen(len), before) = self.lens.find_byte(offset).expect("行索引至少有一行");
+        (line, before.bytes, len)
+    }
+
+    /// 用新的行长度替换指定范围的行（只复制跨度树中涉及的路径）
+    fn splice(&mut self, lines: Range<usize>, new_lens: Vec<usize>) {
+        self.lens.splice(lines, new_lens.into_iter().map(LineLen).collect());
+    }
+
+    /// 按替换后的内容线性重建：保留部分沿用原来的换行位置，插入部分扫描换行
+    fn rebuild_with_replacements(&mut self, replacements: &[Replacement]) {
+        let old_total = self.total_bytes;
+        let line_count = self.lens.len();
+        let mut newlines = self
+            .lens
+            .iter()
+            .scan(0, |line_start, LineLen(len)| {
+                let newline = *line_start + len;
+                *line_start = newline + 1;
+                Some(newline)
+            })
+            .take(line_count.saturating_sub(1))
+            .peekable();
+
+        let mut lens = Vec::with_capacity(line_count);
+        let mut current = 0;
+        let mut pos = 0;
+        let mut total_bytes = old_total;
+        for replacement in replacements.iter().map(Some).chain([None]) {
+            let range = replacement.map_or(old_total..old_total, |r| r.range.clone());
+
+            // 保留 [pos, range.start)
+            while let Some(newline) = newlines.next_if(|&newline| newline < range.start) {
+                lens.push(current + newline - pos);
+                current = 0;
+                pos = newline + 1;
+            }
+            current += range.start - pos;
+
+            // 跳过被删除的换行
+            while newlines.next_if(|&newline| newline < range.end).is_some() {}
+            pos = range.end;
+
+            if let Some(replacement) = replacement {
+                for (i, part) in replacement.text.split(|&b| b == b'\n').enumerate() {
+                    if i > 0 {
+                        lens.push(current);
+                        current = 0;
+                    }
+                    current += part.len();
+                }
+                total_bytes = total_bytes + replacement.text.len() - range.len();
+            }
+        }
+        lens.push(current);
+
+        self.build_from_lens(lens, total_bytes);
+    }
+}
+
+/// 流式行扫描器：逐块输入字节，结束时生成完整的行索引
+#[derive(Debug, Clone, Default)]
+pub struct LineScanner {
+    /// 已完成行的内容长度
+    lens: Vec<usize>,
+    /// 当前未结束行的长度
+    current: usize,
+    /// 已扫描的字节数
+    scanned: usize,
+}
+
+impl LineScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 扫描一块字节
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let mut rest = bytes;
+        while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+            self.lens.push(self.current + pos);
+            self.current = 0;
+            rest = &rest[pos + 1..];
+        }
+        self.current += rest.len();
+        self.scanned += bytes.len();
+    }
+
+    /// 已扫描的字节数
+    pub fn scanned_bytes(&self) -> usize {
+        self.scanned
+    }
+
+    /// 结束扫描，生成行索引
+    pub fn finish(mut self) -> Lines {
+        self.lens.push(self.current);
+
+        let mut lines = Lines::new();
+        lines.build_from_lens(self.lens, self.scanned);
+        lines
+    }
+}
+
+impl Default for Lines {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn built(text: &str) -> Lines {
+        let mut lines = Lines::new();
+        lines.build_from_text(text);
+        lines
+    }
+
+    fn ranges(lines: &Lines) -> Vec<Range<usize>> {
+        (0..lines.total_lines()).map(|n| lines.get_line_range(n).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_build_and_lookup() {
+        let lines = built("ab\ncde\n\nf");
+
+        assert_eq!(ranges(&lines), vec![0..2, 3..6, 7..7, 8..9]);
+        assert_eq!(lines.find_line_by_offset(0), Some(0));
+        assert_eq!(lines.find_line_by_offset(2), Some(0));
+        assert_eq!(lines.find_line_by_offset(3), Some(1));
+        assert_eq!(lines.find_line_by_offset(9), Some(3));
+        assert_eq!(lines.find_line_by_offset(10), None);
+
+        let trailing = built("ab\n");
+        assert_eq!(trailing.total_lines(), 1);
+        assert!(trailing.line_info(0).unwrap().ends_with_newline);
+    }
+
+    #[test]
+    fn test_scanner_matches_build_from_text() {
+        let text = "alpha\nbeta\n\ngamma\n";
+        let mut scanner = LineScanner::new();
+        for chunk in text.as_bytes().chunks(3) {
+            scanner.feed(chunk);
+        }
+
+        let scanned = scanner.finish();
+        assert_eq!(ranges(&scanned), ranges(&built(text)));
+        assert_eq!(scanned.total_bytes(), text.len());
+    }
+
+    #[test]
+    fn test_incremental_edits_match_rebuild() {
+        let mut text = String::from("line one\nline two\nthree");
+        let mut lines = built(&text);
+
+        let mut seed = 7u64;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound.max(1)
+        };
+
+        for step in 0..2000 {
+            if step % 3 == 2 && !text.is_empty() {
+                let start = next(text.len());
+                let end = (start + next(40)).min(text.len());
+                text.replace_range(start..end, "");
+                lines.handle_delete(start..end);
+            } else {
+                let offset = next(text.len() + 1);
+                let insert = ["x", "\n", "ab\ncd", "\n\n", "hello world"][next(5)];
+                text.insert_str(offset, insert);
+                lines.handle_insert(offset, insert);
+            }
+
+            assert!(!lines.is_dirty());
+        }
+
+        let expected = built(&text);
+        assert_eq!(ranges(&lines), ranges(&expected));
+        assert_eq!(lines.total_bytes(), text.len());
+        for offset in (0..=text.len()).step_by(7) {
+            assert_eq!(lines.find_line_by_offset(offset), expected.find_line_by_offset(offset));
+        }
+    }
+
+    #[test]
+    fn test_bulk_replacements_match_rebuild() {
+        let text = "ab\ncd\n\nefgh\n".repeat(20);
+        let original = built(&text);
+
+        // 替换、删除跨行的范围、在换行处插入多行
+        let edits: Vec<(Range<usize>, &str)> = (0..text.len() - 4)
+            .step_by(6)
+            .map(|start| (start..start + [0, 1, 4][start / 6 % 3], ["x\ny", "", "\n\n"][(start / 6 + 1) % 3]))
+            .collect();
+        let replacements: Vec<Replacement> = edits
+            .iter()
+            .map(|(range, insert)| Replacement {
+                range: range.clone(),
+                removed_line_feeds: text[range.clone()].matches('\n').count(),
+                text: insert.as_bytes(),
+            })
+            .collect();
+        let mut expected = text.clone();
+        for (range, insert) in edits.iter().rev() {
+            expected.replace_range(range.clone(), insert);
+        }
+
+        assert!(replacements.len() > INCREMENTAL_REPLACEMENTS);
+        let mut rebuilt = original.clone();
+        rebuilt.handle_replacements(&replacements);
+        assert_eq!(ranges(&rebuilt), ranges(&built(&expected)));
+        assert_eq!(rebuilt.total_bytes(), expected.len());
+
+        let mut incremental = original.clone();
+        incremental.handle_replacements(&replacements[..INCREMENTAL_REPLACEMENTS]);
+        let mut partial = text.clone();
+        for (range, insert) in edits[..INCREMENTAL_REPLACEMENTS].iter().rev() {
+            partial.replace_range(range.clone(), insert);
+        }
+        assert_eq!(ranges(&incremental), ranges(&built(&partial)));
+    }
+
+    #[test]
+    fn test_edit_leaves_previous_snapshot_intact() {
+        let text = "0123456789\n".repeat(2048);
+        let original = built(&text);
+        let mut edited = original.clone();
+
+        edited.handle_insert(5, "\n");
+        edited.handle_delete(11_000..11_003);
+
+        // 插入的换行与删除的换行抵消，删除点所在的两行合并
+        assert_eq!(edited.total_lines(), original.total_lines());
+        assert_eq!(edited.get_line_range(2), Some(12..22));
+        assert_eq!(edited.get_line_range(1000), Some(10_990..11_008));
+        assert_eq!(original.get_line_range(1), Some(11..21));
+        assert_eq!(original.get_line_range(1000), Some(11_000..11_010));
+    }
 }
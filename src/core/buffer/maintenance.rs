@@ -0,0 +1,284 @@
+// 空闲整理
+//
+// 职责：记录空闲时分片执行的后台整理进度（Piece合并、碎片整理、行索引补建），
+//       每次 idle_tick 只做预算时间内的一小段，编辑时按位置保留或作废进度
+
+use std::sync::{Mutex, MutexGuard};
+
+use crate::core::buffer::line_indexer::Scanner;
+
+/// 一次 idle_tick 的结果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IdleReport {
+    /// 合并减少的Piece数量
+    pub merged_pieces: usize,
+    /// 碎片整理减少的Piece数量
+    pub defragmented_pieces: usize,
+    /// 本次扫描进行行索引的字节数
+    pub indexed_bytes: usize,
+    /// 释放的映射窗口字节数
+    pub trimmed_bytes: usize,
+    /// 是否已没有待做的整理（可以停止调度）
+    pub finished: bool,
+}
+
+impl IdleReport {
+    /// 本次是否做了任何工作
+    pub fn did_work(&self) -> bool {
+        self.merged_pieces > 0
+            || self.defragmented_pieces > 0
+            || self.indexed_bytes > 0
+            || self.trimmed_bytes > 0
+    }
+}
+
+/// 整理进度
+///
+/// 未完成的行索引扫描只属于一个快照：克隆（如撤销历史中的版本）不继承，
+/// 需要时从头扫描；编辑时扫描被移交给新版本，旧版本不再继续（避免复制扫描器）
+#[derive(Debug, Default)]
+pub(super) struct MaintenanceState {
+    /// 相邻Piece合并的进度
+    pub(super) merge: PassProgress,
+    /// 碎片整理的进度
+    pub(super) defrag: PassProgress,
+    /// 上次编辑中合并后剩下的Piece数（无法再合并的部分）
+    pub(super) pieces_after_merge: usize,
+    /// 未完成的行索引扫描（编辑时从 &self 移交，需要内部可变）
+    index_scan: Mutex<Option<IndexScan>>,
+}
+
+impl Clone for MaintenanceState {
+    fn clone(&self) -> Self {
+        Self {
+            merge: self.merge,
+            defrag: self.defrag,
+            pieces_after_merge: self.pieces_after_merge,
+            index_scan: Mutex::new(None),
+        }
+    }
+}
+
+/// 逐段扫描Piece链的整理进度
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct PassProgress {
+    /// 扫描续点（字节偏移，之前的Piece已检查过）
+    pub(super) cursor: usize,
+    /// 自上次完整扫描后是否有编辑产生新的碎片
+    pub(super) pending: bool,
+}
+
+impl PassProgress {
+    /// 编辑只会在编辑位置附近产生新碎片，续点退回到编辑位置之前即可
+    fn after_edit(self, offset: usize) -> Self {
+        let edit_cursor = offset.saturating_sub(1);
+        let cursor = if self.pending {
+            self.cursor.min(edit_cursor)
+        } else {
+            edit_cursor
+        };
+
+        Self { cursor, pending: true }
+    }
+
+    /// 完成一整遍扫描
+    pub(super) fn finish(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// 分片进行的行索引扫描
+#[derive(Debug)]
+pub(super) struct IndexScan {
+    pub(super) scanner: Scanner,
+    /// 已扫描到的字节偏移
+    pub(super) position: usize,
+}
+
+impl MaintenanceState {
+    /// 在 offset 处编辑后的进度
+    ///
+    /// 行索引扫描只有在编辑位于已扫描部分之后时才能继续
+    pub(super) fn after_edit(&self, offset: usize) -> Self {
+        let mut slot = self.slot();
+        let index_scan = match slot.as_ref() {
+            Some(scan) if offset >= scan.position => slot.take(),
+            _ => None,
+        };
+
+        Self {
+            merge: self.merge.after_edit(offset),
+            defrag: self.defrag.after_edit(offset),
+            pieces_after_merge: self.pieces_after_merge,
+            index_scan: Mutex::new(index_scan),
+        }
+    }
+
+    /// 取出未完成的行索引扫描
+    pub(super) fn take_index_scan(&self) -> Option<IndexScan> {
+        self.slot().take()
+    }
+
+    /// 放回未完成的行索引扫描
+    pub(super) fn store_index_scan(&self, scan: IndexScan) {
+        *self.slot() = Some(scan);
+    }
+
+    fn slot(&self) -> MutexGuard<'_, Option<IndexScan>> {
+        self.index_scan.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::core::buffer::PieceTable;
+
+    /// 原始内容中每隔5000字节插入两个相邻的追加Piece（"a"、"b"各插入一次）
+    ///
+    /// 原始内容的分段都大于碎片大小，不会被碎片整理
+    fn interleaved(pairs: usize) -> PieceTable {
+        const SEGMENT: usize = 5000;
+        let mut table = PieceTable::from_text(&"x".repeat(SEGMENT * pairs));
+        for pair in (1..=pairs).rev() {
+            let offset = pair * SEGMENT;
+            table = table.insert_char_safe(offset, "a").0;
+            table = table.insert_char_safe(offset + 1, "b").0;
+        }
+        table
+    }
+
+    #[test]
+    fn test_idle_tick_merges_in_bounded_slices() {
+        let mut table = interleaved(50);
+        let text = table.get_all_text();
+        let pieces = table.piece_count();
+        assert_eq!(pieces, 150);
+        assert!(table.needs_maintenance());
+
+        // 预算为0时每次只前进一步
+        let report = table.idle_tick(Duration::ZERO);
+        assert_eq!(report.merged_pieces, 1);
+        assert!(!report.finished);
+
+        let mut merged = report.merged_pieces;
+        loop {
+            let report = table.idle_tick(Duration::ZERO);
+            merged += report.merged_pieces;
+            if report.finished {
+                break;
+            }
+        }
+        assert_eq!(merged, 50);
+        assert_eq!(table.piece_count(), 100);
+        assert_eq!(table.get_all_text(), text);
+        assert!(!table.needs_maintenance());
+
+        // 编辑后只需重新检查编辑位置附近
+        let (edited, _) = table.insert_char_safe(table.total_bytes(), "c");
+        let (mut edited, _) = edited.insert_char_safe(edited.total_bytes(), "d");
+        assert!(edited.needs_maintenance());
+        let report = edited.idle_tick(Duration::from_secs(1));
+        assert_eq!(report.merged_pieces, 1);
+        assert!(report.finished);
+    }
+
+    #[test]
+    fn test_idle_tick_defragments_scattered_inserts() {
+        let mut table = PieceTable::from_text(&"0123456789".repeat(100));
+        for offset in (1..1000).rev().step_by(4) {
+            table = table.insert_char_safe(offset, "+").0;
+        }
+        let text = table.get_all_text();
+        let pieces = table.piece_count();
+        assert!(pieces > 400);
+
+        let report = table.idle_tick(Duration::from_secs(1));
+        assert!(report.finished);
+        assert_eq!(report.merged_pieces, 0);
+        assert_eq!(report.defragmented_pieces, pieces - 1);
+        assert_eq!(table.piece_count(), 1);
+        assert_eq!(table.get_all_text(), text);
+    }
+
+    #[test]
+    fn test_suspended_merge_is_left_alone() {
+        let mut table = interleaved(5);
+        table.suspend_auto_merge();
+
+        let report = table.idle_tick(Duration::from_secs(1));
+        assert_eq!(report.merged_pieces, 0);
+        assert!(report.finished);
+        assert_eq!(table.piece_count(), 15);
+    }
+
+    #[test]
+    fn test_line_index_catches_up_across_edits() {
+        let line = "idle index line\n";
+        let mut table = PieceTable::from_text(&line.repeat(20_000));
+        assert!(table.lines().is_none());
+
+        let first = table.idle_tick(Duration::ZERO);
+        assert!(first.indexed_bytes > 0 && first.indexed_bytes < table.total_bytes());
+        assert!(!first.finished);
+
+        // 在已扫描部分之后编辑：扫描继续，不会重复
+        let (mut table, _) = table.insert_char_safe(table.total_bytes(), "tail\n");
+        let mut indexed = first.indexed_bytes;
+        loop {
+            let report = table.idle_tick(Duration::ZERO);
+            indexed += report.indexed_bytes;
+            if report.finished {
+                break;
+            }
+        }
+        assert_eq!(indexed, table.total_bytes());
+
+        let lines = table.lines().unwrap();
+        assert_eq!(lines.total_lines(), 20_001);
+        assert_eq!(table.get_line(19_999).as_deref(), Some("idle index line"));
+        assert_eq!(table.get_line(20_000).as_deref(), Some("tail"));
+    }
+
+    #[test]
+    fn test_scan_progress_is_not_shared_between_snapshots() {
+        let line = "snapshot\n";
+        let mut table = PieceTable::from_text(&line.repeat(30_000));
+        let first = table.idle_tick(Duration::ZERO);
+        assert!(!first.finished);
+
+        // 克隆的快照从头扫描，不会取走或覆盖原表的进度
+        let mut snapshot = table.clone();
+        let mut indexed = 0;
+        while snapshot.needs_maintenance() {
+            indexed += snapshot.idle_tick(Duration::ZERO).indexed_bytes;
+        }
+        assert_eq!(indexed, snapshot.total_bytes());
+
+        let mut indexed = first.indexed_bytes;
+        while table.needs_maintenance() {
+            indexed += table.idle_tick(Duration::ZERO).indexed_bytes;
+        }
+        assert_eq!(indexed, table.total_bytes());
+        assert_eq!(table.lines().unwrap().total_lines(), 30_000);
+    }
+
+    #[test]
+    fn test_edit_before_scan_position_restarts_index() {
+        let line = "restart\n";
+        let mut scanning = PieceTable::from_text(&line.repeat(30_000));
+        let first = scanning.idle_tick(Duration::ZERO);
+        assert!(!first.finished);
+
+        // 在已扫描部分之前编辑：扫描从头开始
+        let (mut edited, _) = scanning.insert_char_safe(0, "head\n");
+        let mut indexed = 0;
+        while edited.needs_maintenance() {
+            indexed += edited.idle_tick(Duration::ZERO).indexed_bytes;
+        }
+        assert_eq!(indexed, edited.total_bytes());
+        assert_eq!(edited.get_line(0).as_deref(), Some("head"));
+        assert_eq!(edited.lines().unwrap().total_lines(), 30_001);
+    }
+}
@@ -1,35 +1,209 @@
 // 内存映射缓冲区
 //
-// 职责：为大文件提供内存映射支持，避免一次性加载全部内容
+// 职责：为大文件提供内存映射支持，避免一次性加载全部内容；
+//       记录映射时的文件身份，检测外部修改，必要时改用私有副本
+//
+// 超过 WINDOWED_FILE_THRESHOLD 的文件不整体映射，而是按需映射固定大小的窗口，
+// 在地址空间预算内按LRU淘汰，使超大文件只占用有限的虚拟内存。
+//
+// 外部截断或原地重写映射文件后，读取映射可能触发SIGBUS或读到新内容。
+// 检测到变化后应尽快调用 to_private，把仍被引用的范围复制到私有内存
 
-use std::ops::Range;
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
 
 #[cfg(not(target_arch = "wasm32"))]
-use memmap2::Mmap;
+use memmap2::{Mmap, MmapOptions};
+
+use crate::core::buffer::{
+    byte_slice::ByteSlice,
+    WINDOWED_FILE_THRESHOLD,
+};
+
+/// 默认窗口大小
+pub const DEFAULT_WINDOW_SIZE: usize = 64 * 1024 * 1024; // 64MB
+/// 默认映射地址空间预算
+pub const DEFAULT_MAPPING_BUDGET: usize = 1024 * 1024 * 1024; // 1GB
+/// 窗口对齐粒度（映射偏移必须是页大小的倍数，Windows上是64KB）
+const WINDOW_ALIGNMENT: usize = 64 * 1024;
+
+/// 滑动窗口映射配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowConfig {
+    /// 单个窗口的大小（向上对齐到64KB）
+    pub window_size: usize,
+    /// 同时保留的映射总大小上限（至少一个窗口）
+    pub budget: usize,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            window_size: DEFAULT_WINDOW_SIZE,
+            budget: DEFAULT_MAPPING_BUDGET,
+        }
+    }
+}
+
+impl WindowConfig {
+    fn normalized(self) -> Self {
+        let window_size = self.window_size.max(1).div_ceil(WINDOW_ALIGNMENT) * WINDOW_ALIGNMENT;
+        Self {
+            window_size,
+            budget: self.budget.max(window_size),
+        }
+    }
+}
+
+/// 映射时记录的文件身份
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileIdentity {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    /// (设备号, inode)，非Unix平台为None
+    pub inode: Option<(u64, u64)>,
+}
+
+impl FileIdentity {
+    pub fn of(metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let inode = {
+            use std::os::unix::fs::MetadataExt;
+            Some((metadata.dev(), metadata.ino()))
+        };
+        #[cfg(not(unix))]
+        let inode = None;
+
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            inode,
+        }
+    }
+}
+
+/// 原始文件的磁盘状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskStatus {
+    /// 与映射时一致
+    Unchanged,
+    /// 大小、修改时间或inode发生变化
+    Modified,
+    /// 文件已被删除或无法访问
+    Removed,
+    /// 本缓冲区已把新内容保存到该路径（映射仍指向旧文件，内容保持有效）
+    Replaced,
+}
+
+impl DiskStatus {
+    /// 是否被外部修改（自身保存导致的替换不算）
+    pub fn is_changed(&self) -> bool {
+        matches!(self, DiskStatus::Modified | DiskStatus::Removed)
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => DiskStatus::Unchanged,
+            1 => DiskStatus::Modified,
+            2 => DiskStatus::Removed,
+            _ => DiskStatus::Replaced,
+        }
+    }
+}
+
+/// 映射文件的磁盘信息（所有克隆共享检测结果）
+#[derive(Debug)]
+struct DiskState {
+    path: PathBuf,
+    identity: FileIdentity,
+    status: AtomicU8,
+}
+
+impl DiskState {
+    fn new(path: &Path, metadata: &std::fs::Metadata) -> Arc<Self> {
+        Arc::new(Self {
+            path: path.to_path_buf(),
+            identity: FileIdentity::of(metadata),
+            status: AtomicU8::new(DiskStatus::Unchanged as u8),
+        })
+    }
+
+    /// 重新读取文件信息；一旦发现变化就保持变化状态
+    fn check(&self) -> DiskStatus {
+        // 路径已指向自己保存的新文件，不再与映射比较
+        if self.status() == DiskStatus::Replaced {
+            return DiskStatus::Replaced;
+        }
+
+        let current = match std::fs::metadata(&self.path) {
+            Ok(metadata) if FileIdentity::of(&metadata) == self.identity => DiskStatus::Unchanged,
+            Ok(_) => DiskStatus::Modified,
+            Err(_) => DiskStatus::Removed,
+        };
+
+        // 文件改回原样也不能保证内容一致，变化状态不会恢复
+        if current.is_changed() {
+            self.status.store(current as u8, Ordering::Release);
+        }
+        self.status()
+    }
+
+    fn status(&self) -> DiskStatus {
+        DiskStatus::from_u8(self.status.load(Ordering::Acquire))
+    }
+
+    /// 路径上仍是映射的那个文件时，返回其当前长度
+    fn current_len(&self) -> Option<u64> {
+        let metadata = std::fs::metadata(&self.path).ok()?;
+        (FileIdentity::of(&metadata).inode == self.identity.inode).then_some(metadata.len())
+    }
+}
+
+/// 私有副本中的一段（保持原始偏移）
+#[derive(Debug)]
+struct PrivateRange {
+    start: usize,
+    bytes: Box<[u8]>,
+}
 
 /// 内存映射缓冲区（大文件支持）
 #[derive(Debug, Clone)]
 pub struct MmapBuffer {
+    /// 整体映射
     #[cfg(not(target_arch = "wasm32"))]
     mmap: Option<Arc<Mmap>>,
 
+    /// 滑动窗口映射（超大文件）
+    #[cfg(not(target_arch = "wasm32"))]
+    windows: Option<Arc<WindowCache>>,
+
     #[cfg(target_arch = "wasm32")]
     data: Option<Arc<Vec<u8>>>,
 
+    /// 私有副本（只包含仍被引用的范围，存在时不再读取映射）
+    private: Option<Arc<Vec<PrivateRange>>>,
+
+    disk: Option<Arc<DiskState>>,
+
     length: usize,
 }
 
 impl MmapBuffer {
+    /// 映射文件，超过 WINDOWED_FILE_THRESHOLD 时自动使用滑动窗口
     #[cfg(not(target_arch = "wasm32"))]
     pub fn from_file(path: &std::path::Path) -> Result<Self, String> {
-        use std::fs::File;
-
-        let file = File::open(path)
-            .map_err(|e| format!("无法打开文件: {}", e))?;
+        let (file, metadata) = open_file(path)?;
 
-        let metadata = file.metadata()
-            .map_err(|e| format!("无法获取文件信息: {}", e))?;
+        if metadata.len() as usize >= WINDOWED_FILE_THRESHOLD {
+            return Ok(Self::windowed(path, file, &metadata, WindowConfig::default()));
+        }
 
         let mmap = unsafe {
             Mmap::map(&file)
@@ -38,14 +212,46 @@ impl MmapBuffer {
 
         Ok(Self {
             mmap: Some(Arc::new(mmap)),
+            windows: None,
+            private: None,
+            disk: Some(DiskState::new(path, &metadata)),
             length: metadata.len() as usize,
         })
     }
 
+    /// 使用滑动窗口映射文件（不受大小阈值限制）
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file_windowed(path: &std::path::Path, config: WindowConfig) -> Result<Self, String> {
+        let (file, metadata) = open_file(path)?;
+        Ok(Self::windowed(path, file, &metadata, config))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn windowed(path: &Path, file: std::fs::File, metadata: &std::fs::Metadata, config: WindowConfig) -> Self {
+        let length = metadata.len() as usize;
+
+        Self {
+            mmap: None,
+            windows: Some(Arc::new(WindowCache {
+                file,
+                config: config.normalized(),
+                length,
+                state: Mutex::new(WindowState::default()),
+                error: Mutex::new(None),
+            })),
+            private: None,
+            disk: Some(DiskState::new(path, metadata)),
+            length,
+        }
+    }
+
     #[cfg(not(target_arch = "wasm32"))]
     pub fn empty() -> Self {
         Self {
             mmap: None,
+            windows: None,
+            private: None,
+            disk: None,
             length: 0,
         }
     }
@@ -59,6 +265,8 @@ impl MmapBuffer {
     pub fn empty() -> Self {
         Self {
             data: None,
+            private: None,
+            disk: None,
             length: 0,
         }
     }
@@ -73,44 +281,700 @@ impl MmapBuffer {
         self.length == 0
     }
 
-    /// 获取字节切片
-    pub fn get_bytes(&self, range: Range<usize>) -> &[u8] {
+    /// 获取字节（跨越窗口边缘时复制拼接，否则直接借用）
+    ///
+    /// 使用私有副本时，只能读取复制过的范围（超出部分被截去）
+    pub fn get_bytes(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        let mut slices = self.slices(range);
+        let Some(first) = slices.next() else {
+            return Cow::Borrowed(&[]);
+        };
+
+        match (first.as_borrowed(), slices.next()) {
+            (Some(bytes), None) => Cow::Borrowed(bytes),
+            (_, second) => {
+                let mut bytes = first.to_vec();
+                for slice in second.into_iter().chain(slices) {
+                    bytes.extend_from_slice(&slice);
+                }
+                Cow::Owned(bytes)
+            }
+        }
+    }
+
+    /// 尝试获取文本（UTF-8验证）
+    pub fn get_text(&self, range: Range<usize>) -> Result<Cow<'_, str>, std::str::Utf8Error> {
+        match self.get_bytes(range) {
+            Cow::Borrowed(bytes) => std::str::from_utf8(bytes).map(Cow::Borrowed),
+            Cow::Owned(bytes) => String::from_utf8(bytes)
+                .map(Cow::Owned)
+                .map_err(|e| e.utf8_error()),
+        }
+    }
+
+    /// 获取文本（UTF-8损失转换）
+    pub fn get_text_lossy(&self, range: Range<usize>) -> String {
+        let bytes = self.get_bytes(range);
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// 按底层连续内存分段遍历范围内的字节（零拷贝，窗口映射时按窗口分段）
+    pub fn slices(&self, range: Range<usize>) -> MmapSlices<'_> {
         let start = range.start.min(self.length);
         let end = range.end.min(self.length);
 
-        if start >= end {
-            return &[];
+        MmapSlices {
+            buffer: self,
+            range: start..end.max(start),
+        }
+    }
+
+    /// 范围内包含 offset 的那段连续字节，返回 (切片起始位置, 切片)
+    ///
+    /// 只读取 offset 所在的窗口或私有副本，与 offset 之前的内容无关
+    pub fn slice_containing(&self, offset: usize, range: Range<usize>) -> Option<(usize, ByteSlice<'_>)> {
+        let end = range.end.min(self.length);
+        if offset < range.start || offset >= end {
+            return None;
+        }
+
+        let segment_start = self.segment_start(offset)?.max(range.start);
+        let slice = self.slice_from(segment_start, end)?;
+        (offset < segment_start + slice.len()).then_some((segment_start, slice))
+    }
+
+    /// offset 所在的底层连续内存（私有副本、窗口或整体映射）的起始位置
+    fn segment_start(&self, offset: usize) -> Option<usize> {
+        if let Some(ref private) = self.private {
+            let index = private.partition_point(|r| r.start <= offset);
+            return Some(private[index.checked_sub(1)?].start);
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(ref windows) = self.windows {
+            return Some(offset / windows.config.window_size * windows.config.window_size);
+        }
+
+        Some(0)
+    }
+
+    /// 从 start 开始的一段连续字节，不超过 end
+    fn slice_from(&self, start: usize, end: usize) -> Option<ByteSlice<'_>> {
+        if let Some(ref private) = self.private {
+            let index = private.partition_point(|r| r.start <= start);
+            let copy = &private[index.checked_sub(1)?];
+            let copy_end = copy.start + copy.bytes.len();
+            return (start < copy_end)
+                .then(|| ByteSlice::Borrowed(&copy.bytes[start - copy.start..end.min(copy_end) - copy.start]));
         }
 
         #[cfg(not(target_arch = "wasm32"))]
         {
             if let Some(ref mmap) = self.mmap {
-                &mmap[start..end]
-            } else {
-                &[]
+                return Some(ByteSlice::Borrowed(&mmap[start..end]));
             }
+            if let Some(ref windows) = self.windows {
+                return match windows.slice(start, end, self.disk.as_deref()) {
+                    Ok(slice) => Some(ByteSlice::Mapped(slice)),
+                    Err(error) => {
+                        // 读取在此截断；记录原因并刷新磁盘状态，由调用方决定如何处理
+                        windows.record_error(error);
+                        if let Some(disk) = &self.disk {
+                            disk.check();
+                        }
+                        None
+                    }
+                };
+            }
+            None
         }
 
         #[cfg(target_arch = "wasm32")]
         {
-            if let Some(ref data) = self.data {
-                &data[start..end]
-            } else {
-                &[]
+            self.data.as_ref().map(|data| ByteSlice::Borrowed(&data[start..end]))
+        }
+    }
+}
+
+/// 映射字节的分段迭代器
+pub struct MmapSlices<'a> {
+    buffer: &'a MmapBuffer,
+    range: Range<usize>,
+}
+
+impl<'a> Iterator for MmapSlices<'a> {
+    type Item = ByteSlice<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.range.is_empty() {
+            return None;
+        }
+
+        match self.buffer.slice_from(self.range.start, self.range.end) {
+            Some(slice) if !slice.is_empty() => {
+                self.range.start += slice.len();
+                Some(slice)
+            }
+            _ => {
+                self.range.start = self.range.end;
+                None
             }
         }
     }
+}
 
-    /// 尝试获取文本（UTF-8验证）
-    pub fn get_text(&self, range: Range<usize>) -> Result<&str, std::str::Utf8Error> {
-        let bytes = self.get_bytes(range);
-        std::str::from_utf8(bytes)
+#[cfg(not(target_arch = "wasm32"))]
+fn open_file(path: &Path) -> Result<(std::fs::File, std::fs::Metadata), String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("无法打开文件: {}", e))?;
+
+    let metadata = file.metadata()
+        .map_err(|e| format!("无法获取文件信息: {}", e))?;
+
+    Ok((file, metadata))
+}
+
+// ========== 滑动窗口 ==========
+
+/// 映射窗口中的一段字节（持有窗口引用）
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct MappedSlice {
+    window: Arc<Mmap>,
+    range: Range<usize>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MappedSlice {
+    /// 子切片（零拷贝）
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(range.start <= range.end && range.end <= self.range.len(), "切片范围越界");
+
+        Self {
+            window: self.window.clone(),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
     }
+}
 
-    /// 获取文本（UTF-8损失转换）
-    pub fn get_text_lossy(&self, range: Range<usize>) -> String {
-        let bytes = self.get_bytes(range);
-        String::from_utf8_lossy(bytes).into_owned()
+#[cfg(not(target_arch = "wasm32"))]
+impl Deref for MappedSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.window[self.range.clone()]
     }
 }
 
+/// 窗口缓存：按需映射对齐的固定大小区域，超出预算时淘汰最久未使用的窗口
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+struct WindowCache {
+    file: std::fs::File,
+    config: WindowConfig,
+    length: usize,
+    state: Mutex<WindowState>,
+    /// 最近一次映射失败的原因
+    error: Mutex<Option<String>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+struct WindowState {
+    windows: HashMap<usize, CachedWindow>,
+    /// 访问计数（LRU时间戳）
+    clock: u64,
+    /// 缓存中映射的总字节数
+    mapped: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+struct CachedWindow {
+    mmap: Arc<Mmap>,
+    last_used: u64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WindowCache {
+    /// start 所在窗口中 start..end 的部分（在窗口末尾截断）
+    fn slice(&self, start: usize, end: usize, disk: Option<&DiskState>) -> Result<MappedSlice, String> {
+        let index = start / self.config.window_size;
+        let window_start = index * self.config.window_size;
+        let window = self.window(index, disk)?;
+        let end = end.min(window_start + window.len());
+
+        Ok(MappedSlice {
+            window,
+            range: start - window_start..end - window_start,
+        })
+    }
+
+    /// 映射（或取出缓存的）窗口
+    ///
+    /// 文件被截断到窗口之内时不再映射（访问截掉的页会触发SIGBUS）；
+    /// 映射失败时先释放其他窗口的地址空间再重试一次
+    fn window(&self, index: usize, disk: Option<&DiskState>) -> Result<Arc<Mmap>, String> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.clock += 1;
+        let clock = state.clock;
+
+        if let Some(cached) = state.windows.get_mut(&index) {
+            cached.last_used = clock;
+            return Ok(cached.mmap.clone());
+        }
+
+        let start = index * self.config.window_size;
+        let len = self.config.window_size.min(self.length - start);
+        if let Some(current) = disk.and_then(|disk| disk.current_len()) {
+            if (current as usize) < start + len {
+                return Err(format!("原文件已被截断: {} < {}", current, start + len));
+            }
+        }
+
+        let map = || unsafe { MmapOptions::new().offset(start as u64).len(len).map(&self.file) };
+        let mmap = match map() {
+            Ok(mmap) => mmap,
+            Err(_) => {
+                state.windows.clear();
+                state.mapped = 0;
+                map().map_err(|e| format!("映射窗口失败: {}", e))?
+            }
+        };
+        let mmap = Arc::new(mmap);
+
+        state.windows.insert(index, CachedWindow { mmap: mmap.clone(), last_used: clock });
+        state.mapped += len;
+
+        // 超出预算时淘汰（仍被切片引用的窗口在切片释放后才解除映射）
+        while state.mapped > self.config.budget {
+            let oldest = state
+                .windows
+                .iter()
+                .filter(|(&i, _)| i != index)
+                .min_by_key(|(_, window)| window.last_used)
+                .map(|(&i, _)| i);
+            let Some(oldest) = oldest else {
+                break;
+            };
+            if let Some(evicted) = state.windows.remove(&oldest) {
+                state.mapped -= evicted.mmap.len();
+            }
+        }
+
+        Ok(mmap)
+    }
+
+    fn record_error(&self, error: String) {
+        *self.error.lock().unwrap_or_else(|e| e.into_inner()) = Some(error);
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.error.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn mapped_bytes(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).mapped
+    }
+
+    /// 只保留最近使用的 keep 个窗口，返回释放的映射字节数
+    fn trim(&self, keep: usize) -> usize {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.windows.len() <= keep {
+            return 0;
+        }
+
+        let mut by_age: Vec<(u64, usize)> = state
+            .windows
+            .iter()
+            .map(|(&index, window)| (window.last_used, index))
+            .collect();
+        by_age.sort_unstable();
+
+        let mut freed = 0;
+        for &(_, index) in &by_age[..by_age.len() - keep] {
+            if let Some(evicted) = state.windows.remove(&index) {
+                freed += evicted.mmap.len();
+            }
+        }
+        state.mapped -= freed;
+        freed
+    }
+}
+
+impl MmapBuffer {
+    /// 是否使用滑动窗口映射
+    pub fn is_windowed(&self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.windows.is_some()
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            false
+        }
+    }
+
+    /// 当前保留的映射大小（整体映射时为文件大小，私有副本为0）
+    pub fn mapped_bytes(&self) -> usize {
+        if self.is_private() {
+            return 0;
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            match (&self.mmap, &self.windows) {
+                (Some(mmap), _) => mmap.len(),
+                (None, Some(windows)) => windows.mapped_bytes(),
+                (None, None) => 0,
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            0
+        }
+    }
+
+    /// 释放较久未用的映射窗口，只保留最近使用的 keep 个，返回释放的字节数
+    ///
+    /// 整体映射和私有副本没有可释放的窗口，返回0
+    pub fn trim_windows(&self, keep: usize) -> usize {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            match &self.windows {
+                Some(windows) if !self.is_private() => windows.trim(keep),
+                _ => 0,
+            }
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = keep;
+            0
+        }
+    }
+}
+
+// ========== 外部修改检测 ==========
+
+impl MmapBuffer {
+    /// 映射的文件路径
+    pub fn path(&self) -> Option<&Path> {
+        self.disk.as_ref().map(|disk| disk.path.as_path())
+    }
+
+    /// 映射时记录的文件身份
+    pub fn identity(&self) -> Option<FileIdentity> {
+        self.disk.as_ref().map(|disk| disk.identity)
+    }
+
+    /// 最近一次检查的结果（不访问磁盘）
+    pub fn disk_status(&self) -> DiskStatus {
+        self.disk.as_ref().map_or(DiskStatus::Unchanged, |disk| disk.status())
+    }
+
+    /// 最近一次读取失败的原因（窗口映射失败时读取被截断）
+    pub fn read_error(&self) -> Option<String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.windows.as_ref().and_then(|windows| windows.last_error())
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            None
+        }
+    }
+
+    /// 重新检查文件大小、修改时间和inode
+    pub fn check_disk(&self) -> DiskStatus {
+        self.disk.as_ref().map_or(DiskStatus::Unchanged, |disk| disk.check())
+    }
+
+    /// 记录映射的路径已被自身保存的新文件替换
+    pub(super) fn mark_replaced(&self) {
+        if let Some(disk) = &self.disk {
+            disk.status.store(DiskStatus::Replaced as u8, Ordering::Release);
+        }
+    }
+
+    /// 在后台线程中定时检查（没有关联文件时返回None）
+    pub fn watch(&self, interval: Duration) -> Option<DiskWatch> {
+        DiskWatch::spawn(self.disk.clone()?, interval).ok()
+    }
+
+    /// 是否已改用私有副本
+    pub fn is_private(&self) -> bool {
+        self.private.is_some()
+    }
+
+    /// 把指定范围复制到私有内存，返回不再读取映射的缓冲区（偏移保持不变）
+    ///
+    /// 文件已被截断到范围之内时返回错误：读取被截掉的映射页会触发SIGBUS
+    pub fn to_private(&self, ranges: &[Range<usize>]) -> Result<Self, String> {
+        let ranges = merge_ranges(ranges, self.length);
+
+        let needed = ranges.last().map_or(0, |r| r.end) as u64;
+        if !self.is_private() {
+            if let Some(len) = self.disk.as_ref().and_then(|disk| disk.current_len()) {
+                if len < needed {
+                    return Err(format!("原文件已被截断: {} < {}", len, needed));
+                }
+            }
+        }
+
+        let mut copies = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            let bytes = self.get_bytes(range.clone()).into_owned().into_boxed_slice();
+            if bytes.len() < range.len() {
+                let reason = self.read_error().unwrap_or_else(|| "读取被截断".to_string());
+                return Err(format!("无法复制原文件内容: {}", reason));
+            }
+            copies.push(PrivateRange { start: range.start, bytes });
+        }
+
+        Ok(Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            mmap: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            windows: None,
+            #[cfg(target_arch = "wasm32")]
+            data: None,
+            private: Some(Arc::new(copies)),
+            disk: self.disk.clone(),
+            length: self.length,
+        })
+    }
+
+    /// 私有副本占用的字节数
+    pub fn private_bytes(&self) -> usize {
+        self.private
+            .as_ref()
+            .map_or(0, |private| private.iter().map(|r| r.bytes.len()).sum())
+    }
+}
+
+/// 排序、截断并合并相邻或重叠的范围
+fn merge_ranges(ranges: &[Range<usize>], length: usize) -> Vec<Range<usize>> {
+    let mut sorted: Vec<Range<usize>> = ranges
+        .iter()
+        .map(|r| r.start.min(length)..r.end.min(length))
+        .filter(|r| !r.is_empty())
+        .collect();
+    sorted.sort_by_key(|r| r.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(sorted.len());
+    for range in sorted {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+// ========== 定时检查 ==========
+
+/// 后台文件检查（丢弃句柄会停止检查；发现变化后线程自行退出）
+#[derive(Debug)]
+pub struct DiskWatch {
+    disk: Arc<DiskState>,
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl DiskWatch {
+    fn spawn(disk: Arc<DiskState>, interval: Duration) -> std::io::Result<Self> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread_disk = disk.clone();
+
+        let handle = std::thread::Builder::new()
+            .name("mmap-watch".into())
+            .spawn(move || {
+                // 发送端被丢弃时 recv_timeout 立即返回 Disconnected
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if thread_disk.check() != DiskStatus::Unchanged {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            disk,
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+
+    /// 最近一次检查的结果
+    pub fn status(&self) -> DiskStatus {
+        self.disk.status()
+    }
+
+    /// 停止检查并等待线程退出
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for DiskWatch {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("zedit_mmap_{}_{}", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_detects_truncation_and_removal() {
+        let path = temp_file("truncate", b"0123456789");
+        let buffer = MmapBuffer::from_file(&path).unwrap();
+        assert_eq!(buffer.check_disk(), DiskStatus::Unchanged);
+
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(4).unwrap();
+        assert_eq!(buffer.check_disk(), DiskStatus::Modified);
+        // 克隆共享检测结果
+        assert_eq!(buffer.clone().disk_status(), DiskStatus::Modified);
+
+        // 截断后不能再从映射复制被截掉的范围
+        assert!(buffer.to_private(&[2..8, 0..1]).is_err());
+        assert!(buffer.to_private(&[0..1, 2..4]).is_ok());
+
+        drop(buffer);
+        std::fs::remove_file(&path).unwrap();
+        let other = temp_file("removed", b"abc");
+        let buffer = MmapBuffer::from_file(&other).unwrap();
+        std::fs::remove_file(&other).unwrap();
+        assert_eq!(buffer.check_disk(), DiskStatus::Removed);
+    }
+
+    #[test]
+    fn test_private_copy_keeps_offsets() {
+        let path = temp_file("private", b"hello mapped world");
+        let buffer = MmapBuffer::from_file(&path).unwrap();
+
+        let private = buffer.to_private(&[6..12, 0..2, 1..5, 13..18]).unwrap();
+        drop(buffer);
+        std::fs::write(&path, b"rewritten").unwrap();
+
+        assert!(private.is_private());
+        assert_eq!(private.private_bytes(), 5 + 6 + 5);
+        assert_eq!(&*private.get_bytes(1..4), b"ell");
+        assert_eq!(&*private.get_bytes(6..12), b"mapped");
+        assert_eq!(&*private.get_bytes(13..18), b"world");
+        // 未复制的范围读不到内容
+        assert_eq!(&*private.get_bytes(12..13), b"");
+        assert_eq!(private.check_disk(), DiskStatus::Modified);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_windowed_read_stops_when_file_truncated() {
+        let content = vec![b'x'; 4 * 64 * 1024];
+        let path = temp_file("windowed_truncate", &content);
+        let config = WindowConfig { window_size: 1, budget: 64 * 1024 };
+        let buffer = MmapBuffer::from_file_windowed(&path, config).unwrap();
+        assert_eq!(buffer.get_bytes(0..4).len(), 4);
+
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(64 * 1024 + 10).unwrap();
+
+        // 截掉的窗口不再映射：读取被截断而不是中止
+        let edge = 3 * 64 * 1024;
+        assert_eq!(buffer.get_bytes(edge..edge + 10).len(), 0);
+        assert!(buffer.read_error().unwrap().contains("截断"));
+        assert_eq!(buffer.disk_status(), DiskStatus::Modified);
+        assert!(buffer.to_private(&[0..4, edge..edge + 10]).is_err());
+        // 已映射的窗口仍然可读
+        assert_eq!(&*buffer.get_bytes(0..4), b"xxxx");
+
+        drop(buffer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_windowed_reads_across_window_edges() {
+        // 64KB窗口、最多保留3个窗口
+        let content: Vec<u8> = (0..10 * 64 * 1024).map(|i| (i % 251) as u8).collect();
+        let path = temp_file("windowed", &content);
+        let config = WindowConfig { window_size: 1, budget: 3 * 64 * 1024 };
+        let buffer = MmapBuffer::from_file_windowed(&path, config).unwrap();
+        assert!(buffer.is_windowed());
+
+        // 跨越两个窗口：分两段返回，拼接后与文件一致
+        let edge = 64 * 1024;
+        assert_eq!(buffer.slices(edge - 10..edge + 10).count(), 2);
+        assert_eq!(&*buffer.get_bytes(edge - 10..edge + 10), &content[edge - 10..edge + 10]);
+        assert!(matches!(buffer.get_bytes(5..50), Cow::Owned(_)));
+
+        // 顺序读完整个文件，映射大小始终不超过预算
+        let mut read = Vec::new();
+        for slice in buffer.slices(0..content.len()) {
+            read.extend_from_slice(&slice);
+            assert!(buffer.mapped_bytes() <= 3 * 64 * 1024);
+        }
+        assert_eq!(read, content);
+
+        // 被淘汰窗口中的切片仍然有效
+        let first = buffer.slices(0..16).next().unwrap();
+        for slice in buffer.slices(edge..content.len()) {
+            drop(slice);
+        }
+        assert_eq!(&*first, &content[..16]);
+        assert_eq!(buffer.get_text_lossy(0..0), "");
+
+        // 空闲时只保留最近使用的窗口
+        assert_eq!(buffer.trim_windows(1), 2 * 64 * 1024);
+        assert_eq!(buffer.mapped_bytes(), 64 * 1024);
+        assert_eq!(buffer.trim_windows(1), 0);
+        assert_eq!(&*buffer.get_bytes(content.len() - 4..content.len()), &content[content.len() - 4..]);
+
+        // 定位偏移所在的切片只映射那一个窗口
+        buffer.trim_windows(0);
+        let (start, slice) = buffer.slice_containing(7 * edge + 5, 100..content.len()).unwrap();
+        assert_eq!((start, slice.len()), (7 * edge, edge));
+        assert_eq!(buffer.mapped_bytes(), 64 * 1024);
+        let (start, slice) = buffer.slice_containing(150, 100..content.len()).unwrap();
+        assert_eq!((start, &*slice), (100, &content[100..edge]));
+
+        drop(first);
+        drop(buffer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_watch_reports_change() {
+        let path = temp_file("watch", b"watched");
+        let buffer = MmapBuffer::from_file(&path).unwrap();
+        let watch = buffer.watch(Duration::from_millis(5)).unwrap();
+        assert_eq!(watch.status(), DiskStatus::Unchanged);
+
+        std::fs::write(&path, b"changed content").unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !watch.status().is_changed() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(buffer.disk_status(), DiskStatus::Modified);
+
+        watch.stop();
+        std::fs::remove_file(&path).unwrap();
+        assert!(MmapBuffer::empty().watch(Duration::from_millis(5)).is_none());
+    }
+}
@@ -7,23 +7,56 @@ mod piece_table;
 mod mode;
 mod utf8;
 mod mmap;
+mod byte_slice;
 mod lines;
+mod line_indexer;
+mod sparse_lines;
+mod position;
+mod text_cursor;
+mod grapheme;
+mod maintenance;
+mod save;
+mod piece_slice;
+mod anchors;
+mod search;
+mod regex_search;
 mod deletion_info;
 mod chunk_iter;
+mod piece_tree;
+mod span_tree;
+mod add_buffer;
 
 // 重新导出
-pub use self::piece_table::{PieceTable, Piece, PieceType, OriginalBuffer};
+pub use self::piece_table::{PieceTable, Piece, PieceType, OriginalBuffer, ByteSlices, TextEdit, ByteEdit};
 pub use self::mode::BufferMode;
 pub use self::utf8::Utf8Validator;
-pub use self::mmap::MmapBuffer;
-pub use self::lines::{Lines, LineInfo};
+pub use self::mmap::{MmapBuffer, MmapSlices, WindowConfig, FileIdentity, DiskStatus, DiskWatch};
+pub use self::byte_slice::ByteSlice;
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::mmap::MappedSlice;
+pub use self::lines::{Lines, LineInfo, LineScanner};
+pub use self::sparse_lines::{SparseLines, SparseLineScanner, SparseLineConfig};
+pub use self::position::{Position, ColumnUnit, ColumnSpan, DEFAULT_TAB_WIDTH};
+pub use self::text_cursor::{TextCursor, TextChunk, CursorUnit};
+pub use self::save::{SaveOptions, SavedFile};
+pub use self::piece_slice::PieceSlice;
+pub use self::anchors::{Anchor, AnchorId, Gravity};
+pub use self::search::{SearchDirection, LiteralMatches};
+pub use self::regex_search::{RegexMatch, RegexMatches};
+pub use self::maintenance::IdleReport;
+pub use self::line_indexer::{LineIndexTask, LineIndex, LineIndexProgress};
 pub use self::deletion_info::{DeletionInfo, DeletionPiece};
 pub use self::chunk_iter::ChunkIter;
+pub use self::piece_tree::{PieceTree, PieceEntry, PieceSummary, PieceIter};
+pub use self::add_buffer::{AddBuffer, AddChunks};
 
 /// 文件大小阈值配置（根据冻结清单）
 pub const SMALL_FILE_THRESHOLD: usize = 10 * 1024 * 1024; // 10MB
 pub const LARGE_FILE_THRESHOLD: usize = 100 * 1024 * 1024; // 100MB
+pub const WINDOWED_FILE_THRESHOLD: usize = 2 * 1024 * 1024 * 1024; // 2GB，超过后使用滑动窗口映射
 
 /// 性能相关常量
 pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024; // 64KB，流式处理块大小
 pub const LARGE_OPERATION_THRESHOLD: usize = 10 * 1024 * 1024; // 10MB，大型操作阈值
+pub const DEFRAG_FRAGMENT_SIZE: usize = 4 * 1024; // 4KB，碎片整理时视为碎片的Piece大小
+pub const DEFAULT_MAX_MATCH_LEN: usize = 4 * 1024; // 4KB，正则搜索时单个匹配的最大长度
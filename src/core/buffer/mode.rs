@@ -4,6 +4,9 @@
 
 use std::time::Duration;
 
+/// 模式不限制单次合并大小时使用的上限
+const DEFAULT_MAX_MERGE_SIZE: usize = 1024 * 1024; // 1MB
+
 /// 缓冲区工作模式（根据文件大小自适应）
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BufferMode {
@@ -46,7 +49,7 @@ impl BufferMode {
             BufferMode::MemoryMapped {
                 merge_threshold: 2000,
                 merge_on_idle: true,
-                max_merge_size: 1 * 1024 * 1024, // 1MB
+                max_merge_size: DEFAULT_MAX_MERGE_SIZE,
             }
         } else {
             BufferMode::Restricted {
@@ -56,14 +59,6 @@ impl BufferMode {
         }
     }
 
-    /// 默认模式（空文件或新文件）
-    pub fn default() -> Self {
-        BufferMode::InMemory {
-            merge_threshold: 1000,
-            merge_on_edit: true,
-        }
-    }
-
     /// 获取合并阈值
     pub fn merge_threshold(&self) -> usize {
         match self {
@@ -73,6 +68,14 @@ impl BufferMode {
         }
     }
 
+    /// 单次合并（包括碎片整理生成的新Piece）的最大字节数
+    pub fn max_merge_size(&self) -> usize {
+        match self {
+            BufferMode::MemoryMapped { max_merge_size, .. } => *max_merge_size,
+            _ => DEFAULT_MAX_MERGE_SIZE,
+        }
+    }
+
     /// 是否应该自动合并
     pub fn should_auto_merge(&self) -> bool {
         match self {
@@ -89,7 +92,11 @@ impl BufferMode {
 }
 
 impl Default for BufferMode {
+    /// 默认模式（空文件或新文件）
     fn default() -> Self {
-        Self::default()
+        BufferMode::InMemory {
+            merge_threshold: 1000,
+            merge_on_edit: true,
+        }
     }
 }
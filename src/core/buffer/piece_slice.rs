@@ -0,0 +1,248 @@
+// Piece切片
+//
+// 职责：以Piece引用的形式提取文档片段，插入任意 PieceTable 时不复制内容，
+//       用于复制行、移动块和粘贴大段选区
+//
+// 同一文档内直接复用Piece；插入其他文档时，目标的追加缓冲区引用源缓冲区的内容。
+// 源文档的原始内容来自仍在读取文件的内存映射时，引用到的范围复制为私有副本：
+// 目标文档不检查源文件的磁盘状态，源文件被外部修改后不能再读到变化的内容
+
+use std::ops::Range;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+
+use crate::core::buffer::{
+    piece_table::{PieceTable, Piece, PieceType, OriginalBuffer},
+    piece_tree::PieceEntry,
+    add_buffer::{AddBuffer, BlockData},
+};
+
+/// 文档片段（持有源缓冲区的引用和Piece列表，不持有文本）
+#[derive(Debug, Clone)]
+pub struct PieceSlice {
+    original: OriginalBuffer,
+    additions: AddBuffer,
+    pieces: Vec<PieceEntry>,
+    len: usize,
+    line_feeds: usize,
+}
+
+impl PieceSlice {
+    pub(super) fn new(original: OriginalBuffer, additions: AddBuffer, pieces: Vec<PieceEntry>) -> Self {
+        let pieces: Vec<PieceEntry> = pieces.into_iter().filter(|entry| !entry.piece.is_empty()).collect();
+        let len = pieces.iter().map(|entry| entry.piece.length).sum();
+        let line_feeds = pieces.iter().map(|entry| entry.line_feeds).sum();
+
+        Self {
+            original,
+            additions,
+            pieces,
+            len,
+            line_feeds,
+        }
+    }
+
+    /// 字节长度
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 换行数
+    pub fn line_feeds(&self) -> usize {
+        self.line_feeds
+    }
+
+    /// Piece数量
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// 读出全部字节（写入系统剪贴板等需要实际内容时使用）
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let view = PieceTable::from_parts(self.original.clone(), self.additions.clone(), self.pieces.clone());
+        view.get_bytes_range(0..self.len)
+    }
+
+    /// 引用同一个原始缓冲区和追加缓冲区
+    fn shares_buffers_with(&self, table: &PieceTable) -> bool {
+        let (original, additions) = table.buffers();
+        self.original.same_buffer(original) && self.additions.shares_storage_with(additions)
+    }
+
+    /// 各Piece内容的共享引用（按文档顺序）
+    fn shared_blocks(&self) -> Vec<BlockData> {
+        let mut blocks = Vec::with_capacity(self.pieces.len());
+        for entry in &self.pieces {
+            let piece = entry.piece;
+            let range = piece.start..piece.start + piece.length;
+            match piece.piece_type {
+                PieceType::Original => blocks.push(original_block(&self.original, range)),
+                PieceType::Add => blocks.extend(self.additions.share_range(range)),
+            }
+        }
+        blocks
+    }
+}
+
+/// 原始缓冲区中一段内容的共享引用
+fn original_block(original: &OriginalBuffer, range: Range<usize>) -> BlockData {
+    match original {
+        OriginalBuffer::InMemory(bytes) => BlockData::Bytes { bytes: bytes.clone(), range },
+        #[cfg(not(target_arch = "wasm32"))]
+        OriginalBuffer::MemoryMapped(mmap) if mmap.is_private() => BlockData::Mapped { mmap: mmap.clone(), range },
+        #[cfg(not(target_arch = "wasm32"))]
+        OriginalBuffer::MemoryMapped(mmap) => match mmap.to_private(std::slice::from_ref(&range)) {
+            // 只复制引用的这一段（一次），源文件之后被修改也不影响
+            Ok(private) => BlockData::Mapped { mmap: Arc::new(private), range },
+            // 映射已无法读取时只能保留引用
+            Err(_) => BlockData::Mapped { mmap: mmap.clone(), range },
+        },
+        #[cfg(target_arch = "wasm32")]
+        OriginalBuffer::Bytes(bytes) => BlockData::Bytes { bytes: bytes.clone(), range },
+    }
+}
+
+// ========== 提取和插入 ==========
+
+impl PieceTable {
+    /// 提取字节范围为Piece切片（O(log n + 范围内的Piece数)，不复制内容）
+    pub fn piece_slice(&self, range: Range<usize>) -> PieceSlice {
+        let start = range.start.min(self.total_bytes());
+        let end = range.end.min(self.total_bytes());
+        let (original, additions) = self.buffers();
+
+        let mut entries = Vec::new();
+        for (pos, entry) in self.piece_tree().iter_from(start) {
+            if pos >= end {
+                break;
+            }
+
+            let piece = entry.piece;
+            let overlap_start = start.max(pos);
+            let overlap_end = end.min(pos + piece.length);
+            if overlap_start >= overlap_end {
+                continue;
+            }
+
+            let part = Piece {
+                piece_type: piece.piece_type,
+                start: piece.start + (overlap_start - pos),
+                length: overlap_end - overlap_start,
+            };
+            // 完整包含的Piece直接使用缓存的换行数
+            let line_feeds = if part == piece {
+                entry.line_feeds
+            } else {
+                self.byte_slices(overlap_start..overlap_end)
+                    .map(|slice| slice.iter().filter(|&&b| b == b'\n').count())
+                    .sum()
+            };
+            entries.push(PieceEntry::new(part, line_feeds));
+        }
+
+        PieceSlice::new(original.clone(), additions.clone(), entries)
+    }
+
+    /// 在字节偏移处插入Piece切片（偏移调整到字符边界），返回新表和插入的范围
+    ///
+    /// 切片来自同一文档时O(切片Piece数 × log n)；来自其他文档时
+    /// 追加缓冲区只增加对源内容的引用（源文档直接映射的原文件例外：
+    /// 文件可能被外部修改，引用到的范围复制为私有副本）
+    pub fn insert_slice(&self, byte_offset: usize, slice: &PieceSlice) -> (Self, Range<usize>) {
+        let offset = self.ensure_char_boundary(byte_offset.min(self.total_bytes()));
+        if slice.is_empty() {
+            return (self.clone(), offset..offset);
+        }
+
+        (self.splice_slice(offset, slice), offset..offset + slice.len())
+    }
+
+    /// 在 offset 处接入切片（调用方保证偏移有效）
+    pub(super) fn splice_slice(&self, offset: usize, slice: &PieceSlice) -> Self {
+        if slice.shares_buffers_with(self) {
+            return self.splice_entries(offset, slice.pieces.clone());
+        }
+
+        // 来自其他缓冲区：作为一段连续的追加地址引用源内容
+        let (_, additions) = self.buffers();
+        let add_range = additions.append_shared(slice.shared_blocks());
+        self.splice_entries(offset, vec![PieceEntry::new(Piece::add(add_range), slice.line_feeds)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_within_document_reuses_pieces() {
+        let text = "first line\nsecond line\nthird line\n";
+        let (table, _) = PieceTable::from_text(text).insert_char_safe(11, "inserted ");
+
+        // 复制第二行到开头（复制行）
+        let line = 11..32;
+        let slice = table.piece_slice(line.clone());
+        assert_eq!(slice.to_bytes(), b"inserted second line\n");
+        assert_eq!(slice.line_feeds(), 1);
+
+        let (duplicated, inserted) = table.insert_slice(0, &slice);
+        assert_eq!(inserted, 0..line.len());
+        assert_eq!(
+            duplicated.get_all_text(),
+            "inserted second line\nfirst line\ninserted second line\nthird line\n"
+        );
+        // 只复用Piece，追加缓冲区没有增长
+        assert_eq!(duplicated.buffers().1.len(), table.buffers().1.len());
+
+        // 移动块：先插入到末尾再删除原位置
+        let (moved, _) = table.insert_slice(table.total_bytes(), &slice);
+        let (moved, _) = moved.delete_char_safe(line);
+        assert_eq!(moved.get_all_text(), "first line\nthird line\ninserted second line\n");
+    }
+
+    #[test]
+    fn test_paste_into_other_document_shares_content() {
+        let source = PieceTable::from_bytes(b"raw \xFF bytes and text");
+        let (source, _) = source.insert_char_safe(4, "added ");
+        let slice = source.piece_slice(2..16);
+        assert_eq!(slice.piece_count(), 3);
+
+        let target = PieceTable::from_text("[]");
+        let (pasted, inserted) = target.insert_slice(1, &slice);
+        assert_eq!(inserted, 1..15);
+        assert_eq!(pasted.get_bytes_range(0..pasted.total_bytes()), b"[w added \xFF byte]");
+
+        // 源文档释放后内容仍然有效
+        drop(source);
+        drop(slice);
+        assert_eq!(pasted.get_bytes_range(1..4), b"w a");
+        let (edited, _) = pasted.insert_char_safe(8, "!");
+        assert_eq!(edited.get_bytes_range(0..edited.total_bytes()), b"[w added! \xFF byte]");
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_paste_from_mapped_file_survives_source_change() {
+        use crate::core::buffer::MmapBuffer;
+
+        let path = std::env::temp_dir().join(format!("zedit_slice_mapped_{}", std::process::id()));
+        std::fs::write(&path, b"mapped source text").unwrap();
+        let source = PieceTable::from_mapped(MmapBuffer::from_file(&path).unwrap());
+        let slice = source.piece_slice(7..13);
+
+        let (pasted, _) = PieceTable::from_text("<>").insert_slice(1, &slice);
+        assert_eq!(pasted.get_all_text(), "<source>");
+
+        // 源文件被原地改写：目标文档持有复制的内容，不受影响
+        std::fs::write(&path, b"MAPPED SOURCE TEXT").unwrap();
+        assert_eq!(pasted.get_all_text(), "<source>");
+
+        drop((source, slice));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
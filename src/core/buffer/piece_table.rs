@@ -3,24 +3,34 @@
 // 职责：使用 Piece Table 数据结构管理文本内容，
 //       支持高效插入、删除、撤销重做操作
 
+use std::borrow::Cow;
 use std::sync::Arc;
 use std::ops::Range;
 
 use crate::core::buffer::{
     mode::BufferMode,
+    mmap::{MmapBuffer, MmapSlices, DiskStatus, DiskWatch},
+    byte_slice::ByteSlice,
     utf8::Utf8Validator,
-    mmap::MmapBuffer,
-    lines::Lines,
+    lines::{Lines, Replacement},
+    line_indexer::{self, LineIndex, LineIndexTask, Scanner},
+    sparse_lines::{SparseLineConfig, SparseLineScanner},
     deletion_info::{DeletionInfo, DeletionPiece},
     chunk_iter::ChunkIter,
-    SMALL_FILE_THRESHOLD, LARGE_OPERATION_THRESHOLD,
+    text_cursor::TextCursor,
+    piece_slice::PieceSlice,
+    maintenance::{MaintenanceState, IndexScan, IdleReport},
+    anchors::AnchorSet,
+    piece_tree::{PieceTree, PieceEntry, PieceIter},
+    add_buffer::{AddBuffer, AddChunks},
+    SMALL_FILE_THRESHOLD, LARGE_OPERATION_THRESHOLD, DEFAULT_CHUNK_SIZE, DEFRAG_FRAGMENT_SIZE,
 };
 
 /// 原始缓冲区类型
 #[derive(Debug, Clone)]
 pub enum OriginalBuffer {
-    /// 小文件：内存中的字符串（Arc共享）
-    InMemory(Arc<str>),
+    /// 小文件：内存中的原始字节（Arc共享，不要求是合法UTF-8）
+    InMemory(Arc<[u8]>),
 
     /// 大文件：内存映射（只读）
     #[cfg(not(target_arch = "wasm32"))]
@@ -31,6 +41,36 @@ pub enum OriginalBuffer {
     Bytes(Arc<[u8]>),
 }
 
+impl OriginalBuffer {
+    /// 原始内容长度
+    pub fn len(&self) -> usize {
+        match self {
+            OriginalBuffer::InMemory(bytes) => bytes.len(),
+            #[cfg(not(target_arch = "wasm32"))]
+            OriginalBuffer::MemoryMapped(mmap) => mmap.len(),
+            #[cfg(target_arch = "wasm32")]
+            OriginalBuffer::Bytes(data) => data.len(),
+        }
+    }
+
+    /// 是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 是否是同一个缓冲区（共享同一份内容）
+    pub fn same_buffer(&self, other: &OriginalBuffer) -> bool {
+        match (self, other) {
+            (OriginalBuffer::InMemory(a), OriginalBuffer::InMemory(b)) => Arc::ptr_eq(a, b),
+            #[cfg(not(target_arch = "wasm32"))]
+            (OriginalBuffer::MemoryMapped(a), OriginalBuffer::MemoryMapped(b)) => Arc::ptr_eq(a, b),
+            #[cfg(target_arch = "wasm32")]
+            (OriginalBuffer::Bytes(a), OriginalBuffer::Bytes(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
 /// Piece类型
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PieceType {
@@ -70,25 +110,29 @@ impl Piece {
 }
 
 /// Piece Table核心实现
+///
+/// 所有内部数据都是持久化结构：克隆为O(1)，快照可跨线程共享（Send + Sync），
+/// 每次编辑只复制发生变化的树节点
 #[derive(Debug, Clone)]
 pub struct PieceTable {
     // --- 核心数据（使用Arc共享）---
     original: OriginalBuffer,           // 原始内容
-    additions: Arc<str>,                // 新增内容
+    additions: AddBuffer,               // 新增内容（只追加，快照间共享）
 
     // --- Piece链管理 ---
-    pieces: Vec<Piece>,                 // Piece链
-    piece_offsets: Vec<usize>,          // 累积偏移缓存
+    pieces: PieceTree,                  // Piece链（B树，缓存子树字节数/换行数）
 
     // --- 状态和配置 ---
-    total_bytes: usize,                 // 总字节数
     mode: BufferMode,                   // 缓冲区模式
     lines: Option<Lines>,               // 行索引
+    anchors: AnchorSet,                 // 随编辑移动的锚点
 
     // --- 合并控制 ---
     suspend_auto_merge: bool,           // 是否暂停自动合并
     last_merge_time: std::time::Instant, // 上次合并时间
     edit_count_since_last_merge: usize, // 上次合并后的编辑次数
+    last_edit_time: std::time::Instant, // 上次编辑时间（空闲检测）
+    maintenance: MaintenanceState,      // 空闲整理进度
 }
 
 // ========== 构造方法 ==========
@@ -97,33 +141,40 @@ impl PieceTable {
     /// 创建新的空PieceTable
     pub fn new() -> Self {
         Self {
-            original: OriginalBuffer::InMemory(Arc::from("")),
-            additions: Arc::from(""),
-            pieces: Vec::new(),
-            piece_offsets: Vec::new(),
-            total_bytes: 0,
+            original: OriginalBuffer::InMemory(Arc::from(&b""[..])),
+            additions: AddBuffer::new(),
+            pieces: PieceTree::new(),
             mode: BufferMode::default(),
             lines: None,
+            anchors: AnchorSet::default(),
             suspend_auto_merge: false,
             last_merge_time: std::time::Instant::now(),
             edit_count_since_last_merge: 0,
+            last_edit_time: std::time::Instant::now(),
+            maintenance: MaintenanceState::default(),
         }
     }
 
     /// 从文本创建（小文件）
     pub fn from_text(text: &str) -> Self {
-        let text_len = text.len();
+        Self::from_bytes(text.as_bytes())
+    }
+
+    /// 从原始字节创建（无效UTF-8和二进制内容原样保留）
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let len = bytes.len();
         let mut table = Self::new();
 
-        if text_len > SMALL_FILE_THRESHOLD {
-            table.mode = BufferMode::for_file_size(text_len);
+        if len > SMALL_FILE_THRESHOLD {
+            table.mode = BufferMode::for_file_size(len);
         }
 
-        if !text.is_empty() {
-            table.original = OriginalBuffer::InMemory(Arc::from(text));
-            table.pieces = vec![Piece::original(0..text_len)];
-            table.piece_offsets = vec![0];
-            table.total_bytes = text_len;
+        if !bytes.is_empty() {
+            table.original = OriginalBuffer::InMemory(Arc::from(bytes));
+            table.pieces = PieceTree::from_entries([PieceEntry::new(
+                Piece::original(0..len),
+                count_line_feeds(bytes),
+            )]);
         }
 
         table
@@ -142,30 +193,41 @@ impl PieceTable {
 
         match mode {
             BufferMode::InMemory { .. } => {
-                // 小文件：全量读入
-                let content = fs::read_to_string(path)
+                // 小文件：全量读入（按字节读取，非UTF-8内容不报错）
+                let content = fs::read(path)
                     .map_err(|e| format!("读取文件失败: {}", e))?;
 
-                Ok(Self::from_text(&content))
-            }
-            _ => {
-                // 大文件：内存映射
-                let mmap_buffer = MmapBuffer::from_file(path)?;
-                let arc_buffer = Arc::new(mmap_buffer);
-
-                Ok(Self {
-                    original: OriginalBuffer::MemoryMapped(arc_buffer),
-                    additions: Arc::from(""),
-                    pieces: vec![Piece::original(0..file_size)],
-                    piece_offsets: vec![0],
-                    total_bytes: file_size,
-                    mode,
-                    lines: None,
-                    suspend_auto_merge: false,
-                    last_merge_time: std::time::Instant::now(),
-                    edit_count_since_last_merge: 0,
-                })
+                Ok(Self::from_bytes(&content))
             }
+            _ => Ok(Self::from_mapped(MmapBuffer::from_file(path)?)),
+        }
+    }
+
+    /// 从内存映射（整体或滑动窗口）创建
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_mapped(mmap_buffer: MmapBuffer) -> Self {
+        let file_size = mmap_buffer.len();
+        // 初始Piece的换行数需要扫描一遍映射内容（窗口映射时逐窗口扫描）
+        let line_feeds = mmap_buffer
+            .slices(0..file_size)
+            .map(|slice| count_line_feeds(&slice))
+            .sum();
+
+        Self {
+            original: OriginalBuffer::MemoryMapped(Arc::new(mmap_buffer)),
+            additions: AddBuffer::new(),
+            pieces: PieceTree::from_entries([PieceEntry::new(
+                Piece::original(0..file_size),
+                line_feeds,
+            )]),
+            mode: BufferMode::for_file_size(file_size),
+            lines: None,
+            anchors: AnchorSet::default(),
+            suspend_auto_merge: false,
+            last_merge_time: std::time::Instant::now(),
+            edit_count_since_last_merge: 0,
+            last_edit_time: std::time::Instant::now(),
+            maintenance: MaintenanceState::default(),
         }
     }
 
@@ -180,13 +242,18 @@ impl PieceTable {
 impl PieceTable {
     /// 获取总字节数
     pub fn total_bytes(&self) -> usize {
-        self.total_bytes
+        self.pieces.total_bytes()
     }
 
     /// 获取总字符数（UTF-8安全）
     pub fn total_chars(&self) -> usize {
         // 懒计算，需要时遍历
-        self.get_text_range(0..self.total_bytes).chars().count()
+        self.get_text_range(0..self.total_bytes()).chars().count()
+    }
+
+    /// 获取总换行符数（由Piece树汇总，O(1)）
+    pub fn line_feed_count(&self) -> usize {
+        self.pieces.line_feeds()
     }
 
     /// 获取Piece数量
@@ -201,16 +268,15 @@ impl PieceTable {
 
     /// 是否为空
     pub fn is_empty(&self) -> bool {
-        self.total_bytes == 0
+        self.total_bytes() == 0
     }
 
     /// 估计内存使用量
     pub fn estimated_memory(&self) -> usize {
         let additions_size = self.additions.len();
-        let pieces_size = self.pieces.len() * std::mem::size_of::<Piece>();
-        let offsets_size = self.piece_offsets.len() * std::mem::size_of::<usize>();
+        let pieces_size = self.pieces.len() * std::mem::size_of::<PieceEntry>();
 
-        additions_size + pieces_size + offsets_size
+        additions_size + pieces_size
     }
 }
 
@@ -218,333 +284,542 @@ impl PieceTable {
 
 impl PieceTable {
     /// UTF-8安全的插入
-    pub fn insert_char_safe(&mut self, byte_offset: usize, text: &str) -> (Self, String) {
+    pub fn insert_char_safe(&self, byte_offset: usize, text: &str) -> (Self, String) {
         // 确保插入点在字符边界
-        let text_for_check = self.get_text_range(0..self.total_bytes.min(byte_offset + 100));
-        let safe_offset = Utf8Validator::ensure_char_boundary(&text_for_check, byte_offset);
+        let safe_offset = self.ensure_char_boundary(byte_offset);
 
         self.insert_internal(safe_offset, text)
     }
 
     /// UTF-8安全的删除
-    pub fn delete_char_safe(&mut self, range: Range<usize>) -> (Self, String) {
-        let text = self.get_text_range(range.clone());
-        let safe_range = Utf8Validator::ensure_char_boundary_range(&text, range);
+    pub fn delete_char_safe(&self, range: Range<usize>) -> (Self, String) {
+        let start = self.ensure_char_boundary(range.start);
+        let end = self.ensure_char_boundary(range.end);
 
-        if safe_range.is_empty() {
+        if start >= end {
             return (self.clone(), String::new());
         }
 
-        self.delete_internal(safe_range)
+        self.delete_internal(start..end)
+    }
+
+    /// 字节偏移是否位于字符边界（文档末尾也是边界）
+    ///
+    /// 只有位于合法多字节序列内部才不是边界；
+    /// 无效UTF-8中的孤立连续字节各自视为一个单元
+    pub fn is_char_boundary(&self, byte_offset: usize) -> bool {
+        if byte_offset > self.total_bytes() {
+            return false;
+        }
+
+        self.containing_sequence_start(byte_offset).is_none()
+    }
+
+    /// 将字节偏移向前调整到最近的字符边界（只读取偏移附近的字节）
+    pub(super) fn ensure_char_boundary(&self, byte_offset: usize) -> usize {
+        self.containing_sequence_start(byte_offset).unwrap_or(byte_offset)
+    }
+
+    /// 偏移落在多字节序列内部时，返回该序列的起始偏移
+    fn containing_sequence_start(&self, byte_offset: usize) -> Option<usize> {
+        // 10xxxxxx 是连续字节，向前最多3个字节寻找首字节
+        let is_continuation = |pos| matches!(self.byte_at(pos), Some(byte) if (byte & 0xC0) == 0x80);
+        if !is_continuation(byte_offset) {
+            return None;
+        }
+
+        for back in 1..=3.min(byte_offset) {
+            let start = byte_offset - back;
+            if is_continuation(start) {
+                continue;
+            }
+
+            // 首字节声明的长度覆盖该偏移，且序列中的字节都是连续字节
+            let len = self.byte_at(start).and_then(Utf8Validator::sequence_len)?;
+            let complete = (start + 1..start + len).all(is_continuation);
+            return (len > back && complete).then_some(start);
+        }
+
+        None
     }
 }
 
 // ========== 核心操作（内部） ==========
 
 impl PieceTable {
-    fn insert_internal(&mut self, offset: usize, text: &str) -> (Self, String) {
+    fn insert_internal(&self, offset: usize, text: &str) -> (Self, String) {
         if text.is_empty() {
             return (self.clone(), String::new());
         }
 
-        if offset > self.total_bytes {
-            panic!("插入位置超出范围: {} > {}", offset, self.total_bytes);
+        if offset > self.total_bytes() {
+            panic!("插入位置超出范围: {} > {}", offset, self.total_bytes());
         }
 
-        // 1. 查找插入点
-        let (piece_idx, offset_in_piece) = self.find_piece_and_offset(offset);
+        // 1. 在additions缓冲区追加新文本（只复制新插入的字节）
+        let add_range = self.additions.append(text);
 
-        // 2. 在additions缓冲区追加新文本
-        let current_additions = self.additions.to_string();
-        let add_start = current_additions.len();
-        let new_additions = format!("{}{}", current_additions, text);
-        let additions_arc = Arc::from(new_additions);
-        let add_length = text.len();
+        // 2. 在Piece树中插入（O(log n)，必要时分裂所在Piece）
+        let mut pieces = self.pieces.clone();
+        let entry = PieceEntry::new(Piece::add(add_range), count_line_feeds(text.as_bytes()));
+        pieces.insert(offset, entry, &|piece| self.count_piece_line_feeds(piece));
 
-        // 3. 构建新的Piece链
-        let mut new_pieces = Vec::with_capacity(self.pieces.len() + 2);
+        // 3. 创建新实例
+        let mut new_table = Self {
+            original: self.original.clone(),
+            additions: self.additions.clone(),
+            pieces,
+            mode: self.mode,
+            lines: self.lines.clone(),
+            anchors: self.anchors.clone(),
+            suspend_auto_merge: self.suspend_auto_merge,
+            last_merge_time: self.last_merge_time,
+            edit_count_since_last_merge: self.edit_count_since_last_merge + 1,
+            last_edit_time: std::time::Instant::now(),
+            maintenance: self.maintenance.after_edit(offset),
+        };
 
-        // 插入点之前的Piece
-        new_pieces.extend_from_slice(&self.pieces[..piece_idx]);
+        // 4. 智能合并决策
+        if new_table.should_merge_after_edit() {
+            new_table.merge_pieces_smart();
+        }
 
-        let current_piece = self.pieces[piece_idx];
+        // 5. 更新行索引和锚点
+        if let Some(mut lines) = new_table.lines.take() {
+            lines.handle_insert(offset, text);
+            lines.set_source(&new_table);
+            new_table.lines = Some(lines);
+        }
+        new_table.anchors.handle_insert(offset, text.len());
 
-        // 处理当前Piece的分裂和插入
-        if offset_in_piece > 0 && offset_in_piece < current_piece.length {
-            // 在Piece中间插入：分裂为三部分
-            new_pieces.push(Piece {
-                piece_type: current_piece.piece_type,
-                start: current_piece.start,
-                length: offset_in_piece,
-            });
+        (new_table, text.to_string())
+    }
 
-            new_pieces.push(Piece {
-                piece_type: PieceType::Add,
-                start: add_start,
-                length: add_length,
-            });
+    fn delete_internal(&self, range: Range<usize>) -> (Self, String) {
+        let start = range.start;
+        let end = range.end.min(self.total_bytes());
 
-            new_pieces.push(Piece {
-                piece_type: current_piece.piece_type,
-                start: current_piece.start + offset_in_piece,
-                length: current_piece.length - offset_in_piece,
-            });
-        } else if offset_in_piece == 0 {
-            // 在Piece开头插入
-            new_pieces.push(Piece {
-                piece_type: PieceType::Add,
-                start: add_start,
-                length: add_length,
-            });
-            new_pieces.push(current_piece);
-        } else {
-            // offset_in_piece == current_piece.length，在Piece结尾
-            new_pieces.push(current_piece);
-            new_pieces.push(Piece {
-                piece_type: PieceType::Add,
-                start: add_start,
-                length: add_length,
-            });
+        if start >= end {
+            return (self.clone(), String::new());
         }
 
-        // 插入点之后的Piece
-        if piece_idx + 1 < self.pieces.len() {
-            new_pieces.extend_from_slice(&self.pieces[piece_idx + 1..]);
-        }
+        // 1. 获取被删除的文本
+        let deleted_text = self.get_text_range(start..end);
+
+        // 2. 在Piece树中删除（O(log n)，边界Piece被裁剪）
+        let mut pieces = self.pieces.clone();
+        pieces.delete(start..end, &|piece| self.count_piece_line_feeds(piece));
 
-        // 4. 创建新实例
+        // 3. 创建新实例
         let mut new_table = Self {
             original: self.original.clone(),
-            additions: additions_arc,
-            pieces: new_pieces,
-            piece_offsets: Vec::new(),
-            total_bytes: self.total_bytes + add_length,
+            additions: self.additions.clone(),
+            pieces,
             mode: self.mode,
             lines: self.lines.clone(),
+            anchors: self.anchors.clone(),
             suspend_auto_merge: self.suspend_auto_merge,
             last_merge_time: self.last_merge_time,
             edit_count_since_last_merge: self.edit_count_since_last_merge + 1,
+            last_edit_time: std::time::Instant::now(),
+            maintenance: self.maintenance.after_edit(start),
         };
 
-        // 5. 更新累积偏移
-        new_table.update_piece_offsets();
-
-        // 6. 智能合并决策
+        // 4. 智能合并决策
         if new_table.should_merge_after_edit() {
             new_table.merge_pieces_smart();
         }
 
-        // 7. 更新行索引
-        if let Some(ref mut lines) = new_table.lines {
-            lines.handle_insert(offset, text);
+        // 5. 更新行索引（稀疏索引先用旧数据源统计被删除的换行）和锚点
+        if let Some(mut lines) = new_table.lines.take() {
+            lines.handle_delete(start..end);
+            lines.set_source(&new_table);
+            new_table.lines = Some(lines);
         }
+        new_table.anchors.handle_delete(start..end);
 
-        (new_table, text.to_string())
+        (new_table, deleted_text)
     }
 
-    fn delete_internal(&mut self, range: Range<usize>) -> (Self, String) {
+    /// 延迟删除（不立即获取文本，大文件优化）
+    pub fn delete_lazy(&self, range: Range<usize>) -> (Self, DeletionInfo) {
         let start = range.start;
-        let end = range.end.min(self.total_bytes);
+        let end = range.end.min(self.total_bytes());
 
         if start >= end {
-            return (self.clone(), String::new());
+            return (self.clone(), DeletionInfo::new(range, Vec::new()));
         }
 
-        // 1. 获取被删除的文本
-        let deleted_text = self.get_text_range(start..end);
+        // 收集被删除的Piece信息（不获取文本）
+        let mut deleted_pieces = Vec::new();
 
-        // 2. 查找删除范围
-        let (start_piece, start_offset) = self.find_piece_and_offset(start);
-        let (end_piece, end_offset) = self.find_piece_and_offset(end);
+        for (current_pos, entry) in self.pieces.iter_from(start) {
+            if current_pos >= end {
+                break;
+            }
 
-        // 3. 构建新的Piece链
-        let mut new_pieces = Vec::with_capacity(self.pieces.len());
+            let piece = entry.piece;
+            let piece_end = current_pos + piece.length;
+            let overlap_start = start.max(current_pos);
+            let overlap_end = end.min(piece_end);
 
-        // 删除开始之前的Piece
-        new_pieces.extend_from_slice(&self.pieces[..start_piece]);
+            if overlap_start < overlap_end {
+                let piece_start = piece.start + (overlap_start - current_pos);
+                let deleted = Piece {
+                    piece_type: piece.piece_type,
+                    start: piece_start,
+                    length: overlap_end - overlap_start,
+                };
+                // 完整删除的Piece直接使用缓存的换行数
+                let line_feeds = if deleted == piece {
+                    entry.line_feeds
+                } else {
+                    self.count_piece_line_feeds(&deleted)
+                };
+
+                deleted_pieces.push(DeletionPiece {
+                    piece_type: piece.piece_type,
+                    range: piece_start..piece_start + deleted.length,
+                    line_feeds,
+                });
+            }
+        }
 
-        // 处理开始Piece（如果不是从Piece开头删除）
-        if start_offset > 0 {
-            let piece = self.pieces[start_piece];
-            new_pieces.push(Piece {
-                piece_type: piece.piece_type,
-                start: piece.start,
-                length: start_offset,
-            });
+        // 执行删除操作
+        let (new_table, _) = self.delete_internal(start..end);
+        let info = DeletionInfo::new(start..end, deleted_pieces)
+            .with_source(self.original.clone(), self.additions.clone());
+
+        (new_table, info)
+    }
+
+    /// 恢复延迟删除的内容：把记录的Piece引用重新接回 byte_range.start 处
+    ///
+    /// 只拼接Piece（O(Piece数)，不复制文本）；缓冲区已更换（如保存后重新映射）时
+    /// 通过追加缓冲区引用删除时的缓冲区，同样不复制
+    pub fn restore_deletion(&self, info: &DeletionInfo) -> Result<Self, String> {
+        let (original, additions) = match info.source() {
+            Some(source) => (&source.original, &source.additions),
+            None => (&self.original, &self.additions),
+        };
+        for deleted in &info.pieces {
+            let buffer_len = match deleted.piece_type {
+                PieceType::Original => original.len(),
+                PieceType::Add => additions.len(),
+            };
+            if deleted.range.start > deleted.range.end || deleted.range.end > buffer_len {
+                return Err(format!("删除记录超出缓冲区范围: {:?}", deleted.range));
+            }
         }
 
-        // 处理结束Piece（如果不是到Piece结尾删除）
-        if end_offset < self.pieces[end_piece].length {
-            let piece = self.pieces[end_piece];
-            new_pieces.push(Piece {
-                piece_type: piece.piece_type,
-                start: piece.start + end_offset,
-                length: piece.length - end_offset,
-            });
+        let entries = info
+            .pieces
+            .iter()
+            .map(|deleted| {
+                let piece = match deleted.piece_type {
+                    PieceType::Original => Piece::original(deleted.range.clone()),
+                    PieceType::Add => Piece::add(deleted.range.clone()),
+                };
+                PieceEntry::new(piece, deleted.line_feeds)
+            })
+            .collect();
+        let slice = PieceSlice::new(original.clone(), additions.clone(), entries);
+
+        if slice.is_empty() {
+            return Ok(self.clone());
         }
+        let offset = info.byte_range.start;
+        if offset > self.total_bytes() {
+            return Err(format!("恢复位置超出范围: {} > {}", offset, self.total_bytes()));
+        }
+
+        Ok(self.splice_slice(offset, &slice))
+    }
 
-        // 删除结束之后的Piece
-        if end_piece + 1 < self.pieces.len() {
-            new_pieces.extend_from_slice(&self.pieces[end_piece + 1..]);
+    /// 在 offset 处接入一组Piece（内容已在本表的缓冲区中）
+    pub(super) fn splice_entries(&self, offset: usize, entries: Vec<PieceEntry>) -> Self {
+        let len: usize = entries.iter().map(|entry| entry.piece.length).sum();
+        let line_feeds: usize = entries.iter().map(|entry| entry.line_feeds).sum();
+
+        // 1. 依次接入Piece（每个O(log n)）
+        let mut pieces = self.pieces.clone();
+        let mut position = offset;
+        for entry in entries {
+            let length = entry.piece.length;
+            pieces.insert(position, entry, &|piece| self.count_piece_line_feeds(piece));
+            position += length;
         }
 
-        // 4. 创建新实例
+        // 2. 创建新实例
         let mut new_table = Self {
             original: self.original.clone(),
             additions: self.additions.clone(),
-            pieces: new_pieces,
-            piece_offsets: Vec::new(),
-            total_bytes: self.total_bytes - (end - start),
+            pieces,
             mode: self.mode,
             lines: self.lines.clone(),
+            anchors: self.anchors.clone(),
             suspend_auto_merge: self.suspend_auto_merge,
             last_merge_time: self.last_merge_time,
             edit_count_since_last_merge: self.edit_count_since_last_merge + 1,
+            last_edit_time: std::time::Instant::now(),
+            maintenance: self.maintenance.after_edit(offset),
         };
 
-        // 5. 更新累积偏移
-        new_table.update_piece_offsets();
-
-        // 6. 智能合并决策
+        // 3. 智能合并决策
         if new_table.should_merge_after_edit() {
             new_table.merge_pieces_smart();
         }
 
-        // 7. 更新行索引
-        if let Some(ref mut lines) = new_table.lines {
-            lines.handle_delete(start..end);
+        // 4. 更新行索引（逐行索引插入多行时需要内容：较小时读出，较大时留待重建）和锚点
+        if let Some(mut lines) = new_table.lines.take() {
+            if len <= DEFAULT_CHUNK_SIZE && !lines.is_sparse() && line_feeds > 0 {
+                lines.handle_insert_bytes(offset, &new_table.get_bytes_range(offset..offset + len));
+            } else {
+                lines.handle_insert_span(offset, len, line_feeds);
+            }
+            lines.set_source(&new_table);
+            new_table.lines = Some(lines);
         }
+        new_table.anchors.handle_insert(offset, len);
 
-        (new_table, deleted_text)
+        new_table
     }
+}
 
-    /// 延迟删除（不立即获取文本，大文件优化）
-    pub fn delete_lazy(&mut self, range: Range<usize>) -> (Self, DeletionInfo) {
-        let start = range.start;
-        let end = range.end.min(self.total_bytes);
+// ========== 批量编辑 ==========
 
-        if start >= end {
-            return (self.clone(), DeletionInfo::new(range, Vec::new()));
+/// 一处替换：(字节范围, 替换为的文本)
+pub type TextEdit = (Range<usize>, String);
+
+/// 一处按字节的替换（逆向编辑使用，被替换的内容可能不是合法UTF-8）
+pub type ByteEdit = (Range<usize>, Vec<u8>);
+
+impl PieceTable {
+    /// 一次应用多处替换（如全部替换），返回新表和用于撤销的逆向编辑
+    ///
+    /// 各范围按编辑前的文档偏移给出（调整到字符边界），必须按位置排序且互不重叠。
+    /// 插入的文本一次追加，Piece列表、行索引和锚点都只线性遍历一遍，
+    /// 不随编辑数量平方增长。逆向编辑同样有序，按字节保存被替换的内容，
+    /// 对新表调用 apply_edits 即可逐字节恢复（包括无效UTF-8）
+    pub fn apply_edits<T: AsRef<[u8]>>(&self, edits: Vec<(Range<usize>, T)>) -> Result<(Self, Vec<ByteEdit>), String> {
+        // 1. 校验范围
+        let total = self.total_bytes();
+        let mut ranges = Vec::with_capacity(edits.len());
+        let mut previous_end = 0;
+        for (range, _) in &edits {
+            if range.start > range.end || range.end > total {
+                return Err(format!("编辑范围无效: {:?}（文档长度 {}）", range, total));
+            }
+            let range = self.ensure_char_boundary(range.start)..self.ensure_char_boundary(range.end);
+            if range.start < previous_end {
+                return Err(format!("编辑未按位置排序或相互重叠: {:?}", range));
+            }
+            previous_end = range.end;
+            ranges.push(range);
+        }
+        if edits.iter().zip(&ranges).all(|((_, text), range)| text.as_ref().is_empty() && range.is_empty()) {
+            return Ok((self.clone(), Vec::new()));
         }
 
-        // 收集被删除的Piece信息（不获取文本）
-        let mut deleted_pieces = Vec::new();
-        let mut current_pos = 0;
+        // 2. 插入的文本一次追加到additions缓冲区
+        let joined: Vec<u8> = edits.iter().flat_map(|(_, text)| text.as_ref()).copied().collect();
+        let mut add_start = self.additions.append_bytes(&joined).start;
+
+        // 3. 线性构建新的Piece列表：保留的部分切取原Piece，替换处接入追加的文本
+        let mut cutter = SpanCutter::new(self);
+        let mut entries = Vec::with_capacity(self.pieces.len() + edits.len() * 2);
+        let mut replacements = Vec::with_capacity(edits.len());
+        let mut inverse = Vec::with_capacity(edits.len());
+        let mut kept_from = 0;
+        let mut new_offset = 0;
+        for ((_, text), range) in edits.iter().zip(&ranges) {
+            let text = text.as_ref();
+            new_offset += cutter.take(kept_from..range.start, Some(&mut entries)).0;
+            let removed_line_feeds = cutter.take(range.clone(), None).1;
+            kept_from = range.end;
+
+            if !text.is_empty() {
+                let piece = Piece::add(add_start..add_start + text.len());
+                entries.push(PieceEntry::new(piece, count_line_feeds(text)));
+                add_start += text.len();
+            }
+            inverse.push((new_offset..new_offset + text.len(), self.get_bytes_range(range.clone())));
+            new_offset += text.len();
+            replacements.push(Replacement {
+                range: range.clone(),
+                removed_line_feeds,
+                text,
+            });
+        }
+        cutter.take(kept_from..total, Some(&mut entries));
 
-        for piece in &self.pieces {
-            let piece_end = current_pos + piece.length;
+        // 4. 创建新实例（整体重建平衡树，O(n)）
+        let mut new_table = Self {
+            original: self.original.clone(),
+            additions: self.additions.clone(),
+            pieces: PieceTree::from_entries(entries),
+            mode: self.mode,
+            lines: self.lines.clone(),
+            anchors: self.anchors.clone(),
+            suspend_auto_merge: self.suspend_auto_merge,
+            last_merge_time: self.last_merge_time,
+            edit_count_since_last_merge: self.edit_count_since_last_merge + 1,
+            last_edit_time: std::time::Instant::now(),
+            maintenance: self.maintenance.after_edit(ranges[0].start),
+        };
+
+        // 5. 智能合并决策
+        if new_table.should_merge_after_edit() {
+            new_table.merge_pieces_smart();
+        }
+
+        // 6. 更新行索引（稀疏索引先用旧数据源）和锚点
+        if let Some(mut lines) = new_table.lines.take() {
+            lines.handle_replacements(&replacements);
+            lines.set_source(&new_table);
+            new_table.lines = Some(lines);
+        }
+        new_table.anchors.handle_replacements(&replacements);
+
+        Ok((new_table, inverse))
+    }
+}
+
+/// 按文档顺序切取Piece（批量编辑用，每个Piece只访问一次）
+struct SpanCutter<'a> {
+    table: &'a PieceTable,
+    pieces: PieceIter<'a>,
+    current: Option<(usize, PieceEntry)>,
+}
+
+impl<'a> SpanCutter<'a> {
+    fn new(table: &'a PieceTable) -> Self {
+        let mut pieces = table.pieces.iter();
+        let current = pieces.next();
+        Self { table, pieces, current }
+    }
+
+    /// 切取范围内的Piece放入 out（为None时丢弃），返回 (字节数, 换行数)
+    ///
+    /// 范围必须不早于上一次切取的范围
+    fn take(&mut self, range: Range<usize>, mut out: Option<&mut Vec<PieceEntry>>) -> (usize, usize) {
+        let mut line_feeds = 0;
+        while let Some((pos, entry)) = self.current {
+            if pos >= range.end {
+                break;
+            }
 
-            // 检查这个Piece是否与删除范围重叠
-            if piece_end > start && current_pos < end {
-                let overlap_start = start.max(current_pos);
-                let overlap_end = end.min(piece_end);
-                let overlap_len = overlap_end - overlap_start;
-
-                if overlap_len > 0 {
-                    let piece_start = piece.start + (overlap_start - current_pos);
-                    deleted_pieces.push(DeletionPiece {
-                        piece_type: piece.piece_type,
-                        range: piece_start..piece_start + overlap_len,
-                    });
+            let piece = entry.piece;
+            let piece_end = pos + piece.length;
+            let overlap_start = range.start.max(pos);
+            let overlap_end = range.end.min(piece_end);
+            if overlap_start < overlap_end {
+                let part = Piece {
+                    piece_type: piece.piece_type,
+                    start: piece.start + (overlap_start - pos),
+                    length: overlap_end - overlap_start,
+                };
+                // 完整切取的Piece直接使用缓存的换行数
+                let part_line_feeds = if part == piece {
+                    entry.line_feeds
+                } else {
+                    self.table.count_piece_line_feeds(&part)
+                };
+                line_feeds += part_line_feeds;
+                if let Some(out) = out.as_deref_mut() {
+                    out.push(PieceEntry::new(part, part_line_feeds));
                 }
             }
 
-            current_pos = piece_end;
-            if current_pos >= end {
+            if piece_end > range.end {
                 break;
             }
+            self.current = self.pieces.next();
         }
 
-        // 执行删除操作
-        let (new_table, _) = self.delete_internal(range.clone());
-
-        (new_table, DeletionInfo::new(range, deleted_pieces))
+        (range.len(), line_feeds)
     }
 }
 
 // ========== 文本获取 ==========
 
 impl PieceTable {
-    /// 获取指定范围的文本（核心API）
+    /// 获取指定范围的文本（核心API，无效UTF-8替换为U+FFFD，需要原始字节时使用get_bytes_range）
     pub fn get_text_range(&self, range: Range<usize>) -> String {
-        let start = range.start.min(self.total_bytes);
-        let end = range.end.min(self.total_bytes);
+        let start = range.start.min(self.total_bytes());
+        let end = range.end.min(self.total_bytes());
 
         if start >= end {
             return String::new();
         }
 
-        let mut result = String::with_capacity(end - start);
-        let mut current_pos = 0;
-
-        for (piece, piece_start) in self.pieces.iter().zip(&self.piece_offsets) {
-            if current_pos >= end {
-                break;
-            }
-
-            let piece_end = piece_start + piece.length;
-            if piece_end <= start {
-                current_pos = piece_end;
-                continue;
-            }
+        let mut bytes = Vec::with_capacity(end - start);
+        for slice in self.byte_slices(start..end) {
+            bytes.extend_from_slice(&slice);
+        }
 
-            // 计算重叠部分
-            let overlap_start = start.max(*piece_start);
-            let overlap_end = end.min(piece_end);
+        // UTF-8无效时使用损失转换
+        String::from_utf8(bytes)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+    }
 
-            if overlap_start < overlap_end {
-                let overlap_len = overlap_end - overlap_start;
-                let piece_offset = overlap_start - *piece_start;
+    /// 获取指定范围的原始字节（无损，不做UTF-8转换）
+    pub fn get_bytes_range(&self, range: Range<usize>) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(range.len().min(self.total_bytes()));
+        for slice in self.byte_slices(range) {
+            bytes.extend_from_slice(&slice);
+        }
+        bytes
+    }
 
-                match piece.piece_type {
-                    PieceType::Original => {
-                        let slice_start = piece.start + piece_offset;
-                        let slice_end = slice_start + overlap_len;
+    /// 查找范围内的无效UTF-8字节区间（可显示为转义），跨Piece边界的序列按整体判断
+    pub fn invalid_utf8_spans(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let start = range.start.min(self.total_bytes());
+        let mut spans = Vec::new();
+        // 跨切片的不完整序列，与下一个切片拼接后重新扫描
+        let mut carry: Vec<u8> = Vec::new();
+        let mut base = start;
+
+        for slice in self.byte_slices(range) {
+            for chunk in slice.chunks(DEFAULT_CHUNK_SIZE) {
+                let buffer = if carry.is_empty() {
+                    Cow::Borrowed(chunk)
+                } else {
+                    carry.extend_from_slice(chunk);
+                    Cow::Owned(std::mem::take(&mut carry))
+                };
 
-                        match &self.original {
-                            OriginalBuffer::InMemory(s) => {
-                                result.push_str(&s[slice_start..slice_end]);
-                            }
-                            #[cfg(not(target_arch = "wasm32"))]
-                            OriginalBuffer::MemoryMapped(mmap) => {
-                                match mmap.get_text(slice_start..slice_end) {
-                                    Ok(text) => result.push_str(&text),
-                                    Err(_) => {
-                                        // UTF-8无效，使用损失转换
-                                        let lossy = mmap.get_text_lossy(slice_start..slice_end);
-                                        result.push_str(&lossy);
-                                    }
-                                }
-                            }
-                            #[cfg(target_arch = "wasm32")]
-                            OriginalBuffer::Bytes(data) => {
-                                let slice = &data[slice_start..slice_end];
-                                if let Ok(text) = std::str::from_utf8(slice) {
-                                    result.push_str(text);
-                                } else {
-                                    result.push_str(&String::from_utf8_lossy(slice));
-                                }
-                            }
-                        }
-                    }
-                    PieceType::Add => {
-                        let slice_start = piece.start + piece_offset;
-                        let slice_end = slice_start + overlap_len;
-                        result.push_str(&self.additions[slice_start..slice_end]);
-                    }
-                }
+                let tail = Utf8Validator::scan_invalid(&buffer, base, &mut spans);
+                base += buffer.len() - tail;
+                carry = buffer[buffer.len() - tail..].to_vec();
             }
+        }
 
-            current_pos = piece_end;
+        // 文档末尾仍不完整的序列是无效字节
+        if !carry.is_empty() {
+            Utf8Validator::push_span(&mut spans, base..base + carry.len());
         }
 
-        result
+        spans
+    }
+
+    /// 全部内容是否为合法UTF-8
+    pub fn is_valid_utf8(&self) -> bool {
+        self.invalid_utf8_spans(0..self.total_bytes()).is_empty()
+    }
+
+    /// 把全部内容按原始字节写出（未编辑区域与原文件逐字节一致）
+    pub fn write_to<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for slice in self.byte_slices(0..self.total_bytes()) {
+            writer.write_all(&slice)?;
+        }
+        Ok(())
     }
 
     /// 获取全部文本（仅用于测试或小文件）
     #[cfg(test)]
     pub fn get_all_text(&self) -> String {
-        self.get_text_range(0..self.total_bytes)
+        self.get_text_range(0..self.total_bytes())
     }
 
     /// 获取指定行的文本
@@ -553,13 +828,31 @@ impl PieceTable {
             .map(|range| self.get_text_range(range))
     }
 
+    /// 按底层存储分段遍历范围内的字节（零拷贝）
+    pub fn byte_slices(&self, range: Range<usize>) -> ByteSlices<'_> {
+        let start = range.start.min(self.total_bytes());
+        let end = range.end.min(self.total_bytes());
+
+        ByteSlices {
+            table: self,
+            pieces: self.pieces.iter_from(start),
+            current: None,
+            range: start..end,
+        }
+    }
+
+    /// 在指定偏移创建双向文本游标
+    pub fn cursor_at(&self, offset: usize) -> TextCursor<'_> {
+        TextCursor::new(self, offset)
+    }
+
     /// 创建流式迭代器
-    pub fn iter_chunks(&self, chunk_size: usize) -> ChunkIter {
+    pub fn iter_chunks(&self, chunk_size: usize) -> ChunkIter<'_> {
         ChunkIter::new(self, chunk_size)
     }
 
     /// 使用默认块大小的流式迭代器
-    pub fn iter_chunks_default(&self) -> ChunkIter {
+    pub fn iter_chunks_default(&self) -> ChunkIter<'_> {
         ChunkIter::with_default_chunk_size(self)
     }
 }
@@ -567,76 +860,379 @@ impl PieceTable {
 // ========== Piece查找和索引 ==========
 
 impl PieceTable {
-    /// 查找字节偏移所在的Piece和在Piece内的偏移
-    fn find_piece_and_offset(&self, byte_offset: usize) -> (usize, usize) {
-        if byte_offset >= self.total_bytes {
-            return (self.pieces.len().saturating_sub(1), 0);
-        }
-
-        // 使用累积偏移进行二分查找
-        match self.piece_offsets.binary_search(&byte_offset) {
-            Ok(i) => (i, 0),
-            Err(i) => {
-                if i == 0 {
-                    (0, byte_offset)
-                } else {
-                    let piece_idx = i - 1;
-                    let offset_in_piece = byte_offset - self.piece_offsets[piece_idx];
-                    (piece_idx, offset_in_piece.min(self.pieces[piece_idx].length))
+    /// 获取Piece内指定范围的底层字节（按底层块分段，零拷贝）
+    fn piece_slices(&self, piece: &Piece, range: Range<usize>) -> PieceSlices<'_> {
+        let start = piece.start + range.start;
+        let end = piece.start + range.end.min(piece.length);
+
+        match piece.piece_type {
+            PieceType::Original => {
+                match &self.original {
+                    OriginalBuffer::InMemory(bytes) => PieceSlices::Original(Some(&bytes[start..end])),
+                    #[cfg(not(target_arch = "wasm32"))]
+                    OriginalBuffer::MemoryMapped(mmap) => PieceSlices::Mapped(mmap.slices(start..end)),
+                    #[cfg(target_arch = "wasm32")]
+                    OriginalBuffer::Bytes(data) => PieceSlices::Original(Some(&data[start..end])),
                 }
             }
+            PieceType::Add => PieceSlices::Add(self.additions.chunks(start..end)),
         }
     }
 
-    /// 更新累积偏移缓存
-    fn update_piece_offsets(&mut self) {
-        self.piece_offsets.clear();
-        let mut offset = 0;
+    /// 统计Piece内的换行数
+    fn count_piece_line_feeds(&self, piece: &Piece) -> usize {
+        self.piece_slices(piece, 0..piece.length)
+            .map(|slice| count_line_feeds(&slice))
+            .sum()
+    }
+
+    /// 获取字节偏移处的字节（O(log n)）
+    fn byte_at(&self, byte_offset: usize) -> Option<u8> {
+        let (entry, piece_start) = self.pieces.find(byte_offset)?;
+        let offset_in_piece = byte_offset - piece_start;
+
+        self.piece_slices(&entry.piece, offset_in_piece..offset_in_piece + 1)
+            .next()
+            .and_then(|slice| slice.first().copied())
+    }
+
+    /// 包含字节偏移的底层连续切片，返回 (切片起始偏移, 切片)
+    pub(super) fn slice_at(&self, byte_offset: usize) -> Option<(usize, ByteSlice<'_>)> {
+        let (entry, piece_start) = self.pieces.find(byte_offset)?;
+        let piece = &entry.piece;
+        let offset = piece.start + byte_offset - piece_start;
+        let range = piece.start..piece.start + piece.length;
+
+        // 只读取偏移所在的底层切片（窗口映射不从Piece开头逐个映射窗口）
+        let (start, slice) = match piece.piece_type {
+            PieceType::Original => match &self.original {
+                OriginalBuffer::InMemory(bytes) => (range.start, ByteSlice::Borrowed(&bytes[range])),
+                #[cfg(not(target_arch = "wasm32"))]
+                OriginalBuffer::MemoryMapped(mmap) => mmap.slice_containing(offset, range)?,
+                #[cfg(target_arch = "wasm32")]
+                OriginalBuffer::Bytes(data) => (range.start, ByteSlice::Borrowed(&data[range])),
+            },
+            PieceType::Add => self.additions.chunk_containing(offset, range)?,
+        };
+
+        Some((piece_start + start - piece.start, slice))
+    }
+
+    /// 按文档顺序收集所有Piece
+    fn collect_entries(&self) -> Vec<PieceEntry> {
+        self.pieces.iter().map(|(_, entry)| entry).collect()
+    }
+}
+
+/// Piece底层字节的分段迭代器（Add Piece可能跨越多个追加块，窗口映射按窗口分段）
+enum PieceSlices<'a> {
+    Original(Option<&'a [u8]>),
+    Mapped(MmapSlices<'a>),
+    Add(AddChunks<'a>),
+}
+
+impl<'a> Iterator for PieceSlices<'a> {
+    type Item = ByteSlice<'a>;
 
-        for piece in &self.pieces {
-            self.piece_offsets.push(offset);
-            offset += piece.length;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            PieceSlices::Original(slice) => slice.take().map(ByteSlice::Borrowed),
+            PieceSlices::Mapped(slices) => slices.next(),
+            PieceSlices::Add(chunks) => chunks.next(),
         }
+    }
+}
+
+/// 文档字节范围的分段迭代器，每项是底层缓冲区中的连续切片
+pub struct ByteSlices<'a> {
+    table: &'a PieceTable,
+    pieces: PieceIter<'a>,
+    current: Option<PieceSlices<'a>>,
+    range: Range<usize>,
+}
+
+impl<'a> Iterator for ByteSlices<'a> {
+    type Item = ByteSlice<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(slice) = self.current.as_mut().and_then(|slices| slices.next()) {
+                return Some(slice);
+            }
+            self.current = None;
+
+            let (piece_start, entry) = self.pieces.next()?;
+            if piece_start >= self.range.end {
+                return None;
+            }
 
-        self.total_bytes = offset;
+            let overlap_start = self.range.start.max(piece_start) - piece_start;
+            let overlap_end = self.range.end.min(piece_start + entry.piece.length) - piece_start;
+            if overlap_start < overlap_end {
+                self.current = Some(self.table.piece_slices(&entry.piece, overlap_start..overlap_end));
+            }
+        }
     }
 }
 
+/// 统计字节序列中的换行数
+fn count_line_feeds(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&b| b == b'\n').count()
+}
+
 // ========== 行索引管理 ==========
 
 impl PieceTable {
-    /// 获取或构建行索引
+    /// 获取或构建行索引（同步扫描整个文档，Restricted模式使用稀疏索引）
     pub fn get_or_build_lines(&mut self) -> &Lines {
-        if self.lines.is_none() {
-            self.lines = Some(Lines::new());
+        let is_dirty = self.lines.as_ref().map_or(true, |l| l.is_dirty());
+
+        if is_dirty {
+            self.lines = line_indexer::scan(self, None);
         }
 
-        let is_dirty = self.lines.as_ref().map(|l| l.is_dirty()).unwrap_or(false);
+        self.lines.get_or_insert_with(Lines::new)
+    }
 
-        if is_dirty {
-            // 对于大文件，可以延迟构建或增量构建
-            // 这里简化实现：全量构建
-            let text = self.get_text_range(0..self.total_bytes.min(10 * 1024 * 1024)); // 最多10MB
-            if let Some(ref mut lines) = self.lines {
-                lines.build_from_text(&text);
-            }
+    /// 在后台线程扫描整个文档建立行索引（适合内存映射的大文件）
+    pub fn spawn_line_indexing(&self) -> LineIndexTask {
+        LineIndexTask::spawn(self)
+    }
+
+    /// 安装后台扫描得到的行索引
+    ///
+    /// 索引必须由当前版本扫描得到，否则返回false（文档已被编辑，需要重新扫描）
+    pub fn install_line_index(&mut self, index: LineIndex) -> bool {
+        if !index.matches(self) {
+            return false;
+        }
+
+        self.lines = Some(index.into_lines());
+        true
+    }
+
+    /// 使用指定配置构建稀疏行索引（内存只与检查点数量相关）
+    pub fn build_sparse_lines(&mut self, config: SparseLineConfig) -> &Lines {
+        let mut scanner = SparseLineScanner::new(config);
+        for slice in self.byte_slices(0..self.total_bytes()) {
+            scanner.feed(&slice);
         }
 
-        self.lines.as_ref().unwrap()
+        let sparse = scanner.finish(Arc::new(self.without_lines()));
+        self.lines.insert(Lines::from_sparse(sparse))
     }
 
     /// 获取行索引（如果存在）
     pub fn lines(&self) -> Option<&Lines> {
         self.lines.as_ref()
     }
+
+    /// 内部Piece树（用于版本校验）
+    pub(super) fn piece_tree(&self) -> &PieceTree {
+        &self.pieces
+    }
+
+    /// 不含行索引的快照（稀疏索引的数据源，避免索引引用自身）
+    pub(super) fn without_lines(&self) -> Self {
+        Self {
+            lines: None,
+            ..self.clone()
+        }
+    }
+}
+
+// ========== 原始文件状态 ==========
+
+impl PieceTable {
+    /// 原始文件的磁盘状态（只检查内存映射的文件，不访问磁盘）
+    pub fn original_disk_status(&self) -> DiskStatus {
+        match &self.original {
+            #[cfg(not(target_arch = "wasm32"))]
+            OriginalBuffer::MemoryMapped(mmap) => mmap.disk_status(),
+            _ => DiskStatus::Unchanged,
+        }
+    }
+
+    /// 读取内存映射的原始文件失败的原因（失败时读到的内容被截断）
+    pub fn original_read_error(&self) -> Option<String> {
+        match &self.original {
+            #[cfg(not(target_arch = "wasm32"))]
+            OriginalBuffer::MemoryMapped(mmap) => mmap.read_error(),
+            _ => None,
+        }
+    }
+
+    /// 重新检查内存映射的原始文件
+    pub fn check_original(&self) -> DiskStatus {
+        match &self.original {
+            #[cfg(not(target_arch = "wasm32"))]
+            OriginalBuffer::MemoryMapped(mmap) => mmap.check_disk(),
+            _ => DiskStatus::Unchanged,
+        }
+    }
+
+    /// 在后台定时检查内存映射的原始文件（其他缓冲区返回None）
+    pub fn watch_original(&self, interval: std::time::Duration) -> Option<DiskWatch> {
+        match &self.original {
+            #[cfg(not(target_arch = "wasm32"))]
+            OriginalBuffer::MemoryMapped(mmap) => mmap.watch(interval),
+            _ => None,
+        }
+    }
+
+    /// 原始缓冲区中被引用的范围（按起点排序，相邻的已合并）
+    pub fn original_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        let mut pieces: Vec<Range<usize>> = self
+            .pieces
+            .iter()
+            .map(|(_, entry)| entry.piece)
+            .filter(|piece| piece.piece_type == PieceType::Original)
+            .map(|piece| piece.start..piece.start + piece.length)
+            .collect();
+        pieces.sort_by_key(|r| r.start);
+
+        for range in pieces {
+            match ranges.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => ranges.push(range),
+            }
+        }
+        ranges
+    }
+
+    /// 把引用的映射内容复制到私有内存，之后不再读取映射文件
+    pub fn detach_original(&self) -> Result<Self, String> {
+        Ok(Self::detach_originals(&[self])?.remove(0))
+    }
+
+    /// 为多个版本（如撤销历史中的所有快照）共同建立私有副本
+    ///
+    /// 共享同一映射的版本共享同一份副本，每个范围只复制一次
+    pub fn detach_originals(tables: &[&PieceTable]) -> Result<Vec<Self>, String> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut groups: Vec<(Arc<MmapBuffer>, Vec<Range<usize>>)> = Vec::new();
+            for table in tables {
+                let OriginalBuffer::MemoryMapped(mmap) = &table.original else {
+                    continue;
+                };
+                if mmap.is_private() {
+                    continue;
+                }
+                match groups.iter_mut().find(|(m, _)| Arc::ptr_eq(m, mmap)) {
+                    Some((_, ranges)) => ranges.extend(table.original_ranges()),
+                    None => groups.push((mmap.clone(), table.original_ranges())),
+                }
+            }
+
+            let mut detached = Vec::with_capacity(groups.len());
+            for (mmap, ranges) in &groups {
+                detached.push((mmap, Arc::new(mmap.to_private(ranges)?)));
+            }
+
+            Ok(tables
+                .iter()
+                .map(|table| match &table.original {
+                    OriginalBuffer::MemoryMapped(mmap) => {
+                        match detached.iter().find(|(m, _)| Arc::ptr_eq(m, mmap)) {
+                            Some((_, private)) => {
+                                table.with_original(OriginalBuffer::MemoryMapped(private.clone()))
+                            }
+                            None => (*table).clone(),
+                        }
+                    }
+                    _ => (*table).clone(),
+                })
+                .collect())
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        {
+            Ok(tables.iter().map(|table| (*table).clone()).collect())
+        }
+    }
+
+    /// 沿用另一份内容相同的文档的行索引、锚点和合并设置（保存后压缩使用）
+    pub(super) fn with_state_from(mut self, other: &PieceTable) -> Self {
+        self.suspend_auto_merge = other.suspend_auto_merge;
+        if let Some(mut lines) = other.lines.clone() {
+            lines.set_source(&self);
+            self.lines = Some(lines);
+        }
+        self.anchors = other.anchors.clone();
+        self
+    }
+
+    /// 锚点集合
+    pub(super) fn anchor_set(&self) -> &AnchorSet {
+        &self.anchors
+    }
+
+    pub(super) fn anchor_set_mut(&mut self) -> &mut AnchorSet {
+        &mut self.anchors
+    }
+
+    /// 原始缓冲区和追加缓冲区
+    pub(super) fn buffers(&self) -> (&OriginalBuffer, &AddBuffer) {
+        (&self.original, &self.additions)
+    }
+
+    /// 追加缓冲区（同一文档的所有快照共享）
+    pub fn add_buffer(&self) -> &AddBuffer {
+        &self.additions
+    }
+
+    /// 由缓冲区和Piece列表组成的表（不含行索引）
+    pub(super) fn from_parts(original: OriginalBuffer, additions: AddBuffer, entries: Vec<PieceEntry>) -> Self {
+        Self {
+            original,
+            additions,
+            pieces: PieceTree::from_entries(entries),
+            ..Self::new()
+        }
+    }
+
+    /// 内存映射的原始缓冲区
+    pub(super) fn mapped_original(&self) -> Option<&Arc<MmapBuffer>> {
+        match &self.original {
+            #[cfg(not(target_arch = "wasm32"))]
+            OriginalBuffer::MemoryMapped(mmap) => Some(mmap),
+            _ => None,
+        }
+    }
+
+    /// 替换原始缓冲区（内容必须与原来一致），稀疏行索引改用新的数据源
+    fn with_original(&self, original: OriginalBuffer) -> Self {
+        let mut table = Self {
+            original,
+            lines: None,
+            ..self.clone()
+        };
+
+        if let Some(mut lines) = self.lines.clone() {
+            lines.set_source(&table);
+            table.lines = Some(lines);
+        }
+        table
+    }
 }
 
 // ========== 合并策略 ==========
 
+/// 编辑中合并后，Piece数增长到上次剩余数量的多少倍时才再次合并
+const MERGE_GROWTH_FACTOR: usize = 2;
+
 impl PieceTable {
     /// 判断编辑后是否应该合并
+    ///
+    /// 合并后仍超过阈值的部分无法在编辑中减少，Piece数增长到
+    /// MERGE_GROWTH_FACTOR 倍之前不再合并（否则每次编辑都要O(n)重建），
+    /// 其余的整理交给 idle_tick
     fn should_merge_after_edit(&self) -> bool {
-        if self.suspend_auto_merge {
+        if self.suspend_auto_merge
+            || self.pieces.len() < self.maintenance.pieces_after_merge * MERGE_GROWTH_FACTOR
+        {
             return false;
         }
 
@@ -648,8 +1244,8 @@ impl PieceTable {
                 merge_on_edit && piece_count > threshold
             }
             BufferMode::MemoryMapped { merge_on_idle, .. } => {
-                let is_idle = self.edit_count_since_last_merge > 100;
-                merge_on_idle && is_idle && piece_count > threshold
+                // 平时交给 idle_tick 在空闲时整理；碎片远超阈值时才在编辑中兜底合并
+                merge_on_idle && piece_count > threshold * 2
             }
             BufferMode::Restricted { disable_merge, .. } => {
                 !disable_merge && piece_count > threshold * 2
@@ -659,96 +1255,98 @@ impl PieceTable {
 
     /// 智能合并实现
     fn merge_pieces_smart(&mut self) {
-        match self.mode {
+        let entries = self.collect_entries();
+
+        let merged = match self.mode {
             BufferMode::InMemory { .. } => {
-                self.merge_all_adjacent();
+                Self::merge_all_adjacent(entries)
             }
             BufferMode::MemoryMapped { max_merge_size, .. } => {
-                self.merge_incremental(max_merge_size);
+                Self::merge_incremental(entries, max_merge_size)
             }
             BufferMode::Restricted { .. } => {
-                self.merge_small_fragments_only(1024);
+                Self::merge_small_fragments_only(entries, 1024)
             }
+        };
+
+        // 合并后整体重建平衡树（O(n)）
+        self.pieces = PieceTree::from_entries(merged);
+        self.maintenance.merge.finish();
+
+        // 仍超过阈值说明碎片彼此不相邻，复制到新的连续区域
+        if self.pieces.len() > self.mode.merge_threshold() {
+            self.defragment();
         }
 
-        self.update_piece_offsets();
+        self.maintenance.pieces_after_merge = self.pieces.len();
         self.last_merge_time = std::time::Instant::now();
         self.edit_count_since_last_merge = 0;
     }
 
     /// 合并所有相邻的同类型Piece
-    fn merge_all_adjacent(&mut self) {
-        if self.pieces.len() <= 1 {
-            return;
-        }
-
-        let mut merged = Vec::with_capacity(self.pieces.len());
-        merged.push(self.pieces[0]);
+    fn merge_all_adjacent(entries: Vec<PieceEntry>) -> Vec<PieceEntry> {
+        let mut merged: Vec<PieceEntry> = Vec::with_capacity(entries.len());
 
-        for i in 1..self.pieces.len() {
-            let current = self.pieces[i];
-            let last = merged.last_mut().unwrap();
-
-            if self.can_merge_pieces(last, &current) {
-                last.length += current.length;
-            } else {
-                merged.push(current);
+        for current in entries {
+            match merged.last_mut() {
+                Some(last) if Self::can_merge_pieces(&last.piece, &current.piece) => {
+                    Self::absorb(last, &current);
+                }
+                _ => merged.push(current),
             }
         }
 
-        self.pieces = merged;
+        merged
     }
 
     /// 增量合并（控制每次合并的大小）
-    fn merge_incremental(&mut self, max_merge_size: usize) {
-        if self.pieces.len() <= 1 {
-            return;
-        }
-
+    fn merge_incremental(entries: Vec<PieceEntry>, max_merge_size: usize) -> Vec<PieceEntry> {
         let mut merged_bytes = 0;
-        let mut merged = Vec::with_capacity(self.pieces.len());
-        merged.push(self.pieces[0]);
-
-        for i in 1..self.pieces.len() {
-            let current = self.pieces[i];
-            let last = merged.last_mut().unwrap();
-
-            if merged_bytes < max_merge_size && self.can_merge_pieces(last, &current) {
-                last.length += current.length;
-                merged_bytes += current.length;
-            } else {
-                merged.push(current);
+        let mut merged: Vec<PieceEntry> = Vec::with_capacity(entries.len());
+
+        for current in entries {
+            match merged.last_mut() {
+                Some(last)
+                    if merged_bytes < max_merge_size
+                        && Self::can_merge_pieces(&last.piece, &current.piece) =>
+                {
+                    Self::absorb(last, &current);
+                    merged_bytes += current.piece.length;
+                }
+                _ => merged.push(current),
             }
         }
 
-        self.pieces = merged;
+        merged
     }
 
     /// 只合并小碎片
-    fn merge_small_fragments_only(&mut self, max_fragment_size: usize) {
-        if self.pieces.len() <= 1 {
-            return;
-        }
-
-        let mut merged = Vec::with_capacity(self.pieces.len());
-        merged.push(self.pieces[0]);
-
-        for i in 1..self.pieces.len() {
-            let current = self.pieces[i];
-            let last = merged.last_mut().unwrap();
-
-            if current.length <= max_fragment_size && self.can_merge_pieces(last, &current) {
-                last.length += current.length;
-            } else {
-                merged.push(current);
+    fn merge_small_fragments_only(entries: Vec<PieceEntry>, max_fragment_size: usize) -> Vec<PieceEntry> {
+        let mut merged: Vec<PieceEntry> = Vec::with_capacity(entries.len());
+
+        for current in entries {
+            match merged.last_mut() {
+                Some(last)
+                    if current.piece.length <= max_fragment_size
+                        && Self::can_merge_pieces(&last.piece, &current.piece) =>
+                {
+                    Self::absorb(last, &current);
+                }
+                _ => merged.push(current),
             }
         }
 
-        self.pieces = merged;
+        merged
+    }
+
+    /// 将后一个条目并入前一个条目
+    fn absorb(last: &mut PieceEntry, current: &PieceEntry) {
+        last.piece.length += current.piece.length;
+        last.line_feeds += current.line_feeds;
     }
 
     /// 检查两个Piece是否可以合并
-    fn can_merge_pieces(&self, a: &Piece, b: &Piece) -> bool {
+    fn can_merge_pieces(a: &Piece, b: &Piece) -> bool {
         if a.piece_type != b.piece_type {
             return false;
         }
@@ -767,6 +1365,91 @@ impl PieceTable {
     }
 }
 
+// ========== 碎片整理 ==========
+
+impl PieceTable {
+    /// 碎片整理：把相邻的小Piece（不论来源）复制到新的连续追加区域，替换为一个Piece
+    ///
+    /// 新Piece不超过模式的 max_merge_size，因此任意编辑模式下Piece数量都有上界；
+    /// 返回减少的Piece数
+    pub fn defragment(&mut self) -> usize {
+        let max_merge_size = self.mode.max_merge_size();
+        let mut removed = 0;
+        let mut cursor = 0;
+
+        loop {
+            let (step_removed, next_cursor) = self.defrag_step(cursor, max_merge_size);
+            removed += step_removed;
+            match next_cursor {
+                Some(next) => cursor = next,
+                None => break,
+            }
+        }
+
+        self.maintenance.defrag.finish();
+        removed
+    }
+
+    /// 从 cursor 开始整理下一段碎片（最多检查 PIECE_SCAN_STEP 个Piece）
+    ///
+    /// 返回 (减少的Piece数, 下一步的续点)，扫描到文档末尾时续点为None
+    fn defrag_step(&mut self, cursor: usize, max_merge_size: usize) -> (usize, Option<usize>) {
+        let mut run: Vec<PieceEntry> = Vec::new();
+        let mut run_start = 0;
+        let mut run_bytes = 0;
+        let mut next_cursor = None;
+
+        for (checked, (pos, entry)) in self.pieces.iter_from(cursor).enumerate() {
+            let length = entry.piece.length;
+            let is_fragment = length <= DEFRAG_FRAGMENT_SIZE;
+
+            if is_fragment && !run.is_empty() && run_bytes + length <= max_merge_size {
+                if run.len() >= PIECE_SCAN_STEP {
+                    // 本段已足够长，下一步从新Piece处继续（它可能还能与后续碎片整理）
+                    next_cursor = Some(run_start);
+                    break;
+                }
+                run.push(entry);
+                run_bytes += length;
+                continue;
+            }
+            if run.len() > 1 || checked >= PIECE_SCAN_STEP {
+                next_cursor = Some(pos);
+                break;
+            }
+
+            run.clear();
+            run_bytes = 0;
+            if is_fragment {
+                run_start = pos;
+                run.push(entry);
+                run_bytes = length;
+            }
+        }
+
+        if run.len() < 2 {
+            return (0, next_cursor);
+        }
+
+        // 复制内容到追加缓冲区（按字节复制，无效UTF-8原样保留）
+        let bytes = self.get_bytes_range(run_start..run_start + run_bytes);
+        let add_range = self.additions.append_bytes(&bytes);
+        let line_feeds = run.iter().map(|entry| entry.line_feeds).sum();
+        self.replace_range(run_start, PieceEntry::new(Piece::add(add_range), line_feeds));
+
+        (run.len() - 1, next_cursor)
+    }
+
+    /// 用一个等长的Piece替换从 start 开始的内容（内容不变）
+    fn replace_range(&mut self, start: usize, entry: PieceEntry) {
+        let mut pieces = self.pieces.clone();
+        let count = |piece: &Piece| self.count_piece_line_feeds(piece);
+        pieces.delete(start..start + entry.piece.length, &count);
+        pieces.insert(start, entry, &count);
+        self.pieces = pieces;
+    }
+}
+
 // ========== 大型操作防护 ==========
 
 impl PieceTable {
@@ -791,6 +1474,220 @@ impl PieceTable {
     }
 }
 
+// ========== 空闲整理 ==========
+
+/// 逐段整理时每步最多检查的Piece数（步与步之间检查截止时间）
+const PIECE_SCAN_STEP: usize = 256;
+
+/// 空闲整理后保留的映射窗口数（光标附近最近使用的窗口）
+const IDLE_KEEP_WINDOWS: usize = 1;
+
+impl PieceTable {
+    /// 距上次编辑的时间（宿主据此判断用户是否空闲）
+    pub fn idle_duration(&self) -> std::time::Duration {
+        self.last_edit_time.elapsed()
+    }
+
+    /// 距上次完成合并的时间
+    pub fn since_last_merge(&self) -> std::time::Duration {
+        self.last_merge_time.elapsed()
+    }
+
+    /// 是否还有待做的空闲整理（合并、碎片整理或补建行索引）
+    pub fn needs_maintenance(&self) -> bool {
+        let pieces_pending = (self.maintenance.merge.pending || self.maintenance.defrag.pending)
+            && self.idle_merge_policy().is_some();
+        pieces_pending || self.idle_index_pending()
+    }
+
+    /// 是否需要在空闲时补建行索引
+    ///
+    /// 已有的索引失效时重建；超大文件模式不主动建立索引，需要时由 get_or_build_lines 按需建立
+    fn idle_index_pending(&self) -> bool {
+        match &self.lines {
+            Some(lines) => lines.is_dirty(),
+            None => !matches!(self.mode, BufferMode::Restricted { .. }),
+        }
+    }
+
+    /// 在用户空闲时执行一小段后台整理，预算用完即返回
+    ///
+    /// 依次释放多余的映射窗口、合并相邻碎片、整理不相邻的小碎片、补建行索引。未完成的进度记录在表中，
+    /// 下次调用从断点继续；每个阶段至少前进一小步，预算很短时也能最终完成
+    pub fn idle_tick(&mut self, budget: std::time::Duration) -> IdleReport {
+        let deadline = std::time::Instant::now() + budget;
+
+        let trimmed_bytes = self
+            .mapped_original()
+            .map_or(0, |mmap| mmap.trim_windows(IDLE_KEEP_WINDOWS));
+        let merged_pieces = self.idle_merge(deadline);
+        let defragmented_pieces = self.idle_defrag(deadline);
+        let indexed_bytes = self.idle_index(deadline);
+
+        IdleReport {
+            merged_pieces,
+            defragmented_pieces,
+            indexed_bytes,
+            trimmed_bytes,
+            finished: !self.needs_maintenance(),
+        }
+    }
+
+    /// 空闲合并的策略：(单次合并最大字节数, 可并入的最大Piece长度)，不允许合并时返回None
+    fn idle_merge_policy(&self) -> Option<(usize, usize)> {
+        if self.suspend_auto_merge {
+            return None;
+        }
+
+        match self.mode {
+            BufferMode::InMemory { .. } => Some((usize::MAX, usize::MAX)),
+            BufferMode::MemoryMapped { merge_on_idle, max_merge_size, .. } => {
+                merge_on_idle.then_some((max_merge_size, usize::MAX))
+            }
+            // 与 merge_small_fragments_only 一致，只并入小碎片
+            BufferMode::Restricted { disable_merge, .. } => (!disable_merge).then_some((usize::MAX, 1024)),
+        }
+    }
+
+    /// 从续点开始合并相邻的可合并Piece，返回减少的Piece数
+    fn idle_merge(&mut self, deadline: std::time::Instant) -> usize {
+        let Some((max_merge_size, max_fragment_size)) = self.idle_merge_policy() else {
+            return 0;
+        };
+        if !self.maintenance.merge.pending {
+            return 0;
+        }
+
+        let mut merged_pieces = 0;
+        let mut merged_bytes = 0;
+
+        loop {
+            // 找出续点之后的下一段可合并的Piece（最多检查 PIECE_SCAN_STEP 个）
+            let mut run: Vec<PieceEntry> = Vec::new();
+            let mut run_start = 0;
+            let mut next_cursor = None;
+
+            for (checked, (pos, entry)) in self.pieces.iter_from(self.maintenance.merge.cursor).enumerate() {
+                let joins = merged_bytes < max_merge_size
+                    && entry.piece.length <= max_fragment_size
+                    && run.last().is_some_and(|last| Self::can_merge_pieces(&last.piece, &entry.piece));
+
+                if joins {
+                    merged_bytes += entry.piece.length;
+                    run.push(entry);
+                    continue;
+                }
+                if run.len() > 1 || checked >= PIECE_SCAN_STEP {
+                    next_cursor = Some(pos);
+                    break;
+                }
+
+                run_start = pos;
+                run = vec![entry];
+            }
+
+            if run.len() > 1 {
+                merged_pieces += run.len() - 1;
+                self.replace_run(run_start, &run);
+            }
+
+            match next_cursor {
+                Some(cursor) => self.maintenance.merge.cursor = cursor,
+                None => {
+                    // 完成一整遍扫描
+                    self.maintenance.merge.finish();
+                    self.last_merge_time = std::time::Instant::now();
+                    self.edit_count_since_last_merge = 0;
+                    break;
+                }
+            }
+
+            if merged_bytes >= max_merge_size || std::time::Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        merged_pieces
+    }
+
+    /// 用一个Piece替换从 start 开始的一段相邻Piece（内容不变）
+    fn replace_run(&mut self, start: usize, run: &[PieceEntry]) {
+        let mut merged = run[0];
+        for entry in &run[1..] {
+            Self::absorb(&mut merged, entry);
+        }
+        self.replace_range(start, merged);
+    }
+
+    /// 从续点开始整理不相邻的小碎片，返回减少的Piece数
+    ///
+    /// 等相邻合并扫描完成后才开始（合并不复制内容，代价更低）
+    fn idle_defrag(&mut self, deadline: std::time::Instant) -> usize {
+        if self.idle_merge_policy().is_none()
+            || self.maintenance.merge.pending
+            || !self.maintenance.defrag.pending
+        {
+            return 0;
+        }
+
+        let max_merge_size = self.mode.max_merge_size();
+        let mut removed = 0;
+
+        loop {
+            let (step_removed, next_cursor) = self.defrag_step(self.maintenance.defrag.cursor, max_merge_size);
+            removed += step_removed;
+            match next_cursor {
+                Some(cursor) => self.maintenance.defrag.cursor = cursor,
+                None => {
+                    self.maintenance.defrag.finish();
+                    break;
+                }
+            }
+
+            if std::time::Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        removed
+    }
+
+    /// 分步补建缺失或失效的行索引，返回本次扫描的字节数
+    fn idle_index(&mut self, deadline: std::time::Instant) -> usize {
+        if !self.idle_index_pending() {
+            return 0;
+        }
+
+        let total = self.total_bytes();
+        let mut scan = self.maintenance.take_index_scan().unwrap_or_else(|| IndexScan {
+            scanner: Scanner::for_mode(&self.mode),
+            position: 0,
+        });
+        let started_at = scan.position;
+
+        loop {
+            let end = (scan.position + DEFAULT_CHUNK_SIZE).min(total);
+            for slice in self.byte_slices(scan.position..end) {
+                scan.scanner.feed(&slice);
+            }
+            scan.position = end;
+
+            if end >= total || std::time::Instant::now() >= deadline {
+                break;
+            }
+        }
+
+        let indexed_bytes = scan.position - started_at;
+        if scan.position >= total {
+            self.lines = Some(scan.scanner.finish(self));
+        } else {
+            self.maintenance.store_index_scan(scan);
+        }
+
+        indexed_bytes
+    }
+}
+
 // ========== 默认实现 ==========
 
 impl Default for PieceTable {
@@ -859,5 +1756,391 @@ mod tests {
         // 拼接所有块应该等于完整文本
         let reconstructed: String = chunks.concat();
         assert_eq!(reconstructed, "Hello world! This is a test.");
+
+        // 块边界不截断多字节字符
+        let table = PieceTable::from_text("一二三四五");
+        let chunks: Vec<String> = table.iter_chunks(4).collect();
+        assert_eq!(chunks, vec!["一二", "三四", "五"]);
+    }
+
+    #[test]
+    fn test_random_edits_match_string_model() {
+        let mut table = PieceTable::from_text("The quick brown fox\njumps over\nthe lazy dog");
+        let mut model = String::from("The quick brown fox\njumps over\nthe lazy dog");
+
+        // 简单线性同余生成器，保证测试可复现
+        let mut seed: u64 = 42;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as usize) % bound.max(1)
+        };
+
+        for i in 0..2000 {
+            if model.is_empty() || next(3) > 0 {
+                let offset = next(model.len() + 1);
+                let text = if i % 7 == 0 { "\nab" } else { "xy" };
+                let (new_table, _) = table.insert_char_safe(offset, text);
+                table = new_table;
+                model.insert_str(offset, text);
+            } else {
+                let start = next(model.len());
+                let end = (start + next(8) + 1).min(model.len());
+                let (new_table, deleted) = table.delete_char_safe(start..end);
+                table = new_table;
+                assert_eq!(deleted, model[start..end]);
+                model.replace_range(start..end, "");
+            }
+        }
+
+        assert_eq!(table.get_all_text(), model);
+        assert_eq!(table.total_bytes(), model.len());
+        assert_eq!(table.line_feed_count(), model.matches('\n').count());
+    }
+
+    /// 按位置排序的替换依次作用在字节串上
+    fn apply_to_bytes<T: AsRef<[u8]>>(bytes: &[u8], edits: &[(Range<usize>, T)]) -> Vec<u8> {
+        let mut result = bytes.to_vec();
+        for (range, replacement) in edits.iter().rev() {
+            result.splice(range.clone(), replacement.as_ref().iter().copied());
+        }
+        result
+    }
+
+    #[test]
+    fn test_apply_edits_matches_sequential_model() {
+        let (mut table, _) = PieceTable::from_text(&"alpha beta\ngamma delta\n".repeat(30)).insert_char_safe(100, "inserted\n");
+        table.get_or_build_lines();
+        let text = table.get_all_text();
+
+        let mut seed: u64 = 3;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((seed >> 33) as usize) % bound.max(1)
+        };
+        let mut edits: Vec<TextEdit> = Vec::new();
+        let mut offset = 0;
+        while offset < text.len() {
+            let start = (offset + next(12)).min(text.len());
+            let end = (start + next(6)).min(text.len());
+            edits.push((start..end, ["", "Z", "two\nlines", "\n"][next(4)].to_string()));
+            offset = end + 1;
+        }
+        assert!(edits.len() > 50);
+
+        let (edited, inverse) = table.apply_edits(edits.clone()).unwrap();
+        let expected = String::from_utf8(apply_to_bytes(text.as_bytes(), &edits)).unwrap();
+        assert_eq!(edited.get_all_text(), expected);
+        assert_eq!(edited.line_feed_count(), expected.matches('\n').count());
+
+        // 行索引与重新构建的一致
+        let lines = edited.lines().unwrap();
+        assert!(!lines.is_dirty());
+        let mut rebuilt = Lines::new();
+        rebuilt.build_from_text(&expected);
+        assert_eq!(lines.total_lines(), rebuilt.total_lines());
+        for line in 0..rebuilt.total_lines() {
+            assert_eq!(lines.get_line_range(line), rebuilt.get_line_range(line));
+        }
+
+        // 逆向编辑恢复原文
+        let (restored, redo) = edited.apply_edits(inverse).unwrap();
+        assert_eq!(restored.get_all_text(), text);
+        assert_eq!(apply_to_bytes(text.as_bytes(), &redo), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_apply_edits_rejects_invalid_lists() {
+        let table = PieceTable::from_text("0123456789");
+
+        assert!(table.apply_edits(vec![(4..6, "a"), (2..3, "b")]).is_err());
+        assert!(table.apply_edits(vec![(2..5, "a"), (4..6, "b")]).is_err());
+        assert!(table.apply_edits(vec![(8..11, "a")]).is_err());
+
+        // 相邻和同一位置的插入是允许的
+        let edits = vec![(2..2, "a"), (2..2, "b"), (2..4, "c"), (4..4, "d")];
+        let (edited, inverse) = table.apply_edits(edits).unwrap();
+        assert_eq!(edited.get_all_text(), "01abcd456789");
+        assert_eq!(inverse, vec![(2..3, Vec::new()), (3..4, Vec::new()), (4..5, b"23".to_vec()), (5..6, Vec::new())]);
+
+        let (unchanged, inverse) = table.apply_edits(Vec::<TextEdit>::new()).unwrap();
+        assert_eq!(unchanged.get_all_text(), "0123456789");
+        assert!(inverse.is_empty());
+    }
+
+    #[test]
+    fn test_apply_edits_inverse_keeps_invalid_bytes() {
+        let original = b"a\xFFb=\xC3(x\xE2\x82=".repeat(20);
+        let table = PieceTable::from_bytes(&original);
+
+        // 全部替换 "=" 及其前一个字节（部分是无效字节）
+        let edits: Vec<TextEdit> = (0..original.len())
+            .filter(|&i| original[i] == b'=')
+            .map(|i| (i - 1..i + 1, "==".to_string()))
+            .collect();
+        let (replaced, inverse) = table.apply_edits(edits).unwrap();
+        assert_eq!(inverse[0], (2..4, b"b=".to_vec()));
+        assert_eq!(inverse[1].1, b"\x82=".to_vec());
+
+        // 撤销逐字节恢复
+        let (restored, _) = replaced.apply_edits(inverse).unwrap();
+        assert_eq!(restored.get_bytes_range(0..restored.total_bytes()), original);
+    }
+
+    #[test]
+    fn test_apply_edits_updates_sparse_lines() {
+        let mut table = PieceTable::from_text(&"row\n".repeat(400));
+        table.build_sparse_lines(SparseLineConfig { lines_per_checkpoint: 8, bytes_per_checkpoint: 64 });
+
+        // 每行的 "row" 替换为两行
+        let edits: Vec<TextEdit> = (0..400).map(|i| (i * 4..i * 4 + 3, "r\nw".to_string())).collect();
+        let (edited, _) = table.apply_edits(edits).unwrap();
+
+        let lines = edited.lines().unwrap();
+        assert!(lines.is_sparse());
+        assert_eq!(lines.total_lines(), 800);
+        assert_eq!(edited.get_line(0).as_deref(), Some("r"));
+        assert_eq!(edited.get_line(799).as_deref(), Some("w"));
+        assert_eq!(lines.find_line_by_offset(edited.total_bytes() - 1), Some(799));
+    }
+
+    /// 从后往前每隔一个字节插入一个字符：原始和追加的Piece交错，彼此都不相邻
+    fn scattered(original: &[u8]) -> PieceTable {
+        let mut table = PieceTable::from_bytes(original);
+        table.suspend_auto_merge();
+        for offset in (1..original.len()).rev().step_by(2) {
+            table = table.insert_char_safe(offset, if offset % 10 == 1 { "\n" } else { "+" }).0;
+        }
+        table
+    }
+
+    #[test]
+    fn test_defragment_collapses_interleaved_fragments() {
+        let mut original = b"ab\xFFcd".repeat(200);
+        original.push(b'\n');
+        let table = scattered(&original);
+        let bytes = table.get_bytes_range(0..table.total_bytes());
+        let line_feeds = table.line_feed_count();
+        assert!(table.piece_count() > 800);
+
+        // 相邻合并无能为力，碎片整理复制成一个新Piece（无效UTF-8原样保留）
+        let merged = PieceTable::merge_all_adjacent(table.collect_entries());
+        assert!(merged.len() > 800);
+
+        let mut defragmented = table.clone();
+        assert_eq!(defragmented.defragment(), table.piece_count() - 1);
+        assert_eq!(defragmented.piece_count(), 1);
+        assert_eq!(defragmented.get_bytes_range(0..defragmented.total_bytes()), bytes);
+        assert_eq!(defragmented.line_feed_count(), line_feeds);
+        // 旧快照不受影响
+        assert_eq!(table.get_bytes_range(0..table.total_bytes()), bytes);
+
+        // 新Piece不超过 max_merge_size
+        let mut bounded = table.clone();
+        bounded.mode = BufferMode::MemoryMapped {
+            merge_threshold: 2000,
+            merge_on_idle: true,
+            max_merge_size: 100,
+        };
+        bounded.defragment();
+        assert_eq!(bounded.piece_count(), bytes.len().div_ceil(100));
+        assert!(bounded.pieces.iter().all(|(_, entry)| entry.piece.length <= 100));
+        assert_eq!(bounded.get_bytes_range(0..bounded.total_bytes()), bytes);
+    }
+
+    #[test]
+    fn test_piece_count_stays_bounded_under_scattered_inserts() {
+        let text = "0123456789".repeat(1000);
+        let mut table = PieceTable::from_text(&text);
+        let mut model = text.clone();
+        let threshold = table.mode().merge_threshold();
+
+        // 从后往前插入，新Piece与前后都不相邻，只有碎片整理能减少数量
+        for (i, offset) in (1..text.len()).rev().step_by(3).enumerate() {
+            let insert = if i % 5 == 0 { "\n" } else { "x" };
+            table = table.insert_char_safe(offset, insert).0;
+            model.insert_str(offset, insert);
+            assert!(table.piece_count() <= threshold + 1);
+        }
+
+        assert_eq!(table.get_all_text(), model);
+        assert_eq!(table.line_feed_count(), model.matches('\n').count());
+    }
+
+    #[test]
+    fn test_merge_on_edit_waits_for_growth_after_unmergeable_pass() {
+        let mut table = PieceTable::from_text(&"o".repeat(100 * 1000));
+        table.mode = BufferMode::InMemory {
+            merge_threshold: 10,
+            merge_on_edit: true,
+        };
+        let insert = "a".repeat(5000);
+
+        // 从后往前插入大块：Piece都大于碎片大小且互不相邻，合并和整理都无法减少
+        let mut merged_at = Vec::new();
+        for offset in (1..=12).rev().map(|i| i * 7000) {
+            table = table.insert_char_safe(offset, &insert).0;
+            if table.edit_count_since_last_merge == 0 {
+                merged_at.push(table.piece_count());
+            }
+        }
+
+        // 第一次在超过阈值时合并，之后等数量翻倍才再次尝试
+        assert_eq!(merged_at, vec![11, 23]);
+        assert_eq!(table.piece_count(), 25);
+    }
+
+    #[test]
+    fn test_restricted_mode_does_not_index_when_idle() {
+        let mut table = PieceTable::from_text(&"huge log line\n".repeat(1000));
+        table.mode = BufferMode::for_file_size(crate::core::buffer::LARGE_FILE_THRESHOLD);
+        assert!(!table.needs_maintenance());
+        assert_eq!(table.idle_tick(std::time::Duration::from_secs(1)).indexed_bytes, 0);
+        assert!(table.lines().is_none());
+
+        // 需要时按需建立稀疏索引
+        table.get_or_build_lines();
+        assert!(table.lines().unwrap().is_sparse());
+        assert!(!table.needs_maintenance());
+    }
+
+    #[test]
+    fn test_restore_deletion_splices_pieces_back() {
+        let mut table = scattered(b"ab\xFFcd\nef".repeat(20).as_slice());
+        table.resume_auto_merge();
+        table.get_or_build_lines();
+        let bytes = table.get_bytes_range(0..table.total_bytes());
+        let lines_before = table.lines().unwrap().total_lines();
+
+        let (deleted, info) = table.delete_lazy(7..150);
+        assert_eq!(info.byte_range, 7..150);
+        assert_eq!(info.line_feeds(), count_line_feeds(&bytes[7..150]));
+
+        // 只拼接Piece引用，追加缓冲区没有增长
+        let restored = deleted.restore_deletion(&info).unwrap();
+        assert_eq!(restored.get_bytes_range(0..restored.total_bytes()), bytes);
+        assert_eq!(restored.additions.len(), table.additions.len());
+        assert_eq!(restored.line_feed_count(), table.line_feed_count());
+        assert_eq!(restored.lines().unwrap().total_lines(), lines_before);
+        assert_eq!(restored.get_line(3), table.get_line(3));
+
+        // 越界的恢复位置报错，空删除什么都不做
+        let (_, empty) = table.delete_lazy(500..600);
+        assert_eq!(table.restore_deletion(&empty).unwrap().total_bytes(), bytes.len());
+        let (short, _) = deleted.delete_lazy(0..deleted.total_bytes());
+        let (_, tail) = table.delete_lazy(100..110);
+        assert!(short.restore_deletion(&tail).is_err());
+    }
+
+    #[test]
+    fn test_restore_deletion_into_other_buffers_shares_content() {
+        let table = PieceTable::from_text("keep this, drop that\nand more");
+        let (deleted, info) = table.delete_lazy(10..20);
+
+        // 内容相同但缓冲区不同（如保存后重新打开）：引用删除时的缓冲区
+        let reopened = PieceTable::from_text(&deleted.get_all_text());
+        let restored = reopened.restore_deletion(&info).unwrap();
+        assert_eq!(restored.get_all_text(), table.get_all_text());
+        assert_eq!(restored.additions.len(), 10);
+    }
+
+    #[test]
+    fn test_insert_in_multibyte_char_snaps_to_boundary() {
+        let table = PieceTable::from_text("Hello 世界");
+
+        // 偏移7位于"世"中间，应回退到6
+        let (table, _) = table.insert_char_safe(7, "!");
+        assert_eq!(table.get_all_text(), "Hello !世界");
+    }
+
+    #[test]
+    fn test_snapshots_share_append_only_additions() {
+        let table = PieceTable::from_text("base");
+        let (first, _) = table.insert_char_safe(4, " one");
+        let (second, _) = first.insert_char_safe(8, " two");
+
+        // 每次插入只追加新文本，旧快照仍能读取自己的内容
+        assert!(first.additions.shares_storage_with(&second.additions));
+        assert_eq!(second.additions.len(), 8);
+        assert_eq!(first.get_all_text(), "base one");
+        assert_eq!(second.get_all_text(), "base one two");
+    }
+
+    #[test]
+    fn test_snapshot_is_send_sync_and_independent() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<PieceTable>();
+
+        let table = PieceTable::from_text("line 1\nline 2\n");
+        let snapshot = table.clone();
+        let (edited, _) = table.insert_char_safe(0, "// ");
+
+        let handle = std::thread::spawn(move || snapshot.get_all_text());
+        assert_eq!(handle.join().unwrap(), "line 1\nline 2\n");
+        assert_eq!(edited.get_all_text(), "// line 1\nline 2\n");
+    }
+
+    #[test]
+    fn test_line_index_stays_valid_after_edits() {
+        let mut table = PieceTable::from_text("first
+second
+third");
+        table.get_or_build_lines();
+
+        let (table, _) = table.insert_char_safe(6, "new
+");
+        let (table, _) = table.delete_char_safe(0..6);
+
+        let lines = table.lines().unwrap();
+        assert!(!lines.is_dirty());
+        assert_eq!(lines.total_lines(), 3);
+        assert_eq!(lines.find_line_by_offset(5), Some(1));
+        assert_eq!(table.get_line(0).as_deref(), Some("new"));
+        assert_eq!(table.get_line(2).as_deref(), Some("third"));
+    }
+
+    #[test]
+    fn test_sparse_line_index_follows_edits() {
+        let mut table = PieceTable::from_text(&"row\n".repeat(100));
+        table.build_sparse_lines(SparseLineConfig { lines_per_checkpoint: 8, bytes_per_checkpoint: 64 });
+
+        let (table, _) = table.insert_char_safe(40, "new\n");
+        let (table, _) = table.delete_char_safe(0..8);
+
+        let lines = table.lines().unwrap();
+        assert!(lines.is_sparse());
+        assert_eq!(lines.total_lines(), 99);
+        assert_eq!(table.get_line(8).as_deref(), Some("new"));
+        assert_eq!(lines.find_line_by_offset(table.total_bytes() - 1), Some(98));
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_preserved_byte_for_byte() {
+        // Latin-1 编码的 "café\n" 与一个截断的多字节序列
+        let original = b"caf\xE9\nline \xE4\xB8";
+        let table = PieceTable::from_bytes(original);
+
+        assert_eq!(table.get_bytes_range(0..table.total_bytes()), original);
+        assert_eq!(table.invalid_utf8_spans(0..table.total_bytes()), vec![3..4, 10..12]);
+        // 截断序列中的孤立字节各自是独立单元
+        assert!(table.is_char_boundary(11));
+        assert!(table.is_char_boundary(4));
+
+        // 编辑其他区域后，未触及的字节原样写出
+        let (table, _) = table.insert_char_safe(5, "new ");
+        let mut written = Vec::new();
+        table.write_to(&mut written).unwrap();
+        assert_eq!(written, b"caf\xE9\nnew line \xE4\xB8");
+    }
+
+    #[test]
+    fn test_invalid_span_scan_across_chunks() {
+        let text = format!("ab{}", "中".repeat(30_000));
+        let table = PieceTable::from_text(&text);
+        assert!(table.is_valid_utf8());
+
+        let mut bytes = text.into_bytes();
+        bytes.push(0xFF);
+        let table = PieceTable::from_bytes(&bytes);
+        assert_eq!(table.invalid_utf8_spans(0..bytes.len()), vec![bytes.len() - 1..bytes.len()]);
     }
 }
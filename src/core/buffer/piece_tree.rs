@@ -0,0 +1,779 @@
+// Piece 平衡树索引
+//
+// 职责：以 B 树组织 Piece 链，每个节点缓存子树的字节数与换行数，
+//       使定位、插入、删除、范围遍历都在 O(log n) 内完成；
+//       节点通过 Arc 共享，克隆整棵树为 O(1)，编辑只复制被修改的路径
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::core::buffer::piece_table::Piece;
+
+/// 节点最大子节点（条目）数
+const MAX_CHILDREN: usize = 16;
+/// 节点最小子节点（条目）数（根节点除外）
+const MIN_CHILDREN: usize = MAX_CHILDREN / 2;
+
+/// 子树汇总信息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PieceSummary {
+    /// 子树总字节数
+    pub bytes: usize,
+    /// 子树总换行符（'\n'）数
+    pub line_feeds: usize,
+    /// 子树 Piece 数量
+    pub pieces: usize,
+}
+
+impl PieceSummary {
+    fn add(&mut self, other: &PieceSummary) {
+        self.bytes += other.bytes;
+        self.line_feeds += other.line_feeds;
+        self.pieces += other.pieces;
+    }
+}
+
+/// 叶子中的 Piece 条目（附带该 Piece 的换行数）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PieceEntry {
+    pub piece: Piece,
+    pub line_feeds: usize,
+}
+
+impl PieceEntry {
+    pub fn new(piece: Piece, line_feeds: usize) -> Self {
+        Self { piece, line_feeds }
+    }
+
+    fn summary(&self) -> PieceSummary {
+        PieceSummary {
+            bytes: self.piece.length,
+            line_feeds: self.line_feeds,
+            pieces: 1,
+        }
+    }
+
+    /// 在 Piece 内偏移 `at` 处分裂为两段
+    ///
+    /// 只统计较短一侧的换行数，另一侧由总数相减得到
+    fn split(&self, at: usize, count_line_feeds: &dyn Fn(&Piece) -> usize) -> (PieceEntry, PieceEntry) {
+        debug_assert!(at > 0 && at < self.piece.length);
+
+        let left = Piece {
+            piece_type: self.piece.piece_type,
+            start: self.piece.start,
+            length: at,
+        };
+        let right = Piece {
+            piece_type: self.piece.piece_type,
+            start: self.piece.start + at,
+            length: self.piece.length - at,
+        };
+
+        if left.length <= right.length {
+            let left_feeds = count_line_feeds(&left);
+            (
+                PieceEntry::new(left, left_feeds),
+                PieceEntry::new(right, self.line_feeds - left_feeds),
+            )
+        } else {
+            let right_feeds = count_line_feeds(&right);
+            (
+                PieceEntry::new(left, self.line_feeds - right_feeds),
+                PieceEntry::new(right, right_feeds),
+            )
+        }
+    }
+}
+
+/// 树节点（写时复制：修改前通过 Arc::make_mut 取得独占副本）
+#[derive(Debug, Clone)]
+struct Node {
+    summary: PieceSummary,
+    kind: NodeKind,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    Leaf(Vec<PieceEntry>),
+    Internal(Vec<Arc<Node>>),
+}
+
+impl Node {
+    fn empty_leaf() -> Self {
+        Self {
+            summary: PieceSummary::default(),
+            kind: NodeKind::Leaf(Vec::new()),
+        }
+    }
+
+    fn leaf(entries: Vec<PieceEntry>) -> Self {
+        let mut node = Self {
+            summary: PieceSummary::default(),
+            kind: NodeKind::Leaf(entries),
+        };
+        node.update_summary();
+        node
+    }
+
+    fn internal(children: Vec<Arc<Node>>) -> Self {
+        let mut node = Self {
+            summary: PieceSummary::default(),
+            kind: NodeKind::Internal(children),
+        };
+        node.update_summary();
+        node
+    }
+
+    /// 直接子节点（条目）数量
+    fn len(&self) -> usize {
+        match &self.kind {
+            NodeKind::Leaf(entries) => entries.len(),
+            NodeKind::Internal(children) => children.len(),
+        }
+    }
+
+    fn is_underflow(&self) -> bool {
+        self.len() < MIN_CHILDREN
+    }
+
+    fn update_summary(&mut self) {
+        let mut summary = PieceSummary::default();
+        match &self.kind {
+            NodeKind::Leaf(entries) => {
+                for entry in entries {
+                    summary.add(&entry.summary());
+                }
+            }
+            NodeKind::Internal(children) => {
+                for child in children {
+                    summary.add(&child.summary);
+                }
+            }
+        }
+        self.summary = summary;
+    }
+
+    /// 溢出时将后半部分拆分为新的兄弟节点
+    fn split_if_overflow(&mut self) -> Option<Node> {
+        if self.len() <= MAX_CHILDREN {
+            return None;
+        }
+
+        let mid = self.len() / 2;
+        let sibling = match &mut self.kind {
+            NodeKind::Leaf(entries) => Node::leaf(entries.split_off(mid)),
+            NodeKind::Internal(children) => Node::internal(children.split_off(mid)),
+        };
+        self.update_summary();
+
+        Some(sibling)
+    }
+
+    /// 在子树偏移 `offset` 处插入条目，返回溢出拆分出的兄弟节点
+    fn insert(
+        &mut self,
+        offset: usize,
+        entry: PieceEntry,
+        count_line_feeds: &dyn Fn(&Piece) -> usize,
+    ) -> Option<Node> {
+        match &mut self.kind {
+            NodeKind::Leaf(entries) => {
+                let mut pos = 0;
+                let mut index = entries.len();
+
+                for (i, existing) in entries.iter().enumerate() {
+                    if offset == pos {
+                        index = i;
+                        break;
+                    }
+
+                    let end = pos + existing.piece.length;
+                    if offset < end {
+                        // 在Piece中间插入：分裂为三部分
+                        let (left, right) = existing.split(offset - pos, count_line_feeds);
+                        entries.splice(i..=i, [left, entry, right]);
+                        index = usize::MAX;
+                        break;
+                    }
+                    pos = end;
+                }
+
+                if index != usize::MAX {
+                    entries.insert(index, entry);
+                }
+            }
+            NodeKind::Internal(children) => {
+                let mut pos = 0;
+                let mut index = children.len() - 1;
+
+                for (i, child) in children.iter().enumerate() {
+                    if offset <= pos + child.summary.bytes {
+                        index = i;
+                        break;
+                    }
+                    pos += child.summary.bytes;
+                }
+
+                let child = Arc::make_mut(&mut children[index]);
+                if let Some(sibling) = child.insert(offset - pos, entry, count_line_feeds) {
+                    children.insert(index + 1, Arc::new(sibling));
+                }
+            }
+        }
+
+        self.update_summary();
+        self.split_if_overflow()
+    }
+
+    /// 删除子树内 `range` 覆盖的字节，返回溢出拆分出的兄弟节点
+    ///
+    /// 调用方保证 `range` 不会覆盖整个节点
+    fn delete(
+        &mut self,
+        range: Range<usize>,
+        count_line_feeds: &dyn Fn(&Piece) -> usize,
+    ) -> Option<Node> {
+        match &mut self.kind {
+            NodeKind::Leaf(entries) => {
+                let mut kept = Vec::with_capacity(entries.len() + 1);
+                let mut pos = 0;
+
+                for entry in entries.iter() {
+                    let start = pos;
+                    let end = pos + entry.piece.length;
+                    pos = end;
+
+                    if end <= range.start || start >= range.end {
+                        kept.push(*entry);
+                        continue;
+                    }
+
+                    // 保留删除范围之前的部分
+                    let mut rest = *entry;
+                    if start < range.start {
+                        let (left, right) = rest.split(range.start - start, count_line_feeds);
+                        kept.push(left);
+                        rest = right;
+                    }
+
+                    // 保留删除范围之后的部分
+                    if end > range.end {
+                        let cut = rest.piece.length - (end - range.end);
+                        if cut == 0 {
+                            kept.push(rest);
+                        } else {
+                            let (_, right) = rest.split(cut, count_line_feeds);
+                            kept.push(right);
+                        }
+                    }
+                }
+
+                *entries = kept;
+            }
+            NodeKind::Internal(children) => {
+                let mut kept = Vec::with_capacity(children.len() + 1);
+                let mut pos = 0;
+
+                for mut child in std::mem::take(children) {
+                    let start = pos;
+                    let end = pos + child.summary.bytes;
+                    pos = end;
+
+                    if end <= range.start || start >= range.end {
+                        kept.push(child);
+                        continue;
+                    }
+
+                    if range.start <= start && end <= range.end {
+                        // 子树被完整覆盖，直接丢弃
+                        continue;
+                    }
+
+                    let local = range.start.saturating_sub(start)..range.end.min(end) - start;
+                    let sibling = Arc::make_mut(&mut child).delete(local, count_line_feeds);
+                    kept.push(child);
+                    if let Some(sibling) = sibling {
+                        kept.push(Arc::new(sibling));
+                    }
+                }
+
+                fix_underflow(&mut kept);
+                *children = kept;
+            }
+        }
+
+        self.update_summary();
+        self.split_if_overflow()
+    }
+}
+
+/// 合并过小的相邻子节点，保持树的扇出
+fn fix_underflow(children: &mut Vec<Arc<Node>>) {
+    let mut i = 0;
+
+    while i < children.len() {
+        if children.len() == 1 || !children[i].is_underflow() {
+            i += 1;
+            continue;
+        }
+
+        // 与右侧兄弟合并（最后一个则与左侧合并）
+        let (left, right) = if i + 1 < children.len() { (i, i + 1) } else { (i - 1, i) };
+        let right_node = Arc::try_unwrap(children.remove(right)).unwrap_or_else(|shared| (*shared).clone());
+        let left_node = Arc::make_mut(&mut children[left]);
+
+        match (&mut left_node.kind, right_node.kind) {
+            (NodeKind::Leaf(a), NodeKind::Leaf(b)) => a.extend(b),
+            (NodeKind::Internal(a), NodeKind::Internal(b)) => a.extend(b),
+            _ => unreachable!("兄弟节点高度不一致"),
+        }
+        left_node.update_summary();
+
+        if let Some(sibling) = left_node.split_if_overflow() {
+            children.insert(left + 1, Arc::new(sibling));
+            i = left + 1;
+        } else {
+            i = left;
+        }
+    }
+}
+
+/// 将同层节点均匀分组，构建上一层
+fn build_level<T>(items: Vec<T>, make: fn(Vec<T>) -> Node) -> Vec<Arc<Node>> {
+    let groups = items.len().div_ceil(MAX_CHILDREN).max(1);
+    let base = items.len() / groups;
+    let extra = items.len() % groups;
+
+    let mut nodes = Vec::with_capacity(groups);
+    let mut iter = items.into_iter();
+    for g in 0..groups {
+        let size = base + usize::from(g < extra);
+        nodes.push(Arc::new(make(iter.by_ref().take(size).collect())));
+    }
+
+    nodes
+}
+
+/// Piece B 树（持久化结构，克隆为 O(1)）
+#[derive(Debug, Clone)]
+pub struct PieceTree {
+    root: Arc<Node>,
+}
+
+impl PieceTree {
+    /// 创建空树
+    pub fn new() -> Self {
+        Self { root: Arc::new(Node::empty_leaf()) }
+    }
+
+    /// 由有序条目批量构建（O(n)）
+    pub fn from_entries<I>(entries: I) -> Self
+    where
+        I: IntoIterator<Item = PieceEntry>,
+    {
+        let entries: Vec<PieceEntry> = entries
+            .into_iter()
+            .filter(|entry| !entry.piece.is_empty())
+            .collect();
+
+        if entries.is_empty() {
+            return Self::new();
+        }
+
+        let mut level = build_level(entries, Node::leaf);
+        while level.len() > 1 {
+            level = build_level(level, Node::internal);
+        }
+
+        Self { root: level.pop().unwrap() }
+    }
+
+    /// 整棵树的汇总信息
+    pub fn summary(&self) -> PieceSummary {
+        self.root.summary
+    }
+
+    /// 总字节数
+    pub fn total_bytes(&self) -> usize {
+        self.root.summary.bytes
+    }
+
+    /// 总换行数
+    pub fn line_feeds(&self) -> usize {
+        self.root.summary.line_feeds
+    }
+
+    /// Piece 数量
+    pub fn len(&self) -> usize {
+        self.root.summary.pieces
+    }
+
+    /// 是否为空
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 两棵树是否为同一版本（共享根节点）
+    pub fn ptr_eq(&self, other: &PieceTree) -> bool {
+        Arc::ptr_eq(&self.root, &other.root)
+    }
+
+    /// 查找包含字节偏移的 Piece，返回 (条目, Piece 起始偏移)
+    pub fn find(&self, offset: usize) -> Option<(PieceEntry, usize)> {
+        if offset >= self.total_bytes() {
+            return None;
+        }
+
+        let mut node = &self.root;
+        let mut pos = 0;
+
+        loop {
+            match &node.kind {
+                NodeKind::Internal(children) => {
+                    let mut next = &children[children.len() - 1];
+                    for child in children {
+                        if offset < pos + child.summary.bytes {
+                            next = child;
+                            break;
+                        }
+                        pos += child.summary.bytes;
+                    }
+                    node = next;
+                }
+                NodeKind::Leaf(entries) => {
+                    for entry in entries {
+                        if offset < pos + entry.piece.length {
+                            return Some((*entry, pos));
+                        }
+                        pos += entry.piece.length;
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// 查找包含字节偏移的 Piece，额外返回该 Piece 之前的换行数
+    pub fn find_with_line_feeds(&self, offset: usize) -> Option<(PieceEntry, usize, usize)> {
+        if offset >= self.total_bytes() {
+            return None;
+        }
+
+        let mut node = &self.root;
+        let (mut pos, mut feeds) = (0, 0);
+
+        loop {
+            match &node.kind {
+                NodeKind::Internal(children) => {
+                    let mut next = &children[children.len() - 1];
+                    for child in children {
+                        if offset < pos + child.summary.bytes {
+                            next = child;
+                            break;
+                        }
+                        pos += child.summary.bytes;
+                        feeds += child.summary.line_feeds;
+                    }
+                    node = next;
+                }
+                NodeKind::Leaf(entries) => {
+                    for entry in entries {
+                        if offset < pos + entry.piece.length {
+                            return Some((*entry, pos, feeds));
+                        }
+                        pos += entry.piece.length;
+                        feeds += entry.line_feeds;
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// 查找包含第 n 个（0-based）换行符的 Piece，返回 (条目, Piece 起始偏移, 之前的换行数)
+    pub fn find_line_feed(&self, n: usize) -> Option<(PieceEntry, usize, usize)> {
+        if n >= self.line_feeds() {
+            return None;
+        }
+
+        let mut node = &self.root;
+        let (mut pos, mut feeds) = (0, 0);
+
+        loop {
+            match &node.kind {
+                NodeKind::Internal(children) => {
+                    let mut next = &children[children.len() - 1];
+                    for child in children {
+                        if n < feeds + child.summary.line_feeds {
+                            next = child;
+                            break;
+                        }
+                        pos += child.summary.bytes;
+                        feeds += child.summary.line_feeds;
+                    }
+                    node = next;
+                }
+                NodeKind::Leaf(entries) => {
+                    for entry in entries {
+                        if n < feeds + entry.line_feeds {
+                            return Some((*entry, pos, feeds));
+                        }
+                        pos += entry.piece.length;
+                        feeds += entry.line_feeds;
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+
+    /// 在字节偏移处插入条目（偏移位于 Piece 中间时分裂该 Piece）
+    pub fn insert(
+        &mut self,
+        offset: usize,
+        entry: PieceEntry,
+        count_line_feeds: &dyn Fn(&Piece) -> usize,
+    ) {
+        if entry.piece.is_empty() {
+            return;
+        }
+
+        let offset = offset.min(self.total_bytes());
+        if let Some(sibling) = Arc::make_mut(&mut self.root).insert(offset, entry, count_line_feeds) {
+            let old_root = self.root.clone();
+            self.root = Arc::new(Node::internal(vec![old_root, Arc::new(sibling)]));
+        }
+    }
+
+    /// 删除字节范围
+    pub fn delete(&mut self, range: Range<usize>, count_line_feeds: &dyn Fn(&Piece) -> usize) {
+        let start = range.start.min(self.total_bytes());
+        let end = range.end.min(self.total_bytes());
+
+        if start >= end {
+            return;
+        }
+
+        if start == 0 && end == self.total_bytes() {
+            self.root = Arc::new(Node::empty_leaf());
+            return;
+        }
+
+        if let Some(sibling) = Arc::make_mut(&mut self.root).delete(start..end, count_line_feeds) {
+            let old_root = self.root.clone();
+            self.root = Arc::new(Node::internal(vec![old_root, Arc::new(sibling)]));
+        }
+
+        // 根节点只剩一个子节点时降低树高
+        while let NodeKind::Internal(children) = &self.root.kind {
+            if children.len() != 1 {
+                break;
+            }
+            self.root = children[0].clone();
+        }
+    }
+
+    /// 从头遍历所有 Piece
+    pub fn iter(&self) -> PieceIter<'_> {
+        self.iter_from(0)
+    }
+
+    /// 从包含字节偏移的 Piece 开始遍历
+    pub fn iter_from(&self, offset: usize) -> PieceIter<'_> {
+        let mut iter = PieceIter {
+            stack: Vec::new(),
+            leaf: &[],
+            index: 0,
+            offset: 0,
+        };
+
+        if offset >= self.total_bytes() {
+            iter.offset = self.total_bytes();
+            return iter;
+        }
+
+        let mut node = &self.root;
+        let mut pos = 0;
+
+        loop {
+            match &node.kind {
+                NodeKind::Internal(children) => {
+                    let mut index = children.len() - 1;
+                    for (i, child) in children.iter().enumerate() {
+                        if offset < pos + child.summary.bytes {
+                            index = i;
+                            break;
+                        }
+                        pos += child.summary.bytes;
+                    }
+                    iter.stack.push((children.as_slice(), index + 1));
+                    node = &children[index];
+                }
+                NodeKind::Leaf(entries) => {
+                    let mut index = entries.len();
+                    for (i, entry) in entries.iter().enumerate() {
+                        if offset < pos + entry.piece.length {
+                            index = i;
+                            break;
+                        }
+                        pos += entry.piece.length;
+                    }
+                    iter.leaf = entries.as_slice();
+                    iter.index = index;
+                    iter.offset = pos;
+                    return iter;
+                }
+            }
+        }
+    }
+}
+
+impl Default for PieceTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 按文档顺序遍历 Piece，产出 (Piece 起始偏移, 条目)
+pub struct PieceIter<'a> {
+    /// 内部节点路径：(子节点切片, 下一个待访问的子节点下标)
+    stack: Vec<(&'a [Arc<Node>], usize)>,
+    leaf: &'a [PieceEntry],
+    index: usize,
+    offset: usize,
+}
+
+impl<'a> PieceIter<'a> {
+    /// 下降到子树最左侧的叶子
+    fn descend(&mut self, mut node: &'a Node) {
+        loop {
+            match &node.kind {
+                NodeKind::Internal(children) => {
+                    self.stack.push((children.as_slice(), 1));
+                    node = &children[0];
+                }
+                NodeKind::Leaf(entries) => {
+                    self.leaf = entries.as_slice();
+                    self.index = 0;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for PieceIter<'a> {
+    type Item = (usize, PieceEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index >= self.leaf.len() {
+            let (children, next) = self.stack.last_mut()?;
+            if *next < children.len() {
+                let node = &children[*next];
+                *next += 1;
+                self.descend(node);
+            } else {
+                self.stack.pop();
+            }
+        }
+
+        let entry = self.leaf[self.index];
+        let start = self.offset;
+        self.index += 1;
+        self.offset += entry.piece.length;
+
+        Some((start, entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_feeds(_: &Piece) -> usize {
+        0
+    }
+
+    fn collect(tree: &PieceTree) -> Vec<(usize, usize)> {
+        tree.iter().map(|(_, e)| (e.piece.start, e.piece.length)).collect()
+    }
+
+    #[test]
+    fn test_insert_and_find() {
+        let mut tree = PieceTree::new();
+        for i in 0..200 {
+            let entry = PieceEntry::new(Piece::add(i..i + 1), 0);
+            tree.insert(i, entry, &no_feeds);
+        }
+
+        assert_eq!(tree.len(), 200);
+        assert_eq!(tree.total_bytes(), 200);
+        for i in 0..200 {
+            let (entry, start) = tree.find(i).unwrap();
+            assert_eq!(entry.piece.start, i);
+            assert_eq!(start, i);
+        }
+        assert!(tree.find(200).is_none());
+    }
+
+    #[test]
+    fn test_insert_splits_piece() {
+        let mut tree = PieceTree::from_entries([PieceEntry::new(Piece::original(0..10), 0)]);
+        tree.insert(4, PieceEntry::new(Piece::add(0..3), 0), &no_feeds);
+
+        assert_eq!(collect(&tree), vec![(0, 4), (0, 3), (4, 6)]);
+        assert_eq!(tree.total_bytes(), 13);
+    }
+
+    #[test]
+    fn test_delete_across_leaves() {
+        let entries = (0..500).map(|i| PieceEntry::new(Piece::add(i * 2..i * 2 + 2), 1));
+        let mut tree = PieceTree::from_entries(entries);
+
+        tree.delete(3..997, &|p| p.length / 2);
+
+        assert_eq!(tree.total_bytes(), 6);
+        assert_eq!(collect(&tree), vec![(0, 2), (2, 1), (997, 1), (998, 2)]);
+
+        tree.delete(0..6, &no_feeds);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_iter_from_offset() {
+        let entries = (0..100).map(|i| PieceEntry::new(Piece::add(i * 3..i * 3 + 3), 0));
+        let tree = PieceTree::from_entries(entries);
+
+        let (start, entry) = tree.iter_from(151).next().unwrap();
+        assert_eq!(start, 150);
+        assert_eq!(entry.piece.start, 150);
+        assert_eq!(tree.iter_from(151).count(), 50);
+        assert_eq!(tree.iter_from(300).count(), 0);
+    }
+
+    #[test]
+    fn test_summary_line_feeds() {
+        let mut tree = PieceTree::from_entries([PieceEntry::new(Piece::original(0..10), 4)]);
+        // 前3个字节含1个换行
+        tree.delete(0..3, &|p| if p.start == 0 { 1 } else { 3 });
+        assert_eq!(tree.line_feeds(), 3);
+    }
+
+    #[test]
+    fn test_clone_shares_untouched_nodes() {
+        let entries = (0..1000).map(|i| PieceEntry::new(Piece::add(i..i + 1), 0));
+        let original = PieceTree::from_entries(entries);
+        let mut edited = original.clone();
+
+        edited.insert(0, PieceEntry::new(Piece::original(0..5), 0), &no_feeds);
+
+        // 原树不受影响，且未修改的最右子树仍然共享
+        assert_eq!(original.total_bytes(), 1000);
+        assert_eq!(edited.total_bytes(), 1005);
+        let (NodeKind::Internal(a), NodeKind::Internal(b)) = (&original.root.kind, &edited.root.kind) else {
+            panic!("1000个条目应构成多层树");
+        };
+        assert!(Arc::ptr_eq(a.last().unwrap(), b.last().unwrap()));
+    }
+}
@@ -0,0 +1,499 @@
+// 位置换算
+//
+// 职责：字节偏移与 (行, 列) 之间的相互换算，
+//       列可以按字节、码点、UTF-16 码元、字素簇或显示单元格计算
+//
+// 越界规则（所有换算一致）：
+//   1. 偏移超出文档 → 文档末尾
+//   2. 行号超出最后一行 → 文档末尾
+//   3. 列超出行尾 → 行尾（换行符之前）
+//   4. 偏移或列落在单元内部（多字节字符、代理对、字素簇、宽字符、制表符）→ 该单元起点
+
+use std::ops::Range;
+
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete};
+use unicode_width::UnicodeWidthStr;
+
+use crate::core::buffer::{
+    piece_table::PieceTable,
+    lines::Lines,
+    text_cursor::{TextChunk, TextCursor},
+};
+
+/// 默认制表符宽度
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// 文本位置（0-based）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+/// 列的计量单位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnUnit {
+    /// 字节
+    Byte,
+    /// Unicode码点
+    Char,
+    /// UTF-16码元（LSP、JavaScript）
+    Utf16,
+    /// 扩展字素簇（用户感知的字符）
+    Grapheme,
+    /// 显示单元格（制表符对齐到 tab_width，CJK等宽字符占两格）
+    Display { tab_width: usize },
+}
+
+impl ColumnUnit {
+    /// 使用默认制表符宽度的显示列
+    pub fn display() -> Self {
+        ColumnUnit::Display { tab_width: DEFAULT_TAB_WIDTH }
+    }
+}
+
+/// 行内一段列范围对应的内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnSpan {
+    /// 行号
+    pub line: usize,
+    /// 覆盖的字节范围
+    pub bytes: Range<usize>,
+    /// bytes.start 所在的列
+    pub start_column: usize,
+    /// 行的总列数
+    pub line_width: usize,
+}
+
+/// 行内的最小换算单元：占用的字节数与列数
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    bytes: usize,
+    columns: usize,
+}
+
+// ========== 行查询 ==========
+
+impl PieceTable {
+    /// 可放置光标的行数（= 换行数 + 1，以换行结尾时含末尾空行）
+    pub fn line_count(&self) -> usize {
+        self.line_feed_count() + 1
+    }
+
+    /// 行内容的字节范围（不含换行符，行号超出时取最后一行）
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        let line = line.min(self.line_feed_count());
+        let start = self.line_start_offset(line);
+        let end = if line < self.line_feed_count() {
+            self.line_start_offset(line + 1) - 1
+        } else {
+            self.total_bytes()
+        };
+
+        start..end
+    }
+
+    /// 包含字节偏移的行号（偏移超出时取最后一行）
+    pub fn line_of_offset(&self, offset: usize) -> usize {
+        let offset = offset.min(self.total_bytes());
+
+        if let Some(line) = self.clean_lines().and_then(|lines| lines.find_line_by_offset(offset)) {
+            return line;
+        }
+
+        match self.piece_tree().find_with_line_feeds(offset) {
+            Some((_, piece_start, feeds_before)) => {
+                feeds_before + count_line_feeds(self, piece_start..offset)
+            }
+            None => self.line_feed_count(),
+        }
+    }
+
+    /// 行起始偏移（line 不超过换行数）
+    fn line_start_offset(&self, line: usize) -> usize {
+        if line == 0 {
+            return 0;
+        }
+
+        if let Some(range) = self.clean_lines().and_then(|lines| lines.get_line_range(line)) {
+            return range.start;
+        }
+
+        // 从Piece树定位第 line 个换行所在的Piece，再在Piece内扫描
+        let Some((entry, piece_start, feeds_before)) = self.piece_tree().find_line_feed(line - 1) else {
+            return self.total_bytes();
+        };
+        let mut remaining = line - feeds_before;
+        let mut pos = piece_start;
+        for slice in self.byte_slices(piece_start..piece_start + entry.piece.length) {
+            for (i, _) in slice.iter().enumerate().filter(|(_, &b)| b == b'\n') {
+                remaining -= 1;
+                if remaining == 0 {
+                    return pos + i + 1;
+                }
+            }
+            pos += slice.len();
+        }
+
+        self.total_bytes()
+    }
+
+    /// 可直接使用的行索引（已构建且未失效）
+    fn clean_lines(&self) -> Option<&Lines> {
+        self.lines().filter(|lines| !lines.is_dirty())
+    }
+}
+
+// ========== 位置换算 ==========
+
+impl PieceTable {
+    /// 字节偏移 → (行, 列)
+    pub fn offset_to_position(&self, offset: usize, unit: ColumnUnit) -> Position {
+        let offset = offset.min(self.total_bytes());
+        let line = self.line_of_offset(offset);
+        let range = self.line_range(line);
+        let target = offset - range.start;
+
+        let mut bytes = 0;
+        let mut column = 0;
+        for step in LineSteps::new(self, range, unit) {
+            if bytes + step.bytes > target {
+                break;
+            }
+            bytes += step.bytes;
+            column += step.columns;
+        }
+
+        Position { line, column }
+    }
+
+    /// (行, 列) → 字节偏移
+    pub fn position_to_offset(&self, position: Position, unit: ColumnUnit) -> usize {
+        if position.line > self.line_feed_count() {
+            return self.total_bytes();
+        }
+
+        let range = self.line_range(position.line);
+        let mut offset = range.start;
+        let mut column = 0;
+        for step in LineSteps::new(self, range, unit) {
+            if column + step.columns > position.column {
+                break;
+            }
+            column += step.columns;
+            offset += step.bytes;
+        }
+
+        offset
+    }
+
+    /// 行内列范围对应的字节范围（矩形选区使用）
+    ///
+    /// 与列范围部分重叠的单元（制表符、宽字符）整体计入；列范围为空时是插入点，
+    /// 落在单元内部取单元起点；超出行尾的部分截到行尾。行号超出时取最后一行
+    pub fn line_column_span(&self, line: usize, columns: Range<usize>, unit: ColumnUnit) -> ColumnSpan {
+        let range = self.line_range(line);
+        let mut start = None;
+        let mut end = range.start;
+        let mut offset = range.start;
+        let mut column = 0;
+        for step in LineSteps::new(self, range.clone(), unit) {
+            if start.is_none() && column + step.columns > columns.start {
+                start = Some((offset, column));
+            }
+            offset += step.bytes;
+            if column < columns.end {
+                end = offset;
+            }
+            column += step.columns;
+        }
+
+        let (start, start_column) = start.unwrap_or((range.end, column));
+        let end = if columns.is_empty() { start } else { end.max(start) };
+        ColumnSpan {
+            line: line.min(self.line_feed_count()),
+            bytes: start..end,
+            start_column,
+            line_width: column,
+        }
+    }
+
+    /// 按规则修正位置（越界或落在单元内部时）
+    pub fn clamp_position(&self, position: Position, unit: ColumnUnit) -> Position {
+        self.offset_to_position(self.position_to_offset(position, unit), unit)
+    }
+}
+
+/// 统计范围内的换行数
+fn count_line_feeds(table: &PieceTable, range: Range<usize>) -> usize {
+    table
+        .byte_slices(range)
+        .map(|slice| slice.iter().filter(|&&b| b == b'\n').count())
+        .sum()
+}
+
+/// 逐个产生一行的换算单元（无效UTF-8的每个字节单独成为一个单元）
+///
+/// 通过文本游标按切片读取，不复制整行；调用方找到目标后即可停止
+struct LineSteps<'a> {
+    table: &'a PieceTable,
+    cursor: TextCursor<'a>,
+    /// 行尾偏移
+    end: usize,
+    unit: ColumnUnit,
+    /// 当前文本块及其起始偏移
+    chunk: Option<TextChunk<'a>>,
+    chunk_start: usize,
+    /// 下一个单元的起始偏移
+    offset: usize,
+    /// 当前合法文本区间的起点与字素簇游标（偏移相对于区间起点）
+    run: Option<(usize, GraphemeCursor)>,
+    display_column: usize,
+}
+
+impl<'a> LineSteps<'a> {
+    fn new(table: &'a PieceTable, range: Range<usize>, unit: ColumnUnit) -> Self {
+        Self {
+            table,
+            cursor: TextCursor::new(table, range.start),
+            end: range.end,
+            unit,
+            chunk: None,
+            chunk_start: range.start,
+            offset: range.start,
+            run: None,
+            display_column: 0,
+        }
+    }
+
+    /// 读取下一个文本块（不超过行尾，行后的内容不做验证）
+    fn next_chunk(&mut self) -> bool {
+        let start = self.cursor.offset();
+        if start >= self.end {
+            return false;
+        }
+
+        self.chunk = self.cursor.next_text_chunk_within(self.end);
+        self.chunk_start = start;
+        self.chunk.is_some()
+    }
+
+    /// 从当前偏移开始的字素簇的结束偏移（可以跨越多个文本块）
+    fn grapheme_end(&mut self) -> usize {
+        let (run_start, mut graphemes) = self
+            .run
+            .take()
+            .unwrap_or_else(|| (self.offset, GraphemeCursor::new(0, self.end - self.offset, true)));
+
+        loop {
+            let Some(text) = self.chunk.as_ref().and_then(TextChunk::as_str) else {
+                return self.offset;
+            };
+            match graphemes.next_boundary(text, self.chunk_start - run_start) {
+                Ok(Some(boundary)) => {
+                    self.run = Some((run_start, graphemes));
+                    return run_start + boundary;
+                }
+                Ok(None) => return self.end,
+                Err(GraphemeIncomplete::NextChunk) => {
+                    let chunk_end = self.chunk_start + text.len();
+                    // 合法文本区间在行尾或无效字节处结束
+                    if !self.next_chunk() || self.chunk.as_ref().and_then(TextChunk::as_str).is_none() {
+                        return chunk_end;
+                    }
+                }
+                Err(GraphemeIncomplete::PreContext(context_end)) => {
+                    let mut back = TextCursor::new(self.table, run_start + context_end);
+                    match back.prev_text_chunk() {
+                        Some(chunk) => {
+                            let skip = run_start.saturating_sub(back.offset());
+                            let text = chunk.as_str().map_or("", |text| &text[skip..]);
+                            graphemes.provide_context(text, back.offset() + skip - run_start);
+                        }
+                        None => return self.offset + 1,
+                    }
+                }
+                Err(_) => return self.offset + 1,
+            }
+        }
+    }
+
+    /// 字素簇占用的列数（跨文本块时单独读取其内容）
+    fn grapheme_columns(&self, range: Range<usize>, chunk_start: usize) -> usize {
+        let ColumnUnit::Display { tab_width } = self.unit else {
+            return 1;
+        };
+
+        let columns = |grapheme: &str| {
+            if grapheme == "\t" {
+                let tab_width = tab_width.max(1);
+                tab_width - self.display_column % tab_width
+            } else {
+                grapheme.width()
+            }
+        };
+        match self.chunk.as_ref().and_then(TextChunk::as_str) {
+            Some(text) if self.chunk_start == chunk_start && range.end <= chunk_start + text.len() => {
+                columns(&text[range.start - chunk_start..range.end - chunk_start])
+            }
+            _ => columns(&String::from_utf8_lossy(&self.table.get_bytes_range(range))),
+        }
+    }
+}
+
+impl Iterator for LineSteps<'_> {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Step> {
+        if self.offset >= self.end {
+            return None;
+        }
+        while self.offset >= self.chunk_start + self.chunk.as_ref().map_or(0, TextChunk::len) {
+            if !self.next_chunk() {
+                return None;
+            }
+        }
+
+        let chunk_start = self.chunk_start;
+        let step = match self.chunk.as_ref()?.as_str() {
+            None => {
+                self.run = None;
+                Step { bytes: 1, columns: 1 }
+            }
+            Some(text) => {
+                let c = text[self.offset - chunk_start..].chars().next()?;
+                match self.unit {
+                    ColumnUnit::Byte => Step { bytes: c.len_utf8(), columns: c.len_utf8() },
+                    ColumnUnit::Char => Step { bytes: c.len_utf8(), columns: 1 },
+                    ColumnUnit::Utf16 => Step { bytes: c.len_utf8(), columns: c.len_utf16() },
+                    ColumnUnit::Grapheme | ColumnUnit::Display { .. } => {
+                        let end = self.grapheme_end().max(self.offset + c.len_utf8());
+                        Step {
+                            bytes: end - self.offset,
+                            columns: self.grapheme_columns(self.offset..end, chunk_start),
+                        }
+                    }
+                }
+            }
+        };
+
+        self.offset += step.bytes;
+        self.display_column += step.columns;
+        Some(step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISPLAY: ColumnUnit = ColumnUnit::Display { tab_width: 4 };
+
+    #[test]
+    fn test_columns_in_each_unit() {
+        // "a😀中e\u{301}" : a(1) 😀(4) 中(3) e+组合重音(3)
+        let table = PieceTable::from_text("first\na😀中e\u{301}x\n");
+        let offset = 6 + 1 + 4 + 3 + 3; // 'x' 的偏移
+
+        assert_eq!(table.offset_to_position(offset, ColumnUnit::Byte), Position::new(1, 11));
+        assert_eq!(table.offset_to_position(offset, ColumnUnit::Char), Position::new(1, 5));
+        assert_eq!(table.offset_to_position(offset, ColumnUnit::Utf16), Position::new(1, 6));
+        assert_eq!(table.offset_to_position(offset, ColumnUnit::Grapheme), Position::new(1, 4));
+        assert_eq!(table.offset_to_position(offset, DISPLAY), Position::new(1, 6));
+
+        for unit in [ColumnUnit::Byte, ColumnUnit::Char, ColumnUnit::Utf16, ColumnUnit::Grapheme, DISPLAY] {
+            let position = table.offset_to_position(offset, unit);
+            assert_eq!(table.position_to_offset(position, unit), offset);
+        }
+    }
+
+    #[test]
+    fn test_tabs_align_to_tab_stops() {
+        let table = PieceTable::from_text("a\tb\t\tc");
+
+        assert_eq!(table.offset_to_position(2, DISPLAY), Position::new(0, 4));
+        assert_eq!(table.offset_to_position(4, DISPLAY), Position::new(0, 8));
+        assert_eq!(table.offset_to_position(5, DISPLAY), Position::new(0, 12));
+        // 列落在制表符内部时取制表符起点
+        assert_eq!(table.position_to_offset(Position::new(0, 6), DISPLAY), 3);
+    }
+
+    #[test]
+    fn test_clamping_is_consistent() {
+        let table = PieceTable::from_text("中文\nab\n");
+
+        // 偏移落在多字节字符内部 → 字符起点
+        assert_eq!(table.offset_to_position(4, ColumnUnit::Char), Position::new(0, 1));
+        // 列落在宽字符内部 → 字符起点
+        assert_eq!(table.position_to_offset(Position::new(0, 3), DISPLAY), 3);
+        // 列超出行尾 → 行尾
+        assert_eq!(table.position_to_offset(Position::new(1, 99), ColumnUnit::Char), 9);
+        // 行超出 → 文档末尾
+        assert_eq!(table.position_to_offset(Position::new(7, 0), ColumnUnit::Char), 10);
+        // 偏移超出 → 文档末尾（末尾空行）
+        assert_eq!(table.offset_to_position(99, ColumnUnit::Char), Position::new(2, 0));
+        assert_eq!(table.clamp_position(Position::new(0, 3), DISPLAY), Position::new(0, 2));
+    }
+
+    #[test]
+    fn test_column_span_covers_partial_cells() {
+        let table = PieceTable::from_text("a\tb\n中文x\nab");
+
+        // 制表符占 1..4 列，部分选中时整体计入
+        let span = table.line_column_span(0, 2..5, DISPLAY);
+        assert_eq!((span.bytes, span.start_column, span.line_width), (1..3, 1, 5));
+        // 宽字符占两列
+        let span = table.line_column_span(1, 1..4, DISPLAY);
+        assert_eq!((span.bytes, span.start_column), (4..10, 0));
+        // 插入点落在宽字符内部 → 字符起点
+        assert_eq!(table.line_column_span(1, 3..3, DISPLAY).bytes, 7..7);
+        // 超出行尾
+        let span = table.line_column_span(2, 1..9, DISPLAY);
+        assert_eq!((span.bytes, span.line_width), (13..14, 2));
+        let span = table.line_column_span(2, 5..9, DISPLAY);
+        assert_eq!((span.bytes, span.start_column), (14..14, 2));
+    }
+
+    #[test]
+    fn test_graphemes_split_across_pieces() {
+        // 零宽连接符和组合重音由单独的插入产生，字素簇跨越多个Piece
+        let table = PieceTable::from_text("ab👨👩\tc");
+        let (table, _) = table.insert_char_safe(6, "\u{200D}");
+        let (table, _) = table.insert_char_safe(table.total_bytes(), "\u{301}");
+        assert_eq!(table.get_all_text(), "ab👨\u{200D}👩\tc\u{301}");
+
+        let unit = ColumnUnit::Grapheme;
+        assert_eq!(table.offset_to_position(8, unit), Position::new(0, 2));
+        assert_eq!(table.offset_to_position(14, unit), Position::new(0, 4));
+        assert_eq!(table.offset_to_position(15, unit), Position::new(0, 4));
+        assert_eq!(table.position_to_offset(Position::new(0, 3), unit), 13);
+        assert_eq!(table.position_to_offset(Position::new(0, 9), unit), 17);
+
+        let span = table.line_column_span(0, 2..3, unit);
+        assert_eq!((span.bytes, span.line_width), (2..13, 5));
+    }
+
+    #[test]
+    fn test_line_lookup_with_and_without_index() {
+        let text = "line\n".repeat(300);
+        let mut table = PieceTable::from_text(&text);
+        let (edited, _) = table.insert_char_safe(500, "x\ny");
+
+        let lines_of = |table: &PieceTable| -> Vec<usize> {
+            (0..=table.total_bytes()).step_by(13).map(|o| table.line_of_offset(o)).collect()
+        };
+
+        let without_index = lines_of(&edited);
+        table.get_or_build_lines();
+        let (edited, _) = table.insert_char_safe(500, "x\ny");
+        assert_eq!(lines_of(&edited), without_index);
+        assert_eq!(edited.line_range(101), 502..507);
+        assert_eq!(edited.line_count(), 302);
+    }
+}
@@ -0,0 +1,313 @@
+// 正则搜索
+//
+// 职责：在 PieceTable 上按块运行正则表达式，不加载全文，返回带捕获组的匹配
+//
+// 每块读入时向后多读 最大匹配长度 + 上下文 字节（重叠窗口）：起点在块内、
+// 长度不超过最大匹配长度的匹配都完整落在窗口中；前后各保留几个字节的上下文，
+// 使 ^、$、\b 等断言在块边缘也能看到相邻字符。
+// 超过最大匹配长度的匹配可能被截断到窗口末尾
+
+use std::ops::Range;
+
+use regex::bytes::{CaptureLocations, Regex};
+
+use crate::core::buffer::{PieceTable, SearchDirection, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_MATCH_LEN};
+
+/// 断言需要的上下文字节数（一个UTF-8字符最长4字节）
+const CONTEXT_BYTES: usize = 4;
+
+/// 一个正则匹配
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexMatch {
+    /// 整个匹配的字节范围
+    pub range: Range<usize>,
+    /// 各捕获组的字节范围（下标0为整个匹配，未参与匹配的组为None）
+    pub groups: Vec<Option<Range<usize>>>,
+}
+
+impl RegexMatch {
+    /// 第 index 个捕获组的字节范围
+    pub fn group(&self, index: usize) -> Option<Range<usize>> {
+        self.groups.get(index).cloned().flatten()
+    }
+
+    fn from_locations(locations: &CaptureLocations, offset: usize) -> Self {
+        let groups: Vec<Option<Range<usize>>> = (0..locations.len())
+            .map(|i| locations.get(i).map(|(start, end)| offset + start..offset + end))
+            .collect();
+
+        Self {
+            range: groups[0].clone().unwrap_or(offset..offset),
+            groups,
+        }
+    }
+}
+
+/// 正则匹配的惰性迭代器
+///
+/// 正向依次返回互不重叠的匹配（同 Regex::captures_iter），
+/// 反向依次返回终点不晚于上一个匹配起点的最后一个匹配
+pub struct RegexMatches<'a> {
+    table: &'a PieceTable,
+    regex: Regex,
+    locations: CaptureLocations,
+    direction: SearchDirection,
+    max_match_len: usize,
+    chunk_size: usize,
+    /// 已读入的内容
+    bytes: Vec<u8>,
+    /// bytes 在文档中的起始偏移
+    start: usize,
+    /// 正向：下一次搜索的起点
+    cursor: usize,
+    /// 正向：上一个匹配的终点（紧随其后的空匹配不返回）
+    last_end: Option<usize>,
+    /// 反向：匹配终点的上限
+    bound: usize,
+    /// 反向：下一块中匹配起点的上限（不含）
+    start_limit: usize,
+    /// 反向：当前块中尚未返回的匹配（按位置排序，从末尾取出）
+    pending: Vec<RegexMatch>,
+    finished: bool,
+}
+
+impl<'a> RegexMatches<'a> {
+    fn new(table: &'a PieceTable, regex: &Regex, from: usize, direction: SearchDirection) -> Self {
+        let from = from.min(table.total_bytes());
+
+        Self {
+            table,
+            regex: regex.clone(),
+            locations: regex.capture_locations(),
+            direction,
+            max_match_len: DEFAULT_MAX_MATCH_LEN,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            bytes: Vec::new(),
+            start: 0,
+            cursor: from,
+            last_end: None,
+            bound: from,
+            start_limit: from + 1,
+            pending: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// 设置单个匹配的最大长度（默认 DEFAULT_MAX_MATCH_LEN）
+    pub fn with_max_match_len(mut self, max_match_len: usize) -> Self {
+        self.max_match_len = max_match_len;
+        self
+    }
+
+    /// 设置每次读入的块大小（默认 DEFAULT_CHUNK_SIZE）
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.bytes.len()
+    }
+
+    /// 读入范围内的内容（已在窗口中时不重复读取）
+    fn load(&mut self, range: Range<usize>) {
+        if self.start <= range.start && range.end <= self.end() {
+            return;
+        }
+        self.bytes.clear();
+        for slice in self.table.byte_slices(range.clone()) {
+            self.bytes.extend_from_slice(&slice);
+        }
+        self.start = range.start;
+    }
+
+    /// 在窗口中的 position 处搜索，返回匹配的文档范围
+    fn search_at(&mut self, position: usize) -> Option<Range<usize>> {
+        if position > self.bytes.len() {
+            return None;
+        }
+        self.regex
+            .captures_read_at(&mut self.locations, &self.bytes, position)
+            .map(|m| self.start + m.start()..self.start + m.end())
+    }
+
+    fn next_forward(&mut self) -> Option<RegexMatch> {
+        let total = self.table.total_bytes();
+        let lookahead = self.max_match_len + CONTEXT_BYTES;
+
+        while self.cursor <= total {
+            // 窗口中从 cursor 起至少要有一个最大长度的匹配和上下文
+            if self.cursor.saturating_sub(CONTEXT_BYTES) < self.start
+                || self.cursor > self.end()
+                || (self.end() < total && self.end() <= self.cursor + lookahead)
+            {
+                let read_start = self.cursor.saturating_sub(CONTEXT_BYTES);
+                let read_end = (self.cursor + self.chunk_size + lookahead).min(total);
+                self.load(read_start..read_end);
+            }
+            // 起点在 limit 之前的匹配已完整读入
+            let limit = if self.end() == total { total + 1 } else { self.end() - lookahead };
+
+            let Some(found) = self.search_at(self.cursor - self.start).filter(|m| m.start < limit) else {
+                self.cursor = limit;
+                continue;
+            };
+
+            // 紧随上一个匹配的空匹配跳过
+            if found.is_empty() && self.last_end == Some(found.start) {
+                self.cursor = found.start + 1;
+                continue;
+            }
+
+            self.cursor = found.end;
+            self.last_end = Some(found.end);
+            return Some(RegexMatch::from_locations(&self.locations, self.start));
+        }
+
+        None
+    }
+
+    /// 反向：每块只扫描一次，收集其中的全部匹配后从后往前返回
+    ///
+    /// 块内从前往后的匹配链中，后一个匹配的起点不早于前一个的终点，
+    /// 因此依次取出的就是终点不晚于上一个匹配起点的最后一个匹配
+    fn next_backward(&mut self) -> Option<RegexMatch> {
+        let total = self.table.total_bytes();
+        let lookahead = self.max_match_len + CONTEXT_BYTES;
+
+        loop {
+            if let Some(found) = self.pending.pop() {
+                self.bound = found.range.start;
+                return Some(found);
+            }
+            if self.start_limit == 0 {
+                return None;
+            }
+
+            // 检查起点在 [scan_start, start_limit) 内的匹配
+            let scan_start = self.start_limit.saturating_sub(self.chunk_size);
+            let read_end = (self.start_limit + lookahead).min(total);
+            self.load(scan_start.saturating_sub(CONTEXT_BYTES)..read_end);
+
+            let mut position = scan_start - self.start;
+            while let Some(found) = self.search_at(position) {
+                if found.start >= self.start_limit {
+                    break;
+                }
+                if found.end <= self.bound {
+                    self.pending.push(RegexMatch::from_locations(&self.locations, self.start));
+                    position = found.end - self.start + usize::from(found.is_empty());
+                } else {
+                    // 越过上限的匹配之内可能还有更短的匹配
+                    position = found.start - self.start + 1;
+                }
+            }
+            self.start_limit = scan_start;
+        }
+    }
+}
+
+impl<'a> Iterator for RegexMatches<'a> {
+    type Item = RegexMatch;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let found = match self.direction {
+            SearchDirection::Forward => self.next_forward(),
+            SearchDirection::Backward => self.next_backward(),
+        };
+        self.finished = found.is_none();
+        found
+    }
+}
+
+// ========== 正则查找 ==========
+
+impl PieceTable {
+    /// 从 from 开始按方向运行正则，返回惰性的匹配迭代器
+    ///
+    /// 正向返回起点不早于 from 的匹配，反向返回终点不晚于 from 的匹配；
+    /// 查找全部匹配即从0开始正向迭代
+    pub fn search_regex(&self, regex: &Regex, from: usize, direction: SearchDirection) -> RegexMatches<'_> {
+        RegexMatches::new(self, regex, from, direction)
+    }
+
+    /// 从 from 开始按方向查找第一个正则匹配
+    pub fn find_regex(&self, regex: &Regex, from: usize, direction: SearchDirection) -> Option<RegexMatch> {
+        self.search_regex(regex, from, direction).next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(matches: impl Iterator<Item = RegexMatch>) -> Vec<Range<usize>> {
+        matches.map(|m| m.range).collect()
+    }
+
+    #[test]
+    fn test_matches_across_chunks_agree_with_whole_text() {
+        let mut table = PieceTable::from_text(&"key=value; other = 42;\n".repeat(40));
+        for offset in (3..table.total_bytes()).step_by(23).rev() {
+            table = table.insert_char_safe(offset, "_id").0;
+        }
+        let text = table.get_all_text();
+        let regex = Regex::new(r"(?m)^(\w+)\s*=\s*(\w+)|\b\d+\b").unwrap();
+        let expected: Vec<_> = regex.find_iter(text.as_bytes()).map(|m| m.range()).collect();
+        assert_eq!(expected.len(), 80);
+
+        for chunk_size in [1, 5, 64, DEFAULT_CHUNK_SIZE] {
+            let forward = table
+                .search_regex(&regex, 0, SearchDirection::Forward)
+                .with_chunk_size(chunk_size)
+                .with_max_match_len(32);
+            assert_eq!(ranges(forward), expected, "chunk size {}", chunk_size);
+
+            let backward = table
+                .search_regex(&regex, table.total_bytes(), SearchDirection::Backward)
+                .with_chunk_size(chunk_size)
+                .with_max_match_len(32);
+            let mut backward = ranges(backward);
+            backward.reverse();
+            assert_eq!(backward, expected, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_capture_groups() {
+        let table = PieceTable::from_text("name: zedit\nversion: 0.1\n");
+        let regex = Regex::new(r"(?m)^(\w+): (?:(\d+)\.(\d+)|(\w+))$").unwrap();
+
+        let matches: Vec<_> = table.search_regex(&regex, 0, SearchDirection::Forward).collect();
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].group(1), Some(0..4));
+        assert_eq!(matches[0].group(2), None);
+        assert_eq!(matches[0].group(4), Some(6..11));
+        assert_eq!(matches[1].range, 12..24);
+        assert_eq!(matches[1].group(2), Some(21..22));
+        assert_eq!(matches[1].group(3), Some(23..24));
+        assert_eq!(matches[1].group(9), None);
+    }
+
+    #[test]
+    fn test_find_next_and_prev_from_offset() {
+        let table = PieceTable::from_text("a1 b22 c333 d4444");
+        let regex = Regex::new(r"\d+").unwrap();
+
+        assert_eq!(table.find_regex(&regex, 4, SearchDirection::Forward).unwrap().range, 4..6);
+        assert_eq!(table.find_regex(&regex, 5, SearchDirection::Forward).unwrap().range, 5..6);
+        assert_eq!(table.find_regex(&regex, 11, SearchDirection::Backward).unwrap().range, 8..11);
+        // 越过起始位置的匹配不算
+        assert_eq!(table.find_regex(&regex, 10, SearchDirection::Backward).unwrap().range, 4..6);
+        assert_eq!(table.find_regex(&regex, 1, SearchDirection::Backward), None);
+
+        // 空匹配不会死循环
+        let empty = Regex::new(r"\b").unwrap();
+        assert_eq!(table.search_regex(&empty, 0, SearchDirection::Forward).count(), 8);
+        assert_eq!(table.search_regex(&empty, table.total_bytes(), SearchDirection::Backward).count(), 8);
+    }
+}
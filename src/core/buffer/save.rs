@@ -0,0 +1,373 @@
+// 原子保存
+//
+// 职责：把 PieceTable 流式写入同目录的临时文件，同步到磁盘后重命名覆盖目标，
+//       任何时刻目标路径上都是完整的旧文件或完整的新文件；
+//       保存后以新文件为原始缓冲区压缩文档
+//
+// 目标正是内存映射的原始文件时，重命名只替换目录项，
+// 旧inode在映射释放前保持有效，仍指向旧映射的Piece不受影响（Unix）。
+// 不会原地截断或改写被映射的文件
+
+use std::fs::{self, File, OpenOptions};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::core::buffer::{
+    piece_table::PieceTable,
+    mmap::FileIdentity,
+    DEFAULT_CHUNK_SIZE,
+};
+
+/// 符号链接的最大解析深度
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// 同一进程内临时文件名的序号
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// 保存选项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveOptions {
+    /// 重命名前同步文件内容，重命名后同步所在目录
+    pub fsync: bool,
+    /// 沿用目标文件的权限
+    pub preserve_permissions: bool,
+    /// 目标是符号链接时写入链接指向的文件（否则用普通文件替换链接本身）
+    pub follow_symlinks: bool,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        Self {
+            fsync: true,
+            preserve_permissions: true,
+            follow_symlinks: true,
+        }
+    }
+}
+
+/// 保存的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedFile {
+    /// 实际写入的文件路径（符号链接解析后）
+    pub path: PathBuf,
+    /// 写入的文件的身份（重命名前记录，用于确认路径上仍是这个文件）
+    pub identity: FileIdentity,
+}
+
+// ========== 保存 ==========
+
+impl PieceTable {
+    /// 原子保存到指定路径，返回实际写入的文件路径（符号链接解析后）和文件身份
+    ///
+    /// 按Piece流式写出，内存占用与文档大小无关
+    pub fn save_to(&self, path: &Path, options: SaveOptions) -> Result<SavedFile, String> {
+        let target = if options.follow_symlinks {
+            resolve_symlinks(path)?
+        } else {
+            path.to_path_buf()
+        };
+        let existing = fs::metadata(&target).ok();
+        // 重命名之后路径指向新文件，必须先判断
+        let replaces_mapping = self.is_mapped_from(&target);
+
+        #[cfg(not(unix))]
+        if replaces_mapping {
+            // 非Unix平台无法替换仍被映射的文件
+            return Err("目标文件仍被内存映射，请先调用 detach_original".to_string());
+        }
+
+        let (temp_path, file) = create_temp_file(&target)?;
+        let result = self.write_temp_file(file, existing.as_ref(), options)
+            .and_then(|identity| {
+                fs::rename(&temp_path, &target).map_err(|e| format!("重命名临时文件失败: {}", e))?;
+                Ok(identity)
+            });
+        let identity = match result {
+            Ok(identity) => identity,
+            Err(e) => {
+                let _ = fs::remove_file(&temp_path);
+                return Err(e);
+            }
+        };
+
+        if options.fsync {
+            sync_parent_dir(&target)?;
+        }
+        if let Some(mmap) = self.mapped_original().filter(|_| replaces_mapping) {
+            mmap.mark_replaced();
+        }
+
+        Ok(SavedFile { path: target, identity })
+    }
+
+    /// 写入临时文件，返回写完后的文件身份（重命名不改变inode、大小和修改时间）
+    fn write_temp_file(&self, file: File, existing: Option<&fs::Metadata>, options: SaveOptions) -> Result<FileIdentity, String> {
+        let mut writer = BufWriter::with_capacity(DEFAULT_CHUNK_SIZE, file);
+        self.write_to(&mut writer).map_err(|e| format!("写入临时文件失败: {}", e))?;
+        let file = writer.into_inner().map_err(|e| format!("写入临时文件失败: {}", e.error()))?;
+
+        if options.preserve_permissions {
+            if let Some(metadata) = existing {
+                file.set_permissions(metadata.permissions())
+                    .map_err(|e| format!("设置文件权限失败: {}", e))?;
+            }
+        }
+        if options.fsync {
+            file.sync_all().map_err(|e| format!("同步文件失败: {}", e))?;
+        }
+
+        let metadata = file.metadata().map_err(|e| format!("无法获取文件信息: {}", e))?;
+        Ok(FileIdentity::of(&metadata))
+    }
+
+    /// 原始缓冲区是否映射自该路径上的文件
+    fn is_mapped_from(&self, target: &Path) -> bool {
+        let Some(mmap) = self.mapped_original() else {
+            return false;
+        };
+
+        match (mmap.identity().and_then(|id| id.inode), fs::metadata(target).ok()) {
+            (Some(inode), Some(metadata)) => FileIdentity::of(&metadata).inode == Some(inode),
+            _ => mmap.path().and_then(|p| fs::canonicalize(p).ok()) == fs::canonicalize(target).ok(),
+        }
+    }
+}
+
+// ========== 保存后压缩 ==========
+
+impl PieceTable {
+    /// 保存并压缩：写入成功后以新文件为原始缓冲区
+    pub fn save_and_rebase(&self, path: &Path, options: SaveOptions) -> Result<Self, String> {
+        let saved = self.save_to(path, options)?;
+        self.rebase_onto_file(&saved)
+    }
+
+    /// 以刚写入的文件作为新的原始缓冲区：只剩一个Original Piece，不再引用追加缓冲区
+    ///
+    /// 路径上必须仍是 save_to 写入的那个文件（inode、大小、修改时间一致），
+    /// 读取前后各检查一次，期间被其他程序替换或改写时拒绝压缩。
+    /// 旧版本仍持有旧的缓冲区，撤销历史不受影响
+    pub fn rebase_onto_file(&self, saved: &SavedFile) -> Result<Self, String> {
+        let unchanged = || {
+            fs::metadata(&saved.path).is_ok_and(|metadata| FileIdentity::of(&metadata) == saved.identity)
+        };
+        if !unchanged() {
+            return Err(format!("保存后文件已被其他程序修改: {}", saved.path.display()));
+        }
+
+        let table = PieceTable::from_file(&saved.path)?;
+        if !unchanged() {
+            return Err(format!("保存后文件已被其他程序修改: {}", saved.path.display()));
+        }
+        if table.total_bytes() != self.total_bytes() {
+            return Err(format!(
+                "文件内容与文档不一致: {} != {} 字节",
+                table.total_bytes(),
+                self.total_bytes()
+            ));
+        }
+
+        Ok(table.with_state_from(self))
+    }
+}
+
+/// 逐级解析符号链接，返回最终文件路径（最终文件可以尚不存在）
+fn resolve_symlinks(path: &Path) -> Result<PathBuf, String> {
+    let mut current = path.to_path_buf();
+
+    for _ in 0..MAX_SYMLINK_DEPTH {
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&current)
+                    .map_err(|e| format!("读取符号链接失败: {}", e))?;
+                current = match current.parent() {
+                    Some(parent) if link.is_relative() => parent.join(link),
+                    _ => link,
+                };
+            }
+            _ => return Ok(current),
+        }
+    }
+
+    Err(format!("符号链接层数过多: {}", path.display()))
+}
+
+/// 在目标所在目录创建临时文件（同一文件系统才能原子重命名）
+fn create_temp_file(target: &Path) -> Result<(PathBuf, File), String> {
+    let dir = parent_dir(target);
+    let name = target
+        .file_name()
+        .ok_or_else(|| format!("无效的保存路径: {}", target.display()))?
+        .to_string_lossy();
+
+    loop {
+        let temp_path = dir.join(format!(
+            ".{}.{}.{}.tmp",
+            name,
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("创建临时文件失败: {}", e)),
+        }
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// 同步目录项，确保重命名本身已落盘
+fn sync_parent_dir(target: &Path) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        File::open(parent_dir(target))
+            .and_then(|dir| dir.sync_all())
+            .map_err(|e| format!("同步目录失败: {}", e))?;
+    }
+
+    #[cfg(not(unix))]
+    let _ = target;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::{DiskStatus, MmapBuffer};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zedit_save_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 目录中的文件名（确认没有残留临时文件）
+    fn only_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_save_streams_edited_content() {
+        let dir = temp_dir("basic");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "old content").unwrap();
+
+        let table = PieceTable::from_bytes(b"caf\xE9 ");
+        let (table, _) = table.insert_char_safe(5, "中文\n");
+        assert_eq!(table.save_to(&path, SaveOptions::default()).unwrap().path, path);
+
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xE9 \xE4\xB8\xAD\xE6\x96\x87\n");
+        assert_eq!(only_files(&dir), vec!["notes.txt"]);
+        // 目标尚不存在时直接创建
+        let created = dir.join("new.txt");
+        table.save_to(&created, SaveOptions::default()).unwrap();
+        assert_eq!(fs::read(&created).unwrap(), fs::read(&path).unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rebase_after_save_collapses_pieces() {
+        let dir = temp_dir("rebase");
+        let path = dir.join("draft.md");
+
+        let mut table = PieceTable::from_text("# Title\n");
+        table.get_or_build_lines();
+        for i in 0..50 {
+            let end = table.total_bytes();
+            table = table.insert_char_safe(end, &format!("item {}\n", i)).0;
+            table = table.delete_char_safe(0..1).0;
+            table = table.insert_char_safe(0, "#").0;
+        }
+        let text = table.get_text_range(0..table.total_bytes());
+        assert!(table.piece_count() > 1);
+
+        let saved = table.save_to(&path, SaveOptions::default()).unwrap();
+        let rebased = table.rebase_onto_file(&saved).unwrap();
+        assert_eq!(rebased.piece_count(), 1);
+        assert_eq!(rebased.estimated_memory(), std::mem::size_of::<crate::core::buffer::PieceEntry>());
+        assert_eq!(rebased.get_text_range(0..rebased.total_bytes()), text);
+        // 行索引保留
+        assert_eq!(rebased.lines().map(|lines| lines.total_lines()), Some(51));
+        // 旧版本仍可读取
+        assert_eq!(table.get_text_range(0..table.total_bytes()), text);
+
+        fs::write(&path, "changed").unwrap();
+        assert!(table.rebase_onto_file(&saved).is_err());
+
+        // 其他程序以同样长度的内容替换了文件：长度一致也拒绝
+        let saved = table.save_to(&path, SaveOptions::default()).unwrap();
+        let other = dir.join("other.md");
+        fs::write(&other, text.to_uppercase()).unwrap();
+        fs::rename(&other, &path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len() as usize, text.len());
+        assert!(table.rebase_onto_file(&saved).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_keeps_permissions_and_follows_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = temp_dir("links");
+        let real = dir.join("real.conf");
+        let link = dir.join("link.conf");
+        fs::write(&real, "a = 1\n").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o640)).unwrap();
+        symlink("real.conf", &link).unwrap();
+
+        let table = PieceTable::from_text("a = 2\n");
+        assert_eq!(table.save_to(&link, SaveOptions::default()).unwrap().path, real);
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "a = 2\n");
+        assert_eq!(fs::metadata(&real).unwrap().permissions().mode() & 0o777, 0o640);
+
+        // 不跟随时用普通文件替换链接本身
+        let options = SaveOptions { follow_symlinks: false, ..SaveOptions::default() };
+        PieceTable::from_text("a = 3\n").save_to(&link, options).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_file());
+        assert_eq!(fs::read_to_string(&real).unwrap(), "a = 2\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_over_mapped_original() {
+        let dir = temp_dir("mapped");
+        let path = dir.join("app.log");
+        let content = "line one\nline two\nline three\n";
+        fs::write(&path, content).unwrap();
+
+        let table = PieceTable::from_mapped(MmapBuffer::from_file(&path).unwrap());
+        let (edited, _) = table.delete_char_safe(0..9);
+        let (edited, _) = edited.insert_char_safe(edited.total_bytes(), "line four\n");
+        edited.save_to(&path, SaveOptions::default()).unwrap();
+
+        // 新文件内容正确，旧映射中的Piece仍读到旧内容
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line two\nline three\nline four\n");
+        assert_eq!(table.get_text_range(0..table.total_bytes()), content);
+        assert_eq!(edited.get_text_range(0..9), "line two\n");
+        assert_eq!(edited.check_original(), DiskStatus::Replaced);
+        assert_eq!(only_files(&dir), vec!["app.log"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
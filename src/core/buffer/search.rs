@@ -0,0 +1,251 @@
+// 字面量搜索
+//
+// 职责：在 PieceTable 中流式查找字节串，按块读取内容，不加载全文
+//
+// 每次读入一块接在上一块保留的尾部之后（反向搜索则接在头部之前），
+// 保留长度不超过 needle.len() - 1，跨Piece、跨块的匹配也能找到，
+// 内存占用只与块大小和搜索串长度有关
+
+use std::ops::Range;
+
+use memchr::memmem::{Finder, FinderRev};
+
+use crate::core::buffer::{PieceTable, DEFAULT_CHUNK_SIZE};
+
+/// 搜索方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchDirection {
+    /// 向文档末尾
+    Forward,
+    /// 向文档开头
+    Backward,
+}
+
+/// 子串查找器（Two-Way算法，首字节用memchr加速）
+enum Matcher {
+    Forward(Box<Finder<'static>>),
+    Backward(FinderRev<'static>),
+}
+
+/// 字面量匹配的惰性迭代器
+///
+/// 按搜索方向依次返回互不重叠的匹配范围，每次只读入所需的块
+pub struct LiteralMatches<'a> {
+    matcher: Matcher,
+    window: ScanWindow<'a>,
+}
+
+/// 搜索中读入的内容
+struct ScanWindow<'a> {
+    table: &'a PieceTable,
+    needle_len: usize,
+    /// 已读入、尚未搜索完的内容
+    bytes: Vec<u8>,
+    /// bytes 在文档中的起始偏移
+    start: usize,
+    /// 正向：下一个匹配起点的下限；反向：下一个匹配终点的上限
+    cursor: usize,
+    chunk_size: usize,
+}
+
+impl<'a> LiteralMatches<'a> {
+    fn new(table: &'a PieceTable, needle: &[u8], from: usize, direction: SearchDirection) -> Self {
+        let matcher = match direction {
+            SearchDirection::Forward => Matcher::Forward(Box::new(Finder::new(needle).into_owned())),
+            SearchDirection::Backward => Matcher::Backward(FinderRev::new(needle).into_owned()),
+        };
+        let from = from.min(table.total_bytes());
+
+        Self {
+            matcher,
+            window: ScanWindow {
+                table,
+                needle_len: needle.len(),
+                bytes: Vec::new(),
+                start: from,
+                cursor: from,
+                chunk_size: DEFAULT_CHUNK_SIZE,
+            },
+        }
+    }
+
+    /// 设置每次读入的块大小（默认 DEFAULT_CHUNK_SIZE）
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.window.chunk_size = chunk_size.max(1);
+        self
+    }
+}
+
+impl ScanWindow<'_> {
+    fn end(&self) -> usize {
+        self.start + self.bytes.len()
+    }
+
+    fn next_forward(&mut self, finder: &Finder<'static>) -> Option<Range<usize>> {
+        loop {
+            let searched = &self.bytes[self.cursor - self.start..];
+            if let Some(index) = finder.find(searched) {
+                let start = self.cursor + index;
+                self.cursor = start + self.needle_len;
+                return Some(start..self.cursor);
+            }
+
+            let end_offset = self.end();
+            if end_offset >= self.table.total_bytes() {
+                self.bytes.clear();
+                self.start = end_offset;
+                self.cursor = end_offset;
+                return None;
+            }
+
+            // 只保留可能与下一块组成匹配的尾部
+            let keep_from = self.cursor.max(end_offset.saturating_sub(self.needle_len - 1));
+            self.bytes.drain(..keep_from - self.start);
+            self.start = keep_from;
+            self.cursor = keep_from;
+
+            let read_end = (end_offset + self.chunk_size.max(self.needle_len)).min(self.table.total_bytes());
+            for slice in self.table.byte_slices(end_offset..read_end) {
+                self.bytes.extend_from_slice(&slice);
+            }
+        }
+    }
+
+    fn next_backward(&mut self, finder: &FinderRev<'static>) -> Option<Range<usize>> {
+        loop {
+            let searched = &self.bytes[..self.cursor - self.start];
+            if let Some(index) = finder.rfind(searched) {
+                let start = self.start + index;
+                self.cursor = start;
+                return Some(start..start + self.needle_len);
+            }
+
+            if self.start == 0 {
+                self.bytes.clear();
+                self.cursor = 0;
+                return None;
+            }
+
+            // 只保留可能与上一块组成匹配的头部
+            let keep_to = self.cursor.min(self.start + self.needle_len - 1);
+            self.bytes.truncate(keep_to - self.start);
+            self.cursor = keep_to;
+
+            let read_start = self.start.saturating_sub(self.chunk_size.max(self.needle_len));
+            let mut bytes = self.table.get_bytes_range(read_start..self.start);
+            bytes.extend_from_slice(&self.bytes);
+            self.bytes = bytes;
+            self.start = read_start;
+        }
+    }
+}
+
+impl<'a> Iterator for LiteralMatches<'a> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // 空串没有有意义的匹配
+        if self.window.needle_len == 0 {
+            return None;
+        }
+
+        match &self.matcher {
+            Matcher::Forward(finder) => self.window.next_forward(finder),
+            Matcher::Backward(finder) => self.window.next_backward(finder),
+        }
+    }
+}
+
+// ========== 查找 ==========
+
+impl PieceTable {
+    /// 从 from 开始查找字节串，返回惰性的匹配迭代器
+    ///
+    /// 正向返回起点不早于 from 的匹配，反向返回终点不晚于 from 的匹配，
+    /// 匹配互不重叠；空串没有匹配
+    pub fn search_literal(&self, needle: &[u8], from: usize, direction: SearchDirection) -> LiteralMatches<'_> {
+        LiteralMatches::new(self, needle, from, direction)
+    }
+
+    /// 从 from 开始按方向查找第一个匹配
+    pub fn find_literal(&self, needle: &[u8], from: usize, direction: SearchDirection) -> Option<Range<usize>> {
+        self.search_literal(needle, from, direction).next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 逐个位置比对得到的互不重叠匹配
+    fn naive(haystack: &[u8], needle: &[u8]) -> Vec<Range<usize>> {
+        let mut matches = Vec::new();
+        let mut start = 0;
+        while start + needle.len() <= haystack.len() {
+            if &haystack[start..start + needle.len()] == needle {
+                matches.push(start..start + needle.len());
+                start += needle.len();
+            } else {
+                start += 1;
+            }
+        }
+        matches
+    }
+
+    #[test]
+    fn test_matches_span_pieces_and_chunks() {
+        // 每个 "needle" 都被插入拆到两个Piece中
+        let mut table = PieceTable::from_text(&"..nee....".repeat(50));
+        for offset in (5..table.total_bytes()).step_by(9).rev() {
+            table = table.insert_char_safe(offset, "dle").0;
+        }
+        let text = table.get_all_text();
+        let expected = naive(text.as_bytes(), b"needle");
+        assert_eq!(expected.len(), 50);
+
+        for chunk_size in [1, 2, 7, 64, DEFAULT_CHUNK_SIZE] {
+            let forward: Vec<_> = table
+                .search_literal(b"needle", 0, SearchDirection::Forward)
+                .with_chunk_size(chunk_size)
+                .collect();
+            assert_eq!(forward, expected, "chunk size {}", chunk_size);
+
+            let mut backward: Vec<_> = table
+                .search_literal(b"needle", table.total_bytes(), SearchDirection::Backward)
+                .with_chunk_size(chunk_size)
+                .collect();
+            backward.reverse();
+            assert_eq!(backward, expected, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_search_from_offset() {
+        let table = PieceTable::from_text("aaaa-aaaa");
+
+        // 匹配互不重叠
+        let forward: Vec<_> = table.search_literal(b"aa", 1, SearchDirection::Forward).collect();
+        assert_eq!(forward, vec![1..3, 5..7, 7..9]);
+        let backward: Vec<_> = table.search_literal(b"aa", 8, SearchDirection::Backward).collect();
+        assert_eq!(backward, vec![6..8, 2..4, 0..2]);
+
+        assert_eq!(table.find_literal(b"-", 4, SearchDirection::Forward), Some(4..5));
+        assert_eq!(table.find_literal(b"-", 5, SearchDirection::Forward), None);
+        assert_eq!(table.find_literal(b"-", 4, SearchDirection::Backward), None);
+        assert_eq!(table.find_literal(b"-", 5, SearchDirection::Backward), Some(4..5));
+        assert_eq!(table.find_literal(b"", 0, SearchDirection::Forward), None);
+        assert_eq!(table.find_literal(b"aaaa-aaaa!", 0, SearchDirection::Forward), None);
+    }
+
+    #[test]
+    fn test_search_multibyte_text() {
+        let (table, _) = PieceTable::from_text("搜索 and 查找 and 搜索").insert_char_safe(3, "索");
+        let text = table.get_all_text();
+
+        let forward: Vec<_> = table.search_literal("搜索".as_bytes(), 0, SearchDirection::Forward).with_chunk_size(1).collect();
+        assert_eq!(forward, naive(text.as_bytes(), "搜索".as_bytes()));
+        for range in forward {
+            assert_eq!(&text[range], "搜索");
+        }
+    }
+}
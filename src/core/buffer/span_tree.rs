@@ -0,0 +1,465 @@
+// 跨度平衡树
+//
+// 职责：以 B 树保存一列首尾相接的跨度（各行长度、检查点之间的间隔、锚点之间的距离），
+//       每个节点缓存子树的字节数、换行数和跨度数，
+//       按字节、换行序号或下标定位都在 O(log n) 内完成；
+//       位置由前缀汇总得到，编辑点之后的跨度不需要平移；
+//       节点通过 Arc 共享，克隆为 O(1)，编辑只复制被修改的路径
+
+use std::ops::Range;
+use std::sync::Arc;
+
+/// 叶子最多保存的跨度数
+const MAX_LEAF_SPANS: usize = 128;
+/// 内部节点最多的子节点数
+const MAX_CHILDREN: usize = 16;
+
+/// 跨度：占用的字节数及其中的换行数
+pub(super) trait Span: Copy {
+    fn bytes(&self) -> usize;
+    fn line_feeds(&self) -> usize;
+}
+
+/// 一段跨度的汇总信息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct SpanSummary {
+    pub(super) bytes: usize,
+    pub(super) line_feeds: usize,
+    /// 跨度数量
+    pub(super) count: usize,
+}
+
+impl SpanSummary {
+    fn of<T: Span>(span: &T) -> Self {
+        Self {
+            bytes: span.bytes(),
+            line_feeds: span.line_feeds(),
+            count: 1,
+        }
+    }
+
+    fn add(&mut self, other: &SpanSummary) {
+        self.bytes += other.bytes;
+        self.line_feeds += other.line_feeds;
+        self.count += other.count;
+    }
+
+    fn plus(mut self, other: &SpanSummary) -> Self {
+        self.add(other);
+        self
+    }
+}
+
+/// 定位跨度时比较的汇总字段
+#[derive(Debug, Clone, Copy)]
+enum Key {
+    Byte,
+    LineFeed,
+    Index,
+}
+
+impl Key {
+    fn of(self, summary: &SpanSummary) -> usize {
+        match self {
+            Key::Byte => summary.bytes,
+            Key::LineFeed => summary.line_feeds,
+            Key::Index => summary.count,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    summary: SpanSummary,
+    kind: NodeKind<T>,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind<T> {
+    Leaf(Vec<T>),
+    Internal(Vec<Arc<Node<T>>>),
+}
+
+impl<T: Span> Node<T> {
+    fn leaf(spans: Vec<T>) -> Self {
+        let mut summary = SpanSummary::default();
+        for span in &spans {
+            summary.add(&SpanSummary::of(span));
+        }
+        Self { summary, kind: NodeKind::Leaf(spans) }
+    }
+
+    fn internal(children: Vec<Arc<Node<T>>>) -> Self {
+        let mut summary = SpanSummary::default();
+        for child in &children {
+            summary.add(&child.summary);
+        }
+        Self { summary, kind: NodeKind::Internal(children) }
+    }
+
+    /// 直接子节点（跨度）数量
+    fn len(&self) -> usize {
+        match &self.kind {
+            NodeKind::Leaf(spans) => spans.len(),
+            NodeKind::Internal(children) => children.len(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match &self.kind {
+            NodeKind::Leaf(_) => MAX_LEAF_SPANS,
+            NodeKind::Internal(_) => MAX_CHILDREN,
+        }
+    }
+
+    fn is_underflow(&self) -> bool {
+        self.len() < self.capacity() / 4
+    }
+
+    /// 用新的跨度替换下标范围内的跨度，返回替换后的同层节点（可能为空、过小或多个）
+    fn splice(&self, range: Range<usize>, spans: Vec<T>) -> Vec<Node<T>> {
+        let children = match &self.kind {
+            NodeKind::Leaf(old) => {
+                let mut all = old.clone();
+                all.splice(range, spans);
+                return split_even(all, MAX_LEAF_SPANS).into_iter().map(Node::leaf).collect();
+            }
+            NodeKind::Internal(children) => children,
+        };
+
+        // 与范围相交的子节点：first 包含起点（起点在末尾时取最后一个），last 包含终点前的跨度
+        let starts: Vec<usize> = children
+            .iter()
+            .scan(0, |start, child| {
+                let current = *start;
+                *start += child.summary.count;
+                Some(current)
+            })
+            .collect();
+        let containing = |index: usize| starts.partition_point(|&start| start <= index).saturating_sub(1);
+        let first = containing(range.start);
+        let last = if range.is_empty() { first } else { containing(range.end - 1) };
+
+        let mut replaced = Vec::new();
+        if first == last {
+            let local = range.start - starts[first]..range.end - starts[first];
+            replaced.extend(children[first].splice(local, spans));
+        } else {
+            let first_child = &children[first];
+            replaced.extend(first_child.splice(range.start - starts[first]..first_child.summary.count, spans));
+            replaced.extend(children[last].splice(0..range.end - starts[last], Vec::new()));
+        }
+
+        // 过小的节点与相邻节点合并后重新均分
+        let (mut from, mut to) = (first, last + 1);
+        if replaced.is_empty() || replaced.iter().any(Node::is_underflow) {
+            if to < children.len() {
+                replaced.push(clone_node(&children[to]));
+                to += 1;
+            } else if from > 0 {
+                from -= 1;
+                replaced.insert(0, clone_node(&children[from]));
+            }
+            replaced = rebalance(replaced);
+        }
+
+        let mut all: Vec<Arc<Node<T>>> = children[..from].to_vec();
+        all.extend(replaced.into_iter().map(Arc::new));
+        all.extend(children[to..].iter().cloned());
+        split_even(all, MAX_CHILDREN).into_iter().map(Node::internal).collect()
+    }
+}
+
+fn clone_node<T: Span>(node: &Arc<Node<T>>) -> Node<T> {
+    Node {
+        summary: node.summary,
+        kind: node.kind.clone(),
+    }
+}
+
+/// 合并同层节点的内容，再按容量均分
+fn rebalance<T: Span>(nodes: Vec<Node<T>>) -> Vec<Node<T>> {
+    let mut spans = Vec::new();
+    let mut children = Vec::new();
+    for node in nodes {
+        match node.kind {
+            NodeKind::Leaf(items) => spans.extend(items),
+            NodeKind::Internal(items) => children.extend(items),
+        }
+    }
+
+    if !children.is_empty() {
+        return split_even(children, MAX_CHILDREN).into_iter().map(Node::internal).collect();
+    }
+    split_even(spans, MAX_LEAF_SPANS).into_iter().map(Node::leaf).collect()
+}
+
+/// 均匀分组，每组不超过 capacity（空输入得到空结果）
+fn split_even<U>(items: Vec<U>, capacity: usize) -> Vec<Vec<U>> {
+    if items.is_empty() {
+        return Vec::new();
+    }
+
+    let groups = items.len().div_ceil(capacity);
+    let base = items.len() / groups;
+    let extra = items.len() % groups;
+
+    let mut iter = items.into_iter();
+    (0..groups)
+        .map(|g| iter.by_ref().take(base + usize::from(g < extra)).collect())
+        .collect()
+}
+
+/// 跨度 B 树（持久化结构，克隆为 O(1)）
+#[derive(Debug, Clone)]
+pub(super) struct SpanTree<T> {
+    root: Arc<Node<T>>,
+}
+
+impl<T: Span> SpanTree<T> {
+    /// 由有序跨度批量构建（O(n)）
+    pub(super) fn from_spans(spans: Vec<T>) -> Self {
+        Self { root: Self::root_of(split_even(spans, MAX_LEAF_SPANS).into_iter().map(Node::leaf).collect()) }
+    }
+
+    /// 同层节点逐层向上构建根节点，并去掉只有一个子节点的根
+    fn root_of(mut level: Vec<Node<T>>) -> Arc<Node<T>> {
+        while level.len() > 1 {
+            let children: Vec<Arc<Node<T>>> = level.into_iter().map(Arc::new).collect();
+            level = split_even(children, MAX_CHILDREN).into_iter().map(Node::internal).collect();
+        }
+
+        let mut root = Arc::new(level.pop().unwrap_or_else(|| Node::leaf(Vec::new())));
+        while let NodeKind::Internal(children) = &root.kind {
+            if children.len() != 1 {
+                break;
+            }
+            root = children[0].clone();
+        }
+        root
+    }
+
+    /// 所有跨度的汇总
+    pub(super) fn summary(&self) -> SpanSummary {
+        self.root.summary
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.root.summary.count
+    }
+
+    /// 包含字节偏移的跨度，返回 (下标, 跨度, 之前所有跨度的汇总)；超出时取最后一个
+    pub(super) fn find_byte(&self, offset: usize) -> Option<(usize, T, SpanSummary)> {
+        self.find(Key::Byte, offset)
+    }
+
+    /// 包含第 n 个换行（0-based）的跨度；超出时取最后一个
+    pub(super) fn find_line_feed(&self, n: usize) -> Option<(usize, T, SpanSummary)> {
+        self.find(Key::LineFeed, n)
+    }
+
+    /// 下标处的跨度
+    pub(super) fn get(&self, index: usize) -> Option<(T, SpanSummary)> {
+        if index >= self.len() {
+            return None;
+        }
+        self.find(Key::Index, index).map(|(_, span, before)| (span, before))
+    }
+
+    /// 最后一个跨度
+    pub(super) fn last(&self) -> Option<T> {
+        self.len().checked_sub(1).and_then(|index| self.get(index)).map(|(span, _)| span)
+    }
+
+    /// 第一个累计值超过 target 的跨度（没有时取最后一个）
+    fn find(&self, key: Key, target: usize) -> Option<(usize, T, SpanSummary)> {
+        if self.len() == 0 {
+            return None;
+        }
+
+        let mut node = &self.root;
+        let mut before = SpanSummary::default();
+        loop {
+            match &node.kind {
+                NodeKind::Internal(children) => {
+                    let mut index = children.len() - 1;
+                    for (i, child) in children.iter().enumerate() {
+                        if key.of(&before.plus(&child.summary)) > target {
+                            index = i;
+                            break;
+                        }
+                        if i + 1 < children.len() {
+                            before.add(&child.summary);
+                        }
+                    }
+                    node = &children[index];
+                }
+                NodeKind::Leaf(spans) => {
+                    let mut index = spans.len() - 1;
+                    for (i, span) in spans.iter().enumerate() {
+                        let summary = SpanSummary::of(span);
+                        if key.of(&before.plus(&summary)) > target {
+                            index = i;
+                            break;
+                        }
+                        if i + 1 < spans.len() {
+                            before.add(&summary);
+                        }
+                    }
+                    return Some((before.count, spans[index], before));
+                }
+            }
+        }
+    }
+
+    /// 用新的跨度替换下标范围内的跨度（只复制涉及的路径）
+    pub(super) fn splice(&mut self, range: Range<usize>, spans: Vec<T>) {
+        let range = range.start.min(self.len())..range.end.min(self.len());
+        self.root = Self::root_of(self.root.splice(range, spans));
+    }
+
+    /// 从下标开始按顺序遍历跨度
+    pub(super) fn iter_from(&self, index: usize) -> SpanIter<'_, T> {
+        let mut iter = SpanIter { stack: Vec::new(), leaf: &[], index: 0 };
+        if index >= self.len() {
+            return iter;
+        }
+
+        let mut node = &self.root;
+        let mut skipped = 0;
+        loop {
+            match &node.kind {
+                NodeKind::Internal(children) => {
+                    let mut chosen = children.len() - 1;
+                    for (i, child) in children.iter().enumerate() {
+                        if index < skipped + child.summary.count {
+                            chosen = i;
+                            break;
+                        }
+                        skipped += child.summary.count;
+                    }
+                    iter.stack.push((children.as_slice(), chosen + 1));
+                    node = &children[chosen];
+                }
+                NodeKind::Leaf(spans) => {
+                    iter.leaf = spans.as_slice();
+                    iter.index = index - skipped;
+                    return iter;
+                }
+            }
+        }
+    }
+
+    pub(super) fn iter(&self) -> SpanIter<'_, T> {
+        self.iter_from(0)
+    }
+}
+
+/// 按顺序遍历跨度
+pub(super) struct SpanIter<'a, T> {
+    /// 内部节点路径：(子节点切片, 下一个待访问的子节点下标)
+    stack: Vec<(&'a [Arc<Node<T>>], usize)>,
+    leaf: &'a [T],
+    index: usize,
+}
+
+impl<T: Span> Iterator for SpanIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        while self.index >= self.leaf.len() {
+            let (children, next) = self.stack.last_mut()?;
+            if *next < children.len() {
+                let mut node = &children[*next];
+                *next += 1;
+                // 下降到子树最左侧的叶子
+                loop {
+                    match &node.kind {
+                        NodeKind::Internal(children) => {
+                            self.stack.push((children.as_slice(), 1));
+                            node = &children[0];
+                        }
+                        NodeKind::Leaf(spans) => {
+                            self.leaf = spans.as_slice();
+                            self.index = 0;
+                            break;
+                        }
+                    }
+                }
+            } else {
+                self.stack.pop();
+            }
+        }
+
+        let span = self.leaf[self.index];
+        self.index += 1;
+        Some(span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 测试用跨度：(字节数, 换行数)
+    impl Span for (usize, usize) {
+        fn bytes(&self) -> usize {
+            self.0
+        }
+
+        fn line_feeds(&self) -> usize {
+            self.1
+        }
+    }
+
+    #[test]
+    fn test_splice_matches_vec() {
+        let mut expected: Vec<(usize, usize)> = (0..3000).map(|i| (i % 7 + 1, i % 2)).collect();
+        let mut tree = SpanTree::from_spans(expected.clone());
+
+        let mut seed = 5u64;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound.max(1)
+        };
+        for _ in 0..500 {
+            let start = next(expected.len() + 1);
+            let end = (start + next(300)).min(expected.len());
+            let spans: Vec<(usize, usize)> = (0..next(200)).map(|i| (i + 1, i % 3)).collect();
+            expected.splice(start..end, spans.clone());
+            tree.splice(start..end, spans);
+        }
+
+        assert_eq!(tree.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(tree.iter_from(100).next(), expected.get(100).copied());
+        assert_eq!(tree.summary().bytes, expected.iter().map(|s| s.0).sum::<usize>());
+
+        let offset = tree.summary().bytes / 2;
+        let (index, span, before) = tree.find_byte(offset).unwrap();
+        assert_eq!(before.bytes, expected[..index].iter().map(|s| s.0).sum::<usize>());
+        assert!(offset < before.bytes + span.0);
+        let (index, _, before) = tree.find_line_feed(10).unwrap();
+        assert_eq!(before.line_feeds, expected[..index].iter().map(|s| s.1).sum::<usize>());
+        assert_eq!(tree.find_byte(usize::MAX).unwrap().0, expected.len() - 1);
+
+        tree.splice(0..tree.len(), Vec::new());
+        assert_eq!(tree.len(), 0);
+        assert!(tree.find_byte(0).is_none());
+    }
+
+    #[test]
+    fn test_clone_shares_untouched_nodes() {
+        let original = SpanTree::from_spans(vec![(1, 0); 10_000]);
+        let mut edited = original.clone();
+
+        edited.splice(3..4, vec![(2, 1), (3, 0)]);
+
+        assert_eq!(original.summary().bytes, 10_000);
+        assert_eq!(edited.summary(), SpanSummary { bytes: 10_004, line_feeds: 1, count: 10_001 });
+        let (NodeKind::Internal(a), NodeKind::Internal(b)) = (&original.root.kind, &edited.root.kind) else {
+            panic!("10000个跨度应构成多层树");
+        };
+        assert!(!Arc::ptr_eq(&a[0], &b[0]));
+        assert!(Arc::ptr_eq(a.last().unwrap(), b.last().unwrap()));
+    }
+}
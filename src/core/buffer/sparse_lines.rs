@@ -0,0 +1,534 @@
+// 稀疏行索引
+//
+// 职责：为超大文件（Restricted 模式）每隔 N 行或 K 字节记录一个检查点，
+//       精确位置从最近的检查点向后扫描得到，内存和查找延迟都有上界
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::core::buffer::{
+    lines::Replacement,
+    piece_table::PieceTable,
+    span_tree::{Span, SpanTree},
+};
+
+/// 默认每隔多少行记录一个检查点
+pub const DEFAULT_LINES_PER_CHECKPOINT: usize = 1024;
+/// 默认每隔多少字节记录一个检查点（限制超长行的扫描距离）
+pub const DEFAULT_BYTES_PER_CHECKPOINT: usize = 64 * 1024; // 64KB
+
+/// 稀疏索引配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SparseLineConfig {
+    pub lines_per_checkpoint: usize,
+    pub bytes_per_checkpoint: usize,
+}
+
+impl Default for SparseLineConfig {
+    fn default() -> Self {
+        Self {
+            lines_per_checkpoint: DEFAULT_LINES_PER_CHECKPOINT,
+            bytes_per_checkpoint: DEFAULT_BYTES_PER_CHECKPOINT,
+        }
+    }
+}
+
+/// 检查点：字节偏移及其之前的换行数
+///
+/// 任意 (偏移, 之前的换行数) 都是有效检查点，不要求位于行首
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Checkpoint {
+    byte: usize,
+    line: usize,
+}
+
+/// 相邻检查点之间的间隔（最后一个间隔延伸到文档末尾）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Gap {
+    bytes: usize,
+    line_feeds: usize,
+}
+
+impl Span for Gap {
+    fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn line_feeds(&self) -> usize {
+        self.line_feeds
+    }
+}
+
+/// 稀疏行索引
+///
+/// 检查点以间隔的形式保存在跨度树中，位置由前缀汇总得到：
+/// 编辑只修改编辑点所在的间隔，之后的检查点不需要平移，快照之间共享未修改的节点
+#[derive(Debug, Clone)]
+pub struct SparseLines {
+    /// 检查点间隔（第一个检查点总是 (0, 0)，间隔总和为文档的字节数与换行数）
+    gaps: SpanTree<Gap>,
+    config: SparseLineConfig,
+    /// 被索引的文档（不含行索引的快照），扫描时读取
+    source: Option<Arc<PieceTable>>,
+    /// 编辑后可能需要重新划分检查点的字节范围
+    pending: Option<Range<usize>>,
+}
+
+impl SparseLines {
+    /// 总行数（以换行符结尾的文本不计末尾空行）
+    pub fn total_lines(&self) -> usize {
+        let total_bytes = self.total_bytes();
+        if total_bytes == 0 {
+            return 0;
+        }
+
+        match self.byte_at(total_bytes - 1) {
+            Some(b'\n') => self.line_feeds(),
+            _ => self.line_feeds() + 1,
+        }
+    }
+
+    /// 查找包含指定字节偏移的行（行尾换行符的位置属于该行）
+    pub fn find_line_by_offset(&self, offset: usize) -> Option<usize> {
+        if offset > self.total_bytes() || self.source.is_none() {
+            return None;
+        }
+
+        let line = self.line_feeds_before(offset);
+        (line < self.total_lines()).then_some(line)
+    }
+
+    /// 获取指定行的字节范围（不含换行符）
+    pub fn get_line_range(&self, line_number: usize) -> Option<Range<usize>> {
+        if line_number >= self.total_lines() {
+            return None;
+        }
+        let source = self.source.as_deref()?;
+
+        // 行首：从第 line_number 个换行所在间隔的起点向后找
+        let start = if line_number == 0 {
+            0
+        } else {
+            let from = self.checkpoint_before_line_feed(line_number - 1)?;
+            nth_line_feed(source, from.byte, line_number - from.line)? + 1
+        };
+
+        // 行尾：从行尾换行所在间隔的起点（不早于行首）开始找下一个换行
+        let from = self.checkpoint_before_line_feed(line_number)?.byte.max(start);
+        let end = nth_line_feed(source, from, 1).unwrap_or(self.total_bytes());
+
+        Some(start..end)
+    }
+
+    /// 是否为换行符结束的行
+    pub fn ends_with_newline(&self, line_number: usize) -> bool {
+        line_number < self.line_feeds()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.gaps.summary().bytes
+    }
+
+    /// 检查点数量
+    pub fn checkpoint_count(&self) -> usize {
+        self.gaps.len()
+    }
+
+    pub fn config(&self) -> &SparseLineConfig {
+        &self.config
+    }
+
+    /// 文档中的换行数
+    fn line_feeds(&self) -> usize {
+        self.gaps.summary().line_feeds
+    }
+}
+
+// ========== 编辑维护 ==========
+
+impl SparseLines {
+    /// 处理插入：计入插入点所在的间隔
+    pub fn handle_insert(&mut self, offset: usize, text: &str) {
+        let line_feeds = text.bytes().filter(|&b| b == b'\n').count();
+        self.handle_insert_span(offset, text.len(), line_feeds);
+    }
+
+    /// 处理插入（只需要插入的长度和换行数）
+    pub fn handle_insert_span(&mut self, offset: usize, len: usize, line_feeds: usize) {
+        self.replace_gaps(offset..offset, len, line_feeds);
+        self.mark_pending(offset..offset + len);
+    }
+
+    /// 处理删除：范围覆盖的间隔合并为一个（其中的检查点被移除）
+    ///
+    /// 必须在替换数据源之前调用（删除的换行数从旧文档计算）
+    pub fn handle_delete(&mut self, range: Range<usize>) {
+        self.replace_gaps(range.clone(), 0, 0);
+        self.mark_pending(range.start..range.start);
+    }
+
+    /// 处理批量替换（按位置排序、互不重叠）：从后往前逐个修改涉及的间隔
+    ///
+    /// 前面的替换不受后面替换的影响，每个替换只复制跨度树中的一条路径
+    pub(super) fn handle_replacements(&mut self, replacements: &[Replacement]) {
+        let (Some(first), Some(last)) = (replacements.first(), replacements.last()) else {
+            return;
+        };
+
+        let mut last_end = last.range.end;
+        for r in replacements.iter().rev() {
+            let added_lines = r.text.iter().filter(|&&b| b == b'\n').count();
+            self.replace_range(r.range.clone(), r.removed_line_feeds, r.text.len(), added_lines);
+            last_end = last_end + r.text.len() - r.range.len();
+        }
+
+        self.mark_pending(first.range.start..last_end);
+    }
+
+    /// 替换为编辑后的文档，并重新划分过大的检查点间隔
+    pub(super) fn set_source(&mut self, source: Arc<PieceTable>) {
+        self.source = Some(source);
+
+        if let Some(range) = self.pending.take() {
+            self.rebalance(range);
+        }
+    }
+
+    fn mark_pending(&mut self, range: Range<usize>) {
+        self.pending = Some(match self.pending.take() {
+            Some(pending) => pending.start.min(range.start)..pending.end.max(range.end),
+            None => range,
+        });
+    }
+
+    /// 删除范围（换行数从当前数据源计算）并插入指定长度和换行数的内容
+    fn replace_gaps(&mut self, range: Range<usize>, len: usize, line_feeds: usize) {
+        let removed = if range.is_empty() {
+            0
+        } else {
+            self.line_feeds_before(range.end) - self.line_feeds_before(range.start)
+        };
+        self.replace_range(range, removed, len, line_feeds);
+    }
+
+    /// 范围覆盖的间隔合并为一个，并计入插入的内容
+    ///
+    /// 位于范围起点的检查点保留，范围内部的检查点移除
+    fn replace_range(&mut self, range: Range<usize>, removed_line_feeds: usize, len: usize, line_feeds: usize) {
+        let Some((first, first_gap, before)) = self.gaps.find_byte(range.start) else {
+            return;
+        };
+        let (last, last_gap, last_before) = if range.is_empty() {
+            (first, first_gap, before)
+        } else {
+            let Some(found) = self.gaps.find_byte(range.end - 1) else {
+                return;
+            };
+            found
+        };
+
+        let covered_bytes = last_before.bytes + last_gap.bytes - before.bytes;
+        let covered_lines = last_before.line_feeds + last_gap.line_feeds - before.line_feeds;
+        let merged = Gap {
+            bytes: covered_bytes + len - range.len(),
+            line_feeds: covered_lines + line_feeds - removed_line_feeds,
+        };
+
+        // 合并后为空的间隔意味着两个检查点重合，只保留一个
+        let keep_empty = first == 0 && last + 1 == self.gaps.len();
+        let spans = if merged.bytes > 0 || keep_empty { vec![merged] } else { Vec::new() };
+        self.gaps.splice(first..last + 1, spans);
+    }
+
+    /// 重新扫描超出上限的检查点间隔
+    fn rebalance(&mut self, range: Range<usize>) {
+        let Some(source) = self.source.clone() else {
+            return;
+        };
+        let Some((first, _, before)) = self.gaps.find_byte(range.start) else {
+            return;
+        };
+        let Some((last, last_gap, last_before)) = self.gaps.find_byte(range.end.max(range.start + 1) - 1) else {
+            return;
+        };
+
+        // 间隔没有超过两倍上限时不必重新扫描
+        let oversized = self.gaps.iter_from(first).take(last + 1 - first).any(|gap| {
+            gap.bytes > 2 * self.config.bytes_per_checkpoint
+                || gap.line_feeds > 2 * self.config.lines_per_checkpoint
+        });
+        if !oversized {
+            return;
+        }
+
+        let start = Checkpoint { byte: before.bytes, line: before.line_feeds };
+        let end_byte = last_before.bytes + last_gap.bytes;
+        let mut scanner = SparseLineScanner::starting_at(self.config, start);
+        for slice in source.byte_slices(start.byte..end_byte) {
+            scanner.feed(&slice);
+        }
+
+        self.gaps.splice(first..last + 1, scanner.gaps());
+    }
+}
+
+// ========== 内部辅助 ==========
+
+impl SparseLines {
+    /// 偏移之前的换行数（从最近的检查点扫描）
+    fn line_feeds_before(&self, offset: usize) -> usize {
+        let Some((_, _, before)) = self.gaps.find_byte(offset) else {
+            return 0;
+        };
+
+        let counted = self.source.as_deref().map_or(0, |source| {
+            source
+                .byte_slices(before.bytes..offset)
+                .map(|slice| slice.iter().filter(|&&b| b == b'\n').count())
+                .sum()
+        });
+
+        before.line_feeds + counted
+    }
+
+    /// 第 n 个换行（0-based）所在间隔的起始检查点（没有时取最后一个检查点）
+    fn checkpoint_before_line_feed(&self, n: usize) -> Option<Checkpoint> {
+        let (_, _, before) = self.gaps.find_line_feed(n)?;
+        Some(Checkpoint { byte: before.bytes, line: before.line_feeds })
+    }
+
+    fn byte_at(&self, offset: usize) -> Option<u8> {
+        let source = self.source.as_deref()?;
+        source.byte_slices(offset..offset + 1).next()?.first().copied()
+    }
+}
+
+/// 从 from 开始第 n 个（n >= 1）换行符的位置
+fn nth_line_feed(source: &PieceTable, from: usize, n: usize) -> Option<usize> {
+    let mut remaining = n;
+    let mut pos = from;
+
+    for slice in source.byte_slices(from..source.total_bytes()) {
+        for (i, _) in slice.iter().enumerate().filter(|(_, &b)| b == b'\n') {
+            remaining -= 1;
+            if remaining == 0 {
+                return Some(pos + i);
+            }
+        }
+        pos += slice.len();
+    }
+
+    None
+}
+
+/// 流式稀疏行扫描器：逐块输入字节，按配置生成检查点
+#[derive(Debug, Clone)]
+pub struct SparseLineScanner {
+    config: SparseLineConfig,
+    checkpoints: Vec<Checkpoint>,
+    /// 当前位置
+    pos: usize,
+    /// 当前位置之前的换行数
+    line: usize,
+}
+
+impl SparseLineScanner {
+    pub fn new(config: SparseLineConfig) -> Self {
+        Self::starting_at(config, Checkpoint { byte: 0, line: 0 })
+    }
+
+    fn starting_at(config: SparseLineConfig, start: Checkpoint) -> Self {
+        Self {
+            config: SparseLineConfig {
+                lines_per_checkpoint: config.lines_per_checkpoint.max(1),
+                bytes_per_checkpoint: config.bytes_per_checkpoint.max(1),
+            },
+            checkpoints: vec![start],
+            pos: start.byte,
+            line: start.line,
+        }
+    }
+
+    /// 扫描一块字节
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let base = self.pos;
+        for (i, _) in bytes.iter().enumerate().filter(|(_, &b)| b == b'\n') {
+            let line_feed = base + i;
+            self.fill_to(line_feed + 1);
+
+            self.line += 1;
+            let last = self.last();
+            if self.line - last.line >= self.config.lines_per_checkpoint
+                || line_feed + 1 - last.byte >= self.config.bytes_per_checkpoint
+            {
+                self.checkpoints.push(Checkpoint { byte: line_feed + 1, line: self.line });
+            }
+        }
+
+        self.pos = base + bytes.len();
+        self.fill_to(self.pos);
+    }
+
+    /// 已扫描的字节数
+    pub fn scanned_bytes(&self) -> usize {
+        self.pos
+    }
+
+    /// 结束扫描，生成以 source 为数据源的稀疏索引
+    pub fn finish(self, source: Arc<PieceTable>) -> SparseLines {
+        SparseLines {
+            config: self.config,
+            gaps: SpanTree::from_spans(self.gaps()),
+            source: Some(source),
+            pending: None,
+        }
+    }
+
+    /// 相邻检查点之间（以及最后一个检查点到扫描位置）的间隔，跳过重合的检查点
+    fn gaps(&self) -> Vec<Gap> {
+        let end = Checkpoint { byte: self.pos, line: self.line };
+        let mut gaps: Vec<Gap> = self
+            .checkpoints
+            .iter()
+            .zip(self.checkpoints.iter().skip(1).chain([&end]))
+            .map(|(from, to)| Gap { bytes: to.byte - from.byte, line_feeds: to.line - from.line })
+            .filter(|gap| gap.bytes > 0)
+            .collect();
+        if gaps.is_empty() {
+            gaps.push(Gap { bytes: 0, line_feeds: 0 });
+        }
+        gaps
+    }
+
+    fn last(&self) -> Checkpoint {
+        *self.checkpoints.last().expect("至少有一个检查点")
+    }
+
+    /// 在没有换行的区间 [上一个检查点, end) 内按字节间隔补充检查点
+    fn fill_to(&mut self, end: usize) {
+        while self.last().byte + self.config.bytes_per_checkpoint < end {
+            let byte = self.last().byte + self.config.bytes_per_checkpoint;
+            self.checkpoints.push(Checkpoint { byte, line: self.line });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::Lines;
+
+    fn dense(text: &str) -> Lines {
+        let mut lines = Lines::new();
+        lines.build_from_text(text);
+        lines
+    }
+
+    fn sparse(table: &PieceTable, config: SparseLineConfig) -> SparseLines {
+        let mut scanner = SparseLineScanner::new(config);
+        for slice in table.byte_slices(0..table.total_bytes()) {
+            scanner.feed(&slice);
+        }
+        scanner.finish(Arc::new(table.clone()))
+    }
+
+    fn assert_same(sparse: &SparseLines, text: &str) {
+        let expected = dense(text);
+        assert_eq!(sparse.total_lines(), expected.total_lines());
+        for line in 0..=expected.total_lines() {
+            assert_eq!(sparse.get_line_range(line), expected.get_line_range(line), "line {}", line);
+        }
+        for offset in 0..=text.len() + 1 {
+            assert_eq!(sparse.find_line_by_offset(offset), expected.find_line_by_offset(offset));
+        }
+    }
+
+    #[test]
+    fn test_sparse_matches_dense() {
+        let config = SparseLineConfig { lines_per_checkpoint: 3, bytes_per_checkpoint: 16 };
+        let text = format!("a\nbb\n\n{}\nccc\n{}\nd\ne\nf\n", "x".repeat(70), "y".repeat(5));
+        let index = sparse(&PieceTable::from_text(&text), config);
+
+        assert!(index.checkpoint_count() > 5);
+        assert_same(&index, &text);
+        assert_same(&sparse(&PieceTable::from_text("no newline"), config), "no newline");
+        assert_same(&sparse(&PieceTable::new(), config), "");
+    }
+
+    #[test]
+    fn test_memory_is_bounded_by_config() {
+        let text = "short line\n".repeat(100_000);
+        let index = sparse(&PieceTable::from_text(&text), SparseLineConfig::default());
+
+        // 约 100_000 / 1024 个按行检查点，加上按字节的检查点
+        assert!(index.checkpoint_count() < 200);
+        assert_eq!(index.total_lines(), 100_000);
+        assert_eq!(index.get_line_range(99_999), Some(1_099_989..1_099_999));
+    }
+
+    #[test]
+    fn test_edits_keep_sparse_index_exact() {
+        let config = SparseLineConfig { lines_per_checkpoint: 4, bytes_per_checkpoint: 32 };
+        let long = "w".repeat(50);
+        let mut text = "line\n".repeat(40);
+        let mut table = PieceTable::from_text(&text);
+        let mut index = sparse(&table, config);
+
+        let mut seed = 11u64;
+        let mut next = |bound: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound.max(1)
+        };
+
+        for step in 0..300 {
+            if step % 3 == 2 && !text.is_empty() {
+                let start = next(text.len());
+                let end = (start + next(30)).min(text.len());
+                text.replace_range(start..end, "");
+                table = table.delete_char_safe(start..end).0;
+                index.handle_delete(start..end);
+            } else {
+                let offset = next(text.len() + 1);
+                let insert = ["z", "\n", "ab\ncd\n", &long][next(4)];
+                text.insert_str(offset, insert);
+                table = table.insert_char_safe(offset, insert).0;
+                index.handle_insert(offset, insert);
+            }
+            index.set_source(Arc::new(table.clone()));
+        }
+
+        assert_same(&index, &text);
+    }
+
+    #[test]
+    fn test_replacements_leave_previous_snapshot_intact() {
+        let config = SparseLineConfig { lines_per_checkpoint: 4, bytes_per_checkpoint: 32 };
+        let text = "ab\ncd\n\nefgh\n".repeat(30);
+        let table = PieceTable::from_text(&text);
+        let original = sparse(&table, config);
+
+        let edits: Vec<(Range<usize>, &str)> = (0..text.len() - 4)
+            .step_by(9)
+            .map(|start| (start..start + [0, 1, 4][start / 9 % 3], ["x\ny", "", "\n\n"][(start / 9 + 1) % 3]))
+            .collect();
+        let replacements: Vec<Replacement> = edits
+            .iter()
+            .map(|(range, insert)| Replacement {
+                range: range.clone(),
+                removed_line_feeds: text[range.clone()].matches('\n').count(),
+                text: insert.as_bytes(),
+            })
+            .collect();
+        let mut expected = text.clone();
+        for (range, insert) in edits.iter().rev() {
+            expected.replace_range(range.clone(), insert);
+        }
+
+        let mut edited = original.clone();
+        edited.handle_replacements(&replacements);
+        edited.set_source(Arc::new(PieceTable::from_text(&expected)));
+
+        assert_same(&edited, &expected);
+        assert_same(&original, &text);
+    }
+}
@@ -0,0 +1,586 @@
+// 文本游标
+//
+// 职责：从任意字节偏移开始，按字节/字符/字素簇/行双向移动，
+//       以零拷贝切片的形式遍历 PieceTable（搜索、词法分析、渲染使用）
+
+use unicode_segmentation::GraphemeCursor;
+
+use crate::core::buffer::{
+    piece_table::PieceTable,
+    byte_slice::ByteSlice,
+    utf8::Utf8Validator,
+};
+
+/// 字素簇查找的初始窗口（按需加倍）
+const GRAPHEME_WINDOW: usize = 64;
+/// 反向读取文本块时每次验证的末尾字节数
+const SUFFIX_WINDOW: usize = 4096;
+
+/// 移动单位
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorUnit {
+    Byte,
+    Char,
+    Grapheme,
+    Line,
+}
+
+/// 文本块：合法UTF-8文本，或无效字节
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextChunk<'a> {
+    /// 已验证的合法UTF-8
+    Text(ByteSlice<'a>),
+    Bytes(ByteSlice<'a>),
+}
+
+impl<'a> TextChunk<'a> {
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            TextChunk::Text(bytes) | TextChunk::Bytes(bytes) => bytes,
+        }
+    }
+
+    /// 合法文本（无效字节返回None）
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            // SAFETY: Text 只由已验证为合法UTF-8的切片构造
+            TextChunk::Text(bytes) => Some(unsafe { std::str::from_utf8_unchecked(bytes) }),
+            TextChunk::Bytes(_) => None,
+        }
+    }
+}
+
+/// 双向文本游标（借用文档快照，不复制内容）
+#[derive(Debug, Clone)]
+pub struct TextCursor<'a> {
+    table: &'a PieceTable,
+    offset: usize,
+}
+
+impl<'a> TextCursor<'a> {
+    /// 在指定偏移创建游标（超出时取文档末尾）
+    pub fn new(table: &'a PieceTable, offset: usize) -> Self {
+        Self {
+            table,
+            offset: offset.min(table.total_bytes()),
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// 跳转到指定偏移（超出时取文档末尾）
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset.min(self.table.total_bytes());
+    }
+
+    pub fn is_at_start(&self) -> bool {
+        self.offset == 0
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.offset == self.table.total_bytes()
+    }
+}
+
+// ========== 切片访问 ==========
+
+impl<'a> TextCursor<'a> {
+    /// 从游标到所在底层切片末尾的字节
+    pub fn chunk(&self) -> ByteSlice<'a> {
+        match self.table.slice_at(self.offset) {
+            Some((start, slice)) => slice.slice(self.offset - start..slice.len()),
+            None => ByteSlice::Borrowed(&[]),
+        }
+    }
+
+    /// 从所在底层切片开头到游标的字节
+    pub fn chunk_before(&self) -> ByteSlice<'a> {
+        if self.offset == 0 {
+            return ByteSlice::Borrowed(&[]);
+        }
+
+        match self.table.slice_at(self.offset - 1) {
+            Some((start, slice)) => slice.slice(0..self.offset - start),
+            None => ByteSlice::Borrowed(&[]),
+        }
+    }
+
+    /// 返回游标之后的切片并移动到其末尾
+    pub fn next_chunk(&mut self) -> Option<ByteSlice<'a>> {
+        let chunk = self.chunk();
+        if chunk.is_empty() {
+            return None;
+        }
+
+        self.offset += chunk.len();
+        Some(chunk)
+    }
+
+    /// 返回游标之前的切片并移动到其开头
+    pub fn prev_chunk(&mut self) -> Option<ByteSlice<'a>> {
+        let chunk = self.chunk_before();
+        if chunk.is_empty() {
+            return None;
+        }
+
+        self.offset -= chunk.len();
+        Some(chunk)
+    }
+
+    /// 返回游标之后的文本块并移动到其末尾（合法文本与无效字节分开返回）
+    ///
+    /// 跨两个切片的字符拼接后作为单独的文本块返回
+    pub fn next_text_chunk(&mut self) -> Option<TextChunk<'a>> {
+        self.next_text_chunk_within(self.table.total_bytes())
+    }
+
+    /// 同 next_text_chunk，但文本块不超过 limit（只验证 limit 之前的字节）
+    pub fn next_text_chunk_within(&mut self, limit: usize) -> Option<TextChunk<'a>> {
+        let remaining = limit.saturating_sub(self.offset);
+        let chunk = self.chunk();
+        let chunk = chunk.slice(0..chunk.len().min(remaining));
+        let chunk = match split_text_prefix(chunk)? {
+            TextChunk::Bytes(bytes) => self
+                .joined_char(true)
+                .filter(|joined| joined.len() <= remaining)
+                .unwrap_or(TextChunk::Bytes(bytes)),
+            text => text,
+        };
+        self.offset += chunk.len();
+        Some(chunk)
+    }
+
+    /// 返回游标之前的文本块并移动到其开头
+    pub fn prev_text_chunk(&mut self) -> Option<TextChunk<'a>> {
+        let chunk = match split_text_suffix(self.chunk_before())? {
+            TextChunk::Bytes(bytes) => self.joined_char(false).unwrap_or(TextChunk::Bytes(bytes)),
+            text => text,
+        };
+        self.offset -= chunk.len();
+        Some(chunk)
+    }
+
+    /// 游标之后（或之前）的合法字符，读取不受切片边界限制
+    ///
+    /// 切片内的合法字符已由切分处理，这里得到的只会是跨切片的字符
+    fn joined_char(&self, forward: bool) -> Option<TextChunk<'a>> {
+        let range = if forward {
+            self.offset..(self.offset + 4).min(self.table.total_bytes())
+        } else {
+            self.offset.saturating_sub(4)..self.offset
+        };
+        let bytes = self.table.get_bytes_range(range);
+
+        let len = if forward {
+            let valid = match std::str::from_utf8(&bytes) {
+                Ok(text) => text,
+                Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).ok()?,
+            };
+            valid.chars().next()?.len_utf8()
+        } else {
+            let start = bytes.iter().rposition(|&b| (b & 0xC0) != 0x80)?;
+            let text = std::str::from_utf8(&bytes[start..]).ok()?;
+            (text.chars().count() == 1).then_some(text.len())?
+        };
+
+        let joined = if forward { &bytes[..len] } else { &bytes[bytes.len() - len..] };
+        Some(TextChunk::Text(ByteSlice::Joined(joined.into())))
+    }
+}
+
+// ========== 移动 ==========
+
+impl<'a> TextCursor<'a> {
+    /// 向后移动一个单位，已在末尾时返回false
+    pub fn move_forward(&mut self, unit: CursorUnit) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+
+        self.offset = match unit {
+            CursorUnit::Byte => self.offset + 1,
+            CursorUnit::Char => self.next_char_boundary(),
+            CursorUnit::Grapheme => self.grapheme_boundary(true),
+            CursorUnit::Line => self.next_line_start(),
+        };
+        true
+    }
+
+    /// 向前移动一个单位，已在开头时返回false
+    pub fn move_backward(&mut self, unit: CursorUnit) -> bool {
+        if self.is_at_start() {
+            return false;
+        }
+
+        self.offset = match unit {
+            CursorUnit::Byte => self.offset - 1,
+            CursorUnit::Char => self.prev_char_boundary(),
+            CursorUnit::Grapheme => self.grapheme_boundary(false),
+            CursorUnit::Line => self.prev_line_start(),
+        };
+        true
+    }
+
+    /// 游标处的字符（无效字节返回None）
+    pub fn peek_char(&self) -> Option<char> {
+        let mut bytes = [0u8; 4];
+        let mut len = 0;
+        for slice in self.table.byte_slices(self.offset..self.offset + 4) {
+            bytes[len..len + slice.len()].copy_from_slice(&slice);
+            len += slice.len();
+        }
+
+        let valid = match std::str::from_utf8(&bytes[..len]) {
+            Ok(text) => text,
+            Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).ok()?,
+        };
+        valid.chars().next()
+    }
+
+    /// 读取下一个字符并前进（无效字节按单字节跳过并返回U+FFFD）
+    pub fn next_char(&mut self) -> Option<char> {
+        if self.is_at_end() {
+            return None;
+        }
+
+        let c = self.peek_char().unwrap_or(char::REPLACEMENT_CHARACTER);
+        self.move_forward(CursorUnit::Char);
+        Some(c)
+    }
+
+    /// 后退并读取前一个字符
+    pub fn prev_char(&mut self) -> Option<char> {
+        if !self.move_backward(CursorUnit::Char) {
+            return None;
+        }
+
+        Some(self.peek_char().unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn next_char_boundary(&self) -> usize {
+        // 合法字符之后就是边界，无需逐字节检查
+        if let Some(c) = self.peek_char() {
+            return self.offset + c.len_utf8();
+        }
+
+        let end = self.table.total_bytes();
+        let mut pos = self.offset + 1;
+        while pos < end && !self.table.is_char_boundary(pos) {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn prev_char_boundary(&self) -> usize {
+        let mut pos = self.offset - 1;
+        while pos > 0 && !self.table.is_char_boundary(pos) {
+            pos -= 1;
+        }
+        pos
+    }
+
+    fn next_line_start(&self) -> usize {
+        let line = self.table.line_of_offset(self.offset);
+        if line < self.table.line_feed_count() {
+            self.table.line_range(line + 1).start
+        } else {
+            self.table.total_bytes()
+        }
+    }
+
+    fn prev_line_start(&self) -> usize {
+        let line = self.table.line_of_offset(self.offset);
+        let start = self.table.line_range(line).start;
+        if start < self.offset || line == 0 {
+            start
+        } else {
+            self.table.line_range(line - 1).start
+        }
+    }
+
+    /// 查找相邻的字素簇边界
+    ///
+    /// 在偏移附近取窗口分段；边界落在被截断的窗口边缘、
+    /// 或窗口起点是区域指示符（国旗按奇偶配对）时加倍窗口重试
+    fn grapheme_boundary(&self, forward: bool) -> usize {
+        let total = self.table.total_bytes();
+        let mut window = GRAPHEME_WINDOW;
+
+        loop {
+            let mut start = self.offset.saturating_sub(window);
+            let end = (self.offset + window).min(total);
+            // 窗口起点不落在多字节字符内部
+            while start > 0 && !self.table.is_char_boundary(start) {
+                start += 1;
+            }
+
+            let bytes = self.table.get_bytes_range(start..end);
+            let mut spans = Vec::new();
+            let tail = Utf8Validator::scan_invalid(&bytes, start, &mut spans);
+            let valid_end = if end == total { end } else { end - tail };
+            if end == total && tail > 0 {
+                spans.push(end - tail..end);
+            }
+
+            // 无效字节各自是一个单元
+            let probe = if forward { self.offset } else { self.offset - 1 };
+            if spans.iter().any(|span| span.contains(&probe)) {
+                return if forward { self.offset + 1 } else { self.offset - 1 };
+            }
+
+            // 偏移所在的合法文本区间
+            let region_start = spans.iter().map(|s| s.end).filter(|&e| e <= self.offset).max().unwrap_or(start);
+            let region_end = spans.iter().map(|s| s.start).filter(|&s| s >= self.offset).min().unwrap_or(valid_end);
+            let text = std::str::from_utf8(&bytes[region_start - start..region_end - start])
+                .expect("区间内只有合法UTF-8");
+
+            let mut cursor = GraphemeCursor::new(self.offset - region_start, text.len(), true);
+            let boundary = if forward {
+                cursor.next_boundary(text, 0)
+            } else {
+                cursor.prev_boundary(text, 0)
+            };
+            let boundary = boundary.ok().flatten().map(|b| region_start + b);
+
+            let cut_start = region_start == start && start > 0;
+            let cut_end = region_end == valid_end && end < total;
+            let uncertain = match boundary {
+                None => true,
+                Some(b) => (cut_end && b == region_end) || (cut_start && b == region_start),
+            };
+            let regional_start = cut_start
+                && matches!(text.chars().next(), Some('\u{1F1E6}'..='\u{1F1FF}'));
+
+            if let Some(b) = boundary.filter(|_| !uncertain && !regional_start) {
+                return b;
+            }
+            if start == 0 && end == total {
+                return boundary.unwrap_or(if forward { total } else { 0 });
+            }
+            window *= 2;
+        }
+    }
+}
+
+/// 切分开头的合法文本或无效字节
+fn split_text_prefix(bytes: ByteSlice<'_>) -> Option<TextChunk<'_>> {
+    if bytes.is_empty() {
+        return None;
+    }
+
+    match std::str::from_utf8(&bytes) {
+        Ok(_) => Some(TextChunk::Text(bytes)),
+        Err(e) if e.valid_up_to() > 0 => Some(TextChunk::Text(bytes.slice(0..e.valid_up_to()))),
+        Err(e) => {
+            let len = e.error_len().unwrap_or(bytes.len());
+            Some(TextChunk::Bytes(bytes.slice(0..len)))
+        }
+    }
+}
+
+/// 切分末尾的合法文本或无效字节
+///
+/// 只验证末尾 SUFFIX_WINDOW 字节（起点后移到字符开头），
+/// 反向逐块读取时每个字节只验证一次
+fn split_text_suffix(bytes: ByteSlice<'_>) -> Option<TextChunk<'_>> {
+    if bytes.is_empty() {
+        return None;
+    }
+
+    // 窗口从字符中间开始时跳过至多3个连续字节，留给下一块
+    let mut start = bytes.len().saturating_sub(SUFFIX_WINDOW);
+    if start > 0 {
+        start += bytes[start..start + 3].iter().take_while(|&&b| (b & 0xC0) == 0x80).count();
+    }
+
+    let window = bytes.slice(start..bytes.len());
+    let spans = Utf8Validator::invalid_spans(&window);
+    match spans.last() {
+        Some(span) if span.end == window.len() => Some(TextChunk::Bytes(window.slice(span.clone()))),
+        Some(span) => Some(TextChunk::Text(window.slice(span.end..window.len()))),
+        None => Some(TextChunk::Text(window)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unicode_segmentation::UnicodeSegmentation;
+
+    fn edited_table() -> PieceTable {
+        // 三个Piece："hello " + "中文" + " world"
+        let table = PieceTable::from_text("hello  world");
+        table.insert_char_safe(6, "中文").0
+    }
+
+    #[test]
+    fn test_chunks_forward_and_backward_without_copy() {
+        let table = edited_table();
+        let mut cursor = TextCursor::new(&table, 0);
+
+        let forward: Vec<&[u8]> = std::iter::from_fn(|| cursor.next_chunk())
+            .map(|chunk| chunk.as_borrowed().expect("内存中的Piece直接借用"))
+            .collect();
+        assert_eq!(forward, vec![&b"hello "[..], "中文".as_bytes(), b" world"]);
+        assert!(cursor.is_at_end());
+
+        let backward: Vec<ByteSlice> = std::iter::from_fn(|| cursor.prev_chunk()).collect();
+        assert_eq!(backward, vec![&b" world"[..], "中文".as_bytes(), b"hello "]);
+
+        // 从Piece中间开始
+        let cursor = TextCursor::new(&table, 9);
+        assert_eq!(cursor.chunk(), "文".as_bytes());
+        assert_eq!(cursor.chunk_before(), "中".as_bytes());
+    }
+
+    #[test]
+    fn test_move_by_char_and_line() {
+        let table = PieceTable::from_text("ab\n中x\nlast");
+        let mut cursor = TextCursor::new(&table, 3);
+
+        assert_eq!(cursor.next_char(), Some('中'));
+        assert_eq!(cursor.offset(), 6);
+        assert_eq!(cursor.prev_char(), Some('中'));
+        assert_eq!(cursor.offset(), 3);
+
+        cursor.seek(4); // "中" 内部
+        cursor.move_backward(CursorUnit::Char);
+        assert_eq!(cursor.offset(), 3);
+
+        cursor.move_forward(CursorUnit::Line);
+        assert_eq!(cursor.offset(), 8);
+        cursor.move_forward(CursorUnit::Line);
+        assert_eq!(cursor.offset(), table.total_bytes());
+        assert!(!cursor.move_forward(CursorUnit::Line));
+
+        cursor.move_backward(CursorUnit::Line);
+        assert_eq!(cursor.offset(), 8);
+        cursor.move_backward(CursorUnit::Line);
+        assert_eq!(cursor.offset(), 3);
+    }
+
+    #[test]
+    fn test_move_by_grapheme() {
+        // 组合字符、ZWJ表情序列、国旗（两个区域指示符）
+        let text = "e\u{301}👨\u{200D}👩\u{200D}👧🇨🇳🇯🇵x";
+        let table = PieceTable::from_text(text);
+        let mut cursor = TextCursor::new(&table, 0);
+
+        let mut forward = vec![0];
+        while cursor.move_forward(CursorUnit::Grapheme) {
+            forward.push(cursor.offset());
+        }
+        let expected: Vec<usize> = text
+            .grapheme_indices(true)
+            .map(|(i, _)| i)
+            .chain([text.len()])
+            .collect();
+        assert_eq!(expected.len(), 6);
+        assert_eq!(forward, expected);
+
+        let mut backward = vec![table.total_bytes()];
+        while cursor.move_backward(CursorUnit::Grapheme) {
+            backward.push(cursor.offset());
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+    }
+
+    #[test]
+    fn test_text_chunks_separate_invalid_bytes() {
+        let table = PieceTable::from_bytes(b"ok\xFFfine");
+        let mut cursor = TextCursor::new(&table, 0);
+
+        let text = |bytes: &'static [u8]| Some(TextChunk::Text(ByteSlice::Borrowed(bytes)));
+        let invalid = |bytes: &'static [u8]| Some(TextChunk::Bytes(ByteSlice::Borrowed(bytes)));
+
+        assert_eq!(cursor.next_text_chunk(), text(b"ok"));
+        assert_eq!(cursor.next_text_chunk(), invalid(b"\xFF"));
+        let fine = cursor.next_text_chunk().unwrap();
+        assert_eq!(fine.as_str(), Some("fine"));
+        assert_eq!(cursor.next_text_chunk(), None);
+
+        assert_eq!(cursor.prev_text_chunk(), text(b"fine"));
+        assert_eq!(cursor.prev_text_chunk(), invalid(b"\xFF"));
+        cursor.move_backward(CursorUnit::Grapheme);
+        assert_eq!(cursor.offset(), 1);
+
+        // 限定范围时不读取范围之后的字节
+        cursor.seek(3);
+        assert_eq!(cursor.next_text_chunk_within(5), text(b"fi"));
+        assert_eq!(cursor.next_text_chunk_within(5), None);
+        assert_eq!(cursor.offset(), 5);
+    }
+
+    #[test]
+    fn test_backward_text_chunks_validate_bounded_tail() {
+        let mut content = b"\xFF".to_vec();
+        content.extend_from_slice("中".repeat(3000).as_bytes());
+        let table = PieceTable::from_bytes(&content);
+        let mut cursor = TextCursor::new(&table, table.total_bytes());
+
+        // 每块只取末尾的窗口，起点落在字符开头
+        let last = cursor.prev_text_chunk().unwrap();
+        assert!(last.len() <= SUFFIX_WINDOW && last.len() % 3 == 0);
+        assert!(last.as_str().is_some());
+        assert_eq!(cursor.offset(), table.total_bytes() - last.len());
+        assert_eq!(collect_text_chunks(&table, false).0, content);
+    }
+
+    /// 依次取出所有文本块，拼接后的内容与各块是否都是合法文本
+    fn collect_text_chunks(table: &PieceTable, forward: bool) -> (Vec<u8>, bool) {
+        let mut cursor = TextCursor::new(table, if forward { 0 } else { table.total_bytes() });
+        let mut chunks = Vec::new();
+        while let Some(chunk) = if forward { cursor.next_text_chunk() } else { cursor.prev_text_chunk() } {
+            chunks.push(chunk);
+        }
+        if !forward {
+            chunks.reverse();
+        }
+
+        let all_text = chunks.iter().all(|chunk| chunk.as_str().is_some());
+        (chunks.iter().flat_map(|chunk| chunk.as_bytes().to_vec()).collect(), all_text)
+    }
+
+    #[test]
+    fn test_text_chunks_join_chars_split_across_slices() {
+        // Piece从字符中间切开
+        let table = PieceTable::from_text("中");
+        let (table, _) = table.insert_slice(3, &table.piece_slice(0..1));
+        let (table, _) = table.insert_slice(4, &table.piece_slice(1..3));
+        assert_eq!(collect_text_chunks(&table, true), ("中中".as_bytes().to_vec(), true));
+        assert_eq!(collect_text_chunks(&table, false), ("中中".as_bytes().to_vec(), true));
+
+        // 64KB窗口的边缘落在三字节字符中间
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use crate::core::buffer::{MmapBuffer, WindowConfig};
+
+            let content = "中".repeat(50_000);
+            let path = std::env::temp_dir().join(format!("zedit_cursor_windowed_{}", std::process::id()));
+            std::fs::write(&path, &content).unwrap();
+            let config = WindowConfig { window_size: 1, budget: 2 * 64 * 1024 };
+            let table = PieceTable::from_mapped(MmapBuffer::from_file_windowed(&path, config).unwrap());
+
+            assert_eq!(collect_text_chunks(&table, true), (content.as_bytes().to_vec(), true));
+            assert_eq!(collect_text_chunks(&table, false), (content.as_bytes().to_vec(), true));
+
+            let mut cursor = TextCursor::new(&table, 64 * 1024 - 1);
+            assert_eq!(cursor.next_text_chunk().and_then(|chunk| chunk.as_str().map(str::to_owned)), Some("中".to_owned()));
+            assert_eq!(cursor.offset(), 64 * 1024 + 2);
+
+            drop(table);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -4,6 +4,8 @@
 
 use std::ops::Range;
 
+use unicode_segmentation::GraphemeCursor;
+
 /// UTF-8边界安全工具
 #[derive(Debug, Clone, Copy)]
 pub struct Utf8Validator;
@@ -66,6 +68,145 @@ impl Utf8Validator {
     }
 }
 
+// ========== 无效字节处理 ==========
+
+impl Utf8Validator {
+    /// 首字节声明的序列长度（连续字节或非法首字节返回None）
+    pub fn sequence_len(lead: u8) -> Option<usize> {
+        match lead {
+            0x00..=0x7F => Some(1),
+            0xC2..=0xDF => Some(2),
+            0xE0..=0xEF => Some(3),
+            0xF0..=0xF4 => Some(4),
+            _ => None,
+        }
+    }
+
+    /// 扫描字节序列，把无效UTF-8区间（加上 base 偏移）追加到 spans，相邻区间合并
+    ///
+    /// 返回末尾不完整序列的长度：调用方可与后续字节拼接后重新扫描，
+    /// 若已是输入末尾则应视为无效
+    pub fn scan_invalid(bytes: &[u8], base: usize, spans: &mut Vec<Range<usize>>) -> usize {
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            match std::str::from_utf8(&bytes[pos..]) {
+                Ok(_) => return 0,
+                Err(e) => {
+                    let start = pos + e.valid_up_to();
+                    let Some(len) = e.error_len() else {
+                        return bytes.len() - start;
+                    };
+                    Self::push_span(spans, base + start..base + start + len);
+                    pos = start + len;
+                }
+            }
+        }
+
+        0
+    }
+
+    /// 完整输入中的无效UTF-8区间
+    pub fn invalid_spans(bytes: &[u8]) -> Vec<Range<usize>> {
+        let mut spans = Vec::new();
+        let tail = Self::scan_invalid(bytes, 0, &mut spans);
+        if tail > 0 {
+            Self::push_span(&mut spans, bytes.len() - tail..bytes.len());
+        }
+        spans
+    }
+
+    /// 转换为文本，无效字节显示为 `\xNN` 转义
+    pub fn escape_invalid(bytes: &[u8]) -> String {
+        let mut text = String::with_capacity(bytes.len());
+        let mut pos = 0;
+
+        for span in Self::invalid_spans(bytes) {
+            // 区间之间的字节已验证为合法UTF-8
+            text.push_str(std::str::from_utf8(&bytes[pos..span.start]).unwrap_or_default());
+            for byte in &bytes[span.clone()] {
+                text.push_str(&format!("\\x{:02X}", byte));
+            }
+            pos = span.end;
+        }
+        text.push_str(std::str::from_utf8(&bytes[pos..]).unwrap_or_default());
+
+        text
+    }
+
+    /// 追加区间，与上一个相邻时合并
+    pub(super) fn push_span(spans: &mut Vec<Range<usize>>, span: Range<usize>) {
+        match spans.last_mut() {
+            Some(last) if last.end == span.start => last.end = span.end,
+            _ => spans.push(span),
+        }
+    }
+}
+
+// ========== 字素簇边界（UAX #29） ==========
+
+impl Utf8Validator {
+    /// 是否是扩展字素簇边界（开头和末尾都是边界）
+    pub fn is_grapheme_boundary(text: &str, byte_offset: usize) -> bool {
+        if byte_offset > text.len() || !text.is_char_boundary(byte_offset) {
+            return false;
+        }
+
+        GraphemeCursor::new(byte_offset, text.len(), true)
+            .is_boundary(text, 0)
+            .unwrap_or(true)
+    }
+
+    /// 偏移之前最近的字素簇边界（已在开头时返回0）
+    pub fn prev_grapheme_boundary(text: &str, byte_offset: usize) -> usize {
+        let offset = byte_offset.min(text.len());
+        let safe = Self::ensure_char_boundary(text, offset);
+        if safe < offset {
+            return Self::ensure_grapheme_boundary(text, safe);
+        }
+
+        GraphemeCursor::new(safe, text.len(), true)
+            .prev_boundary(text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(0)
+    }
+
+    /// 偏移之后最近的字素簇边界（已在末尾时返回文本长度）
+    pub fn next_grapheme_boundary(text: &str, byte_offset: usize) -> usize {
+        let safe = Self::ensure_char_boundary(text, byte_offset.min(text.len()));
+
+        GraphemeCursor::new(safe, text.len(), true)
+            .next_boundary(text, 0)
+            .ok()
+            .flatten()
+            .unwrap_or(text.len())
+    }
+
+    /// 将字节偏移向前调整到所在字素簇的起点
+    pub fn ensure_grapheme_boundary(text: &str, byte_offset: usize) -> usize {
+        let safe = Self::ensure_char_boundary(text, byte_offset.min(text.len()));
+        if Self::is_grapheme_boundary(text, safe) {
+            safe
+        } else {
+            Self::prev_grapheme_boundary(text, safe)
+        }
+    }
+
+    /// 把范围扩展到完整的字素簇（起点向前、终点向后）
+    pub fn expand_to_grapheme_boundaries(text: &str, range: Range<usize>) -> Range<usize> {
+        let start = Self::ensure_grapheme_boundary(text, range.start);
+        let end = range.end.min(text.len()).max(start);
+        let end = if Self::is_grapheme_boundary(text, end) {
+            end
+        } else {
+            Self::next_grapheme_boundary(text, end)
+        };
+
+        start..end
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,6 +231,41 @@ mod tests {
         assert_eq!(range, 6..9); // "世"的完整范围
     }
 
+    #[test]
+    fn test_invalid_spans_and_escape() {
+        // Latin-1 的 "é"（0xE9）、孤立连续字节、截断的多字节序列
+        let bytes = b"caf\xE9 ok \x80\x81 \xE4\xB8";
+
+        assert_eq!(Utf8Validator::invalid_spans(bytes), vec![3..4, 8..10, 11..13]);
+        assert_eq!(Utf8Validator::escape_invalid(bytes), "caf\\xE9 ok \\x80\\x81 \\xE4\\xB8");
+        assert!(Utf8Validator::invalid_spans("Hello 世界".as_bytes()).is_empty());
+    }
+
+    #[test]
+    fn test_grapheme_boundaries() {
+        // 家庭emoji(ZWJ序列，18字节) + 两面国旗(各8字节) + e+组合重音(3字节) + 韩文字母组合(3×3字节)
+        let text = "a👨\u{200D}👩\u{200D}👧🇨🇳🇯🇵e\u{301}\u{1100}\u{1161}\u{11A8}";
+        let boundaries = [0, 1, 19, 27, 35, 38, 47];
+
+        let mut offset = 0;
+        for &expected in &boundaries[1..] {
+            offset = Utf8Validator::next_grapheme_boundary(text, offset);
+            assert_eq!(offset, expected);
+        }
+        for &expected in boundaries[..boundaries.len() - 1].iter().rev() {
+            offset = Utf8Validator::prev_grapheme_boundary(text, offset);
+            assert_eq!(offset, expected);
+        }
+
+        // 簇内部（含多字节字符内部）调整到簇起点
+        assert!(!Utf8Validator::is_grapheme_boundary(text, 5));
+        assert_eq!(Utf8Validator::ensure_grapheme_boundary(text, 6), 1);
+        assert_eq!(Utf8Validator::ensure_grapheme_boundary(text, 31), 27);
+        assert_eq!(Utf8Validator::expand_to_grapheme_boundaries(text, 36..40), 35..47);
+        assert_eq!(Utf8Validator::prev_grapheme_boundary(text, 37), 35);
+        assert_eq!(Utf8Validator::next_grapheme_boundary(text, 37), 38);
+    }
+
     #[test]
     fn test_safe_substr() {
         let text = "Hello 世界";
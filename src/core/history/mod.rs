@@ -0,0 +1,15 @@
+// History - 撤销历史
+//
+// 职责：以 PieceTable 快照记录每个事务前后的状态，
+//       支持线性撤销/重做、撤销树分支、跳转与容量限制
+
+mod revision;
+mod undo_tree;
+
+pub use self::revision::{CursorState, Revision, RevisionId};
+pub use self::undo_tree::{History, HistoryConfig};
+
+/// 默认最多保留的撤销步数（功能清单要求 ≥50）
+pub const DEFAULT_MAX_UNDO_STEPS: usize = 200;
+/// 默认撤销历史内存上限
+pub const DEFAULT_MAX_HISTORY_MEMORY: usize = 64 * 1024 * 1024; // 64MB
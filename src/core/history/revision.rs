@@ -0,0 +1,92 @@
+// 历史版本
+//
+// 职责：描述撤销树中的单个节点（某次事务之后的文档版本）
+
+use std::ops::Range;
+use std::time::Instant;
+
+use crate::core::buffer::PieceTable;
+
+/// 版本ID（在同一个History内单调递增）
+pub type RevisionId = u64;
+
+/// 光标状态（字节偏移）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CursorState {
+    /// 光标位置
+    pub offset: usize,
+    /// 选区范围（无选区时为None）
+    pub selection: Option<Range<usize>>,
+}
+
+impl CursorState {
+    /// 无选区的光标
+    pub fn at(offset: usize) -> Self {
+        Self {
+            offset,
+            selection: None,
+        }
+    }
+
+    /// 带选区的光标
+    pub fn with_selection(offset: usize, selection: Range<usize>) -> Self {
+        Self {
+            offset,
+            selection: Some(selection),
+        }
+    }
+}
+
+/// 撤销树节点
+#[derive(Debug, Clone)]
+pub struct Revision {
+    pub(super) id: RevisionId,
+    pub(super) parent: Option<RevisionId>,
+    pub(super) children: Vec<RevisionId>,
+    /// 重做时进入的子节点（最近访问的分支）
+    pub(super) redo_child: Option<RevisionId>,
+    /// 事务完成后的文档快照
+    pub(super) table: PieceTable,
+    /// 事务开始前的光标（撤销后恢复）
+    pub(super) cursor_before: CursorState,
+    /// 事务完成后的光标（重做后恢复）
+    pub(super) cursor_after: CursorState,
+    pub(super) timestamp: Instant,
+    /// 相对父版本新增的估计内存
+    pub(super) memory_cost: usize,
+}
+
+impl Revision {
+    pub fn id(&self) -> RevisionId {
+        self.id
+    }
+
+    pub fn parent(&self) -> Option<RevisionId> {
+        self.parent
+    }
+
+    /// 所有分支（按创建顺序）
+    pub fn children(&self) -> &[RevisionId] {
+        &self.children
+    }
+
+    pub fn table(&self) -> &PieceTable {
+        &self.table
+    }
+
+    pub fn cursor_before(&self) -> &CursorState {
+        &self.cursor_before
+    }
+
+    pub fn cursor_after(&self) -> &CursorState {
+        &self.cursor_after
+    }
+
+    pub fn timestamp(&self) -> Instant {
+        self.timestamp
+    }
+
+    pub fn memory_cost(&self) -> usize {
+        self.memory_cost
+    }
+}
@@ -0,0 +1,533 @@
+// 撤销树
+//
+// 职责：保存每个事务之后的 PieceTable 快照，
+//       撤销后重新编辑时保留旧分支，按步数和内存上限淘汰最旧版本
+
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use crate::core::buffer::{AddBuffer, PieceEntry, PieceTable};
+use crate::core::history::revision::{CursorState, Revision, RevisionId};
+use crate::core::history::{DEFAULT_MAX_HISTORY_MEMORY, DEFAULT_MAX_UNDO_STEPS};
+
+/// 每个版本的固定开销估计（版本记录本身 + 被复制的树路径）
+const REVISION_OVERHEAD: usize = 1024;
+
+/// 历史容量配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryConfig {
+    /// 最多保留的版本数（不含根版本）
+    pub max_steps: usize,
+    /// 历史占用内存上限（字节，估计值）
+    pub max_memory: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_steps: DEFAULT_MAX_UNDO_STEPS,
+            max_memory: DEFAULT_MAX_HISTORY_MEMORY,
+        }
+    }
+}
+
+/// 撤销历史（撤销树）
+#[derive(Debug, Clone)]
+pub struct History {
+    revisions: HashMap<RevisionId, Revision>,
+    /// 最旧的保留版本
+    root: RevisionId,
+    /// 当前版本
+    current: RevisionId,
+    next_id: RevisionId,
+    config: HistoryConfig,
+    /// 所有版本估计内存之和
+    memory_used: usize,
+    /// 上次记录时见到的追加缓冲区及其长度
+    ///
+    /// 所有快照共享同一个追加缓冲区，比较两个快照的 estimated_memory 得不到
+    /// 新增的内容，只能看缓冲区在两次记录之间增长了多少
+    additions: AddBuffer,
+    additions_seen: usize,
+}
+
+// ========== 构造方法 ==========
+
+impl History {
+    /// 以初始文档创建历史（默认容量）
+    pub fn new(table: PieceTable, cursor: CursorState) -> Self {
+        Self::with_config(table, cursor, HistoryConfig::default())
+    }
+
+    /// 以初始文档和容量配置创建历史
+    pub fn with_config(table: PieceTable, cursor: CursorState, config: HistoryConfig) -> Self {
+        let additions = table.add_buffer().clone();
+        let additions_seen = additions.len();
+        let root = Revision {
+            id: 0,
+            parent: None,
+            children: Vec::new(),
+            redo_child: None,
+            table,
+            cursor_before: cursor.clone(),
+            cursor_after: cursor,
+            timestamp: Instant::now(),
+            memory_cost: 0,
+        };
+
+        Self {
+            revisions: HashMap::from([(0, root)]),
+            root: 0,
+            current: 0,
+            next_id: 1,
+            config,
+            memory_used: 0,
+            additions,
+            additions_seen,
+        }
+    }
+}
+
+// ========== 记录与导航 ==========
+
+impl History {
+    /// 记录一个事务完成后的版本，返回新版本ID
+    ///
+    /// 新版本成为当前版本的子节点；当前版本已有的子节点作为旧分支保留
+    pub fn record(
+        &mut self,
+        table: PieceTable,
+        cursor_before: CursorState,
+        cursor_after: CursorState,
+    ) -> RevisionId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let appended = self.take_appended(&table);
+        let parent = self.revisions.get_mut(&self.current).expect("当前版本必须存在");
+        let memory_cost = appended + piece_growth(&parent.table, &table) + REVISION_OVERHEAD;
+        parent.children.push(id);
+        parent.redo_child = Some(id);
+
+        self.revisions.insert(id, Revision {
+            id,
+            parent: Some(self.current),
+            children: Vec::new(),
+            redo_child: None,
+            table,
+            cursor_before,
+            cursor_after,
+            timestamp: Instant::now(),
+            memory_cost,
+        });
+        self.memory_used += memory_cost;
+        self.current = id;
+
+        self.enforce_limits();
+        id
+    }
+
+    /// 用新的文档覆盖当前版本（用于合并连续输入）
+    ///
+    /// 只有当前版本是没有分支的叶子时才能修改，否则返回false
+    pub fn amend_current(&mut self, table: PieceTable, cursor_after: CursorState) -> bool {
+        let current = match self.revisions.get(&self.current) {
+            Some(current) if current.parent.is_some() && current.children.is_empty() => current,
+            _ => return false,
+        };
+        let parent = current.parent.and_then(|id| self.revisions.get(&id)).map(|parent| &parent.table);
+        // 保留之前累计的追加内容，Piece增长按新文档重新计算
+        let previous_growth = parent.map_or(0, |parent| piece_growth(parent, &current.table));
+        let growth = parent.map_or(0, |parent| piece_growth(parent, &table));
+        let memory_cost = current.memory_cost.saturating_sub(previous_growth) + growth;
+        let memory_cost = memory_cost + self.take_appended(&table);
+
+        let current = self.revisions.get_mut(&self.current).expect("当前版本必须存在");
+        self.memory_used = self.memory_used - current.memory_cost + memory_cost;
+        current.table = table;
+        current.cursor_after = cursor_after;
+        current.timestamp = Instant::now();
+        current.memory_cost = memory_cost;
+
+        self.enforce_limits();
+        true
+    }
+
+    /// 用内容相同的文档替换当前版本（保存后压缩），光标与分支不变
+    ///
+    /// 长度不一致时返回false
+    pub fn rebase_current(&mut self, table: PieceTable) -> bool {
+        let current = &self.revisions[&self.current];
+        if current.table.total_bytes() != table.total_bytes() {
+            return false;
+        }
+        // 内容不变，追加的内容仍由其他版本引用，开销保持不变
+        self.take_appended(&table);
+        let current = self.revisions.get_mut(&self.current).expect("当前版本必须存在");
+        current.table = table;
+        true
+    }
+
+    /// 撤销：回到父版本，返回其文档和撤销后的光标
+    pub fn undo(&mut self) -> Option<(PieceTable, CursorState)> {
+        let undone = self.revisions.get(&self.current)?;
+        let parent_id = undone.parent?;
+        let cursor = undone.cursor_before.clone();
+        let undone_id = undone.id;
+
+        let parent = self.revisions.get_mut(&parent_id)?;
+        parent.redo_child = Some(undone_id);
+        self.current = parent_id;
+
+        Some((parent.table.clone(), cursor))
+    }
+
+    /// 重做：进入最近访问的分支，返回其文档和光标
+    pub fn redo(&mut self) -> Option<(PieceTable, CursorState)> {
+        let current = self.revisions.get(&self.current)?;
+        let child_id = current.redo_child.or_else(|| current.children.last().copied())?;
+        let child = self.revisions.get(&child_id)?;
+
+        self.current = child_id;
+        Some((child.table.clone(), child.cursor_after.clone()))
+    }
+
+    /// 跳转到任意保留的版本（包括其他分支）
+    ///
+    /// 跳转后从根到目标路径上的重做方向都指向该分支
+    pub fn jump_to(&mut self, id: RevisionId) -> Option<(PieceTable, CursorState)> {
+        let target = self.revisions.get(&id)?;
+        let result = (target.table.clone(), target.cursor_after.clone());
+
+        let mut child = id;
+        while let Some(parent_id) = self.revisions.get(&child).and_then(|r| r.parent) {
+            if let Some(parent) = self.revisions.get_mut(&parent_id) {
+                parent.redo_child = Some(child);
+            }
+            child = parent_id;
+        }
+
+        self.current = id;
+        Some(result)
+    }
+
+    /// 清空历史，以当前版本作为新的根
+    pub fn clear(&mut self) {
+        let mut current = self.revisions.remove(&self.current).expect("当前版本必须存在");
+        current.parent = None;
+        current.children.clear();
+        current.redo_child = None;
+        current.memory_cost = 0;
+
+        self.root = current.id;
+        self.revisions = HashMap::from([(current.id, current)]);
+        self.memory_used = 0;
+    }
+
+    /// 原始文件在外部被修改时，为所有版本建立私有副本（撤销后仍能读取旧内容）
+    pub fn detach_original(&mut self) -> Result<(), String> {
+        let ids: Vec<RevisionId> = self.revisions.keys().copied().collect();
+        let tables: Vec<&PieceTable> = ids.iter().map(|id| &self.revisions[id].table).collect();
+        let detached = PieceTable::detach_originals(&tables)?;
+
+        for (id, table) in ids.into_iter().zip(detached) {
+            if let Some(revision) = self.revisions.get_mut(&id) {
+                revision.table = table;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ========== 内存估计 ==========
+
+impl History {
+    /// 自上次记录以来追加缓冲区新增的字节数
+    ///
+    /// 快照换用了另一个追加缓冲区（如保存后重建）时整个缓冲区都计入
+    fn take_appended(&mut self, table: &PieceTable) -> usize {
+        let additions = table.add_buffer();
+        let len = additions.len();
+        let appended = if additions.shares_storage_with(&self.additions) {
+            len.saturating_sub(self.additions_seen)
+        } else {
+            len
+        };
+
+        self.additions = additions.clone();
+        self.additions_seen = len;
+        appended
+    }
+}
+
+/// 相对父版本新增的Piece占用
+fn piece_growth(parent: &PieceTable, table: &PieceTable) -> usize {
+    table.piece_count().saturating_sub(parent.piece_count()) * std::mem::size_of::<PieceEntry>()
+}
+
+// ========== 查询 ==========
+
+impl History {
+    /// 当前版本
+    pub fn current(&self) -> &Revision {
+        &self.revisions[&self.current]
+    }
+
+    /// 当前版本ID
+    pub fn current_id(&self) -> RevisionId {
+        self.current
+    }
+
+    /// 当前文档
+    pub fn current_table(&self) -> &PieceTable {
+        &self.current().table
+    }
+
+    /// 最旧的保留版本ID
+    pub fn root_id(&self) -> RevisionId {
+        self.root
+    }
+
+    /// 获取指定版本
+    pub fn revision(&self, id: RevisionId) -> Option<&Revision> {
+        self.revisions.get(&id)
+    }
+
+    /// 所有保留的版本（按创建顺序）
+    pub fn revisions(&self) -> Vec<&Revision> {
+        let mut revisions: Vec<&Revision> = self.revisions.values().collect();
+        revisions.sort_by_key(|r| r.id);
+        revisions
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.current().parent.is_some()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.current().children.is_empty()
+    }
+
+    /// 从当前版本可连续撤销的步数
+    pub fn undo_depth(&self) -> usize {
+        self.path_to_root().len() - 1
+    }
+
+    /// 保留的版本数（含根版本）
+    pub fn len(&self) -> usize {
+        self.revisions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.revisions.is_empty()
+    }
+
+    /// 历史占用的估计内存
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    pub fn config(&self) -> &HistoryConfig {
+        &self.config
+    }
+
+    /// 修改容量配置（立即按新上限淘汰）
+    pub fn set_config(&mut self, config: HistoryConfig) {
+        self.config = config;
+        self.enforce_limits();
+    }
+}
+
+// ========== 容量限制 ==========
+
+impl History {
+    /// 当前版本到根的路径（含两端）
+    fn path_to_root(&self) -> Vec<RevisionId> {
+        let mut path = vec![self.current];
+        let mut id = self.current;
+        while let Some(parent) = self.revisions.get(&id).and_then(|r| r.parent) {
+            path.push(parent);
+            id = parent;
+        }
+        path
+    }
+
+    fn is_over_limit(&self) -> bool {
+        self.revisions.len() - 1 > self.config.max_steps
+            || self.memory_used > self.config.max_memory
+    }
+
+    fn enforce_limits(&mut self) {
+        while self.is_over_limit() {
+            if !self.remove_oldest() {
+                break;
+            }
+        }
+    }
+
+    /// 淘汰最旧的可移除版本
+    ///
+    /// 可移除的版本：不在当前路径上的分支叶子，
+    /// 或者只剩一个子节点且不是当前版本的根
+    fn remove_oldest(&mut self) -> bool {
+        let path: HashSet<RevisionId> = self.path_to_root().into_iter().collect();
+
+        let oldest = self
+            .revisions
+            .values()
+            .filter(|r| {
+                if r.id == self.root {
+                    r.children.len() == 1 && r.id != self.current
+                } else {
+                    r.children.is_empty() && !path.contains(&r.id)
+                }
+            })
+            .map(|r| r.id)
+            .min();
+
+        let Some(id) = oldest else {
+            return false;
+        };
+
+        let removed = self.revisions.remove(&id).expect("候选版本必须存在");
+        self.memory_used = self.memory_used.saturating_sub(removed.memory_cost);
+
+        if id == self.root {
+            // 唯一子节点成为新的根
+            let new_root = removed.children[0];
+            if let Some(child) = self.revisions.get_mut(&new_root) {
+                child.parent = None;
+            }
+            self.root = new_root;
+        } else if let Some(parent) = removed.parent.and_then(|p| self.revisions.get_mut(&p)) {
+            parent.children.retain(|&c| c != id);
+            if parent.redo_child == Some(id) {
+                parent.redo_child = parent.children.last().copied();
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(table: &PieceTable) -> String {
+        table.get_text_range(0..table.total_bytes())
+    }
+
+    /// 在当前版本末尾追加文本并记录
+    fn type_text(history: &mut History, s: &str) -> RevisionId {
+        let table = history.current_table().clone();
+        let offset = table.total_bytes();
+        let (table, _) = table.insert_char_safe(offset, s);
+        history.record(table, CursorState::at(offset), CursorState::at(offset + s.len()))
+    }
+
+    #[test]
+    fn test_linear_undo_redo() {
+        let mut history = History::new(PieceTable::from_text("a"), CursorState::at(1));
+        type_text(&mut history, "b");
+        type_text(&mut history, "c");
+
+        let (table, cursor) = history.undo().unwrap();
+        assert_eq!(text(&table), "ab");
+        assert_eq!(cursor, CursorState::at(2));
+
+        let (table, _) = history.undo().unwrap();
+        assert_eq!(text(&table), "a");
+        assert!(history.undo().is_none());
+
+        let (table, cursor) = history.redo().unwrap();
+        assert_eq!(text(&table), "ab");
+        assert_eq!(cursor, CursorState::at(2));
+        assert_eq!(history.undo_depth(), 1);
+    }
+
+    #[test]
+    fn test_branches_are_kept() {
+        let mut history = History::new(PieceTable::from_text(""), CursorState::default());
+        let first = type_text(&mut history, "x");
+        let abandoned = type_text(&mut history, "y");
+
+        history.undo();
+        let branch = type_text(&mut history, "z");
+
+        // 旧分支仍然保留，可以跳转回去
+        assert_eq!(history.revision(first).unwrap().children(), &[abandoned, branch]);
+        let (table, _) = history.jump_to(abandoned).unwrap();
+        assert_eq!(text(&table), "xy");
+
+        // 跳转后撤销再重做沿着跳转的分支
+        history.undo();
+        let (table, _) = history.redo().unwrap();
+        assert_eq!(text(&table), "xy");
+
+        let (table, _) = history.jump_to(history.root_id()).unwrap();
+        assert_eq!(text(&table), "");
+    }
+
+    #[test]
+    fn test_step_limit_drops_oldest() {
+        let config = HistoryConfig {
+            max_steps: 50,
+            ..HistoryConfig::default()
+        };
+        let mut history = History::with_config(PieceTable::new(), CursorState::default(), config);
+
+        for i in 0..80 {
+            type_text(&mut history, &format!("{} ", i));
+        }
+
+        assert_eq!(history.len(), 51);
+        let mut steps = 0;
+        while history.undo().is_some() {
+            steps += 1;
+        }
+        assert_eq!(steps, 50);
+        // 最旧的保留版本是第30次记录之后的状态
+        assert!(text(history.current_table()).ends_with(" 28 29 "));
+    }
+
+    #[test]
+    fn test_memory_limit_prunes_abandoned_branches_first() {
+        let config = HistoryConfig {
+            max_steps: 100,
+            max_memory: 3 * REVISION_OVERHEAD + 500,
+        };
+        let mut history = History::with_config(PieceTable::from_text("base"), CursorState::default(), config);
+
+        let a = type_text(&mut history, "a");
+        history.undo();
+        let b = type_text(&mut history, "b");
+        type_text(&mut history, "c");
+        type_text(&mut history, "d");
+
+        // 分支 a 最旧，先被淘汰；当前路径保持完整
+        assert!(history.revision(a).is_none());
+        assert!(history.revision(b).is_some());
+        assert!(history.memory_used() <= config.max_memory);
+        assert_eq!(text(history.current_table()), "basebcd");
+    }
+
+    #[test]
+    fn test_large_insert_is_charged_by_content() {
+        let config = HistoryConfig {
+            max_steps: 100,
+            max_memory: 1024 * 1024,
+        };
+        let mut history = History::with_config(PieceTable::from_text("base"), CursorState::default(), config);
+
+        let small = type_text(&mut history, "x");
+        assert!(history.revision(small).unwrap().memory_cost() < 2 * REVISION_OVERHEAD);
+
+        // 大段粘贴按实际追加的字节计入，超过上限时淘汰旧版本
+        let large = type_text(&mut history, &"paste".repeat(300 * 1024));
+        assert!(history.revision(large).unwrap().memory_cost() >= 1500 * 1024);
+        assert!(history.revision(small).is_none());
+        assert!(history.revision(large).is_some());
+        assert_eq!(history.undo_depth(), 0);
+    }
+}
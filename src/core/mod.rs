@@ -4,5 +4,11 @@
 //       接收 EditorAction，通过事务模型更新状态
 
 pub mod buffer;
+pub mod history;
+pub mod selection;
+pub mod transaction;
 
 pub use buffer::{PieceTable, Piece, PieceType, OriginalBuffer, BufferMode};
+pub use history::{History, HistoryConfig, CursorState};
+pub use selection::RectSelection;
+pub use transaction::{Transaction, TransactionBuilder, AtomicEdit, InputSource};
@@ -0,0 +1,7 @@
+// Selection - 选区模型
+//
+// 职责：描述光标之外的选区形态，把针对选区的编辑转换为事务
+
+mod rect;
+
+pub use self::rect::RectSelection;
@@ -0,0 +1,279 @@
+// 矩形选区
+//
+// 职责：按行范围和显示列范围描述列选区，把删除、输入、替换和粘贴
+//       转换为逐行编辑，作为一个事务整体应用和撤销
+//
+// 列按显示单元格计算（制表符对齐到制表位，CJK等宽字符占两格），
+// 部分落在选区内的制表符或宽字符整体计入；
+// 短于选区起始列的行在插入内容时用空格补齐（虚拟空间）
+
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::core::buffer::{ColumnSpan, ColumnUnit, PieceTable, Position, DEFAULT_TAB_WIDTH};
+use crate::core::history::CursorState;
+use crate::core::transaction::{DeleteDirection, InputSource, Transaction};
+
+/// 矩形（列）选区
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RectSelection {
+    /// 选中的行（左闭右开）
+    pub lines: Range<usize>,
+    /// 显示列范围（左闭右开，可以超出行尾；宽度为0时是多行光标）
+    pub columns: Range<usize>,
+    /// 制表符宽度
+    pub tab_width: usize,
+}
+
+impl RectSelection {
+    pub fn new(lines: Range<usize>, columns: Range<usize>) -> Self {
+        Self {
+            lines,
+            columns,
+            tab_width: DEFAULT_TAB_WIDTH,
+        }
+    }
+
+    /// 由两个角的显示位置构成（包含两个角所在的行）
+    pub fn from_corners(anchor: Position, head: Position) -> Self {
+        Self::new(
+            anchor.line.min(head.line)..anchor.line.max(head.line) + 1,
+            anchor.column.min(head.column)..anchor.column.max(head.column),
+        )
+    }
+
+    /// 指定制表符宽度
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    /// 宽度是否为0（多行光标）
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    fn unit(&self) -> ColumnUnit {
+        ColumnUnit::Display { tab_width: self.tab_width }
+    }
+
+    /// 文档中存在的各行被选中的部分
+    pub fn spans(&self, table: &PieceTable) -> Vec<ColumnSpan> {
+        let end = self.lines.end.min(table.line_feed_count() + 1);
+        (self.lines.start..end)
+            .map(|line| table.line_column_span(line, self.columns.clone(), self.unit()))
+            .collect()
+    }
+
+    /// 选中的文本（每行一段，以换行连接，用于复制）
+    pub fn text(&self, table: &PieceTable) -> String {
+        self.spans(table)
+            .iter()
+            .map(|span| table.get_text_range(span.bytes.clone()))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// ========== 编辑 ==========
+
+impl RectSelection {
+    /// 删除各行选中的部分，之后成为起始列处的多行光标
+    pub fn delete(&self, table: &PieceTable) -> (Transaction, RectSelection) {
+        let transaction = self.edit_rows(table, InputSource::Keyboard, &[""]);
+        (transaction, self.collapsed_at(self.columns.start))
+    }
+
+    /// 在每行输入文本（替换选中的部分），之后成为输入内容之后的多行光标
+    pub fn type_text(&self, table: &PieceTable, text: &str) -> (Transaction, RectSelection) {
+        let transaction = self.edit_rows(table, InputSource::Keyboard, &[text]);
+        let end = self.columns.start + self.display_width(text);
+        (transaction, self.collapsed_at(end))
+    }
+
+    /// 把每行选中的部分替换为文本，之后选中替换后的内容
+    pub fn replace(&self, table: &PieceTable, text: &str) -> (Transaction, RectSelection) {
+        let transaction = self.edit_rows(table, InputSource::Script, &[text]);
+        let selection = Self {
+            columns: self.columns.start..self.columns.start + self.display_width(text),
+            ..self.clone()
+        };
+        (transaction, selection)
+    }
+
+    /// 粘贴：剪贴板只有一行时每行粘贴相同内容，多行时逐行对应
+    ///
+    /// 剪贴板行数多于选区时向下扩展，超出文档末尾的行追加到末尾
+    pub fn paste(&self, table: &PieceTable, clipboard: &str) -> (Transaction, RectSelection) {
+        let rows: Vec<&str> = clipboard.lines().collect();
+        let rows = if rows.is_empty() { vec![""] } else { rows };
+        let transaction = self.edit_rows(table, InputSource::Paste, &rows);
+
+        let width = rows.iter().map(|row| self.display_width(row)).max().unwrap_or(0);
+        let line_count = if rows.len() > 1 { self.lines.len().max(rows.len()) } else { self.lines.len() };
+        let selection = Self {
+            lines: self.lines.start..self.lines.start + line_count,
+            columns: self.columns.start + width..self.columns.start + width,
+            ..self.clone()
+        };
+        (transaction, selection)
+    }
+
+    /// 把第 i 行选中的部分替换为 rows[i]（只有一项时每行相同）
+    ///
+    /// 从最后一行往前编辑，每个编辑的偏移都不受之前编辑的影响
+    fn edit_rows(&self, table: &PieceTable, source: InputSource, rows: &[&str]) -> Transaction {
+        let row_count = if rows.len() > 1 { self.lines.len().max(rows.len()) } else { self.lines.len() };
+        let row_text = |row: usize| match rows {
+            [text] => *text,
+            _ => rows.get(row).copied().unwrap_or(""),
+        };
+        let spans = Self {
+            lines: self.lines.start..self.lines.start + row_count,
+            ..self.clone()
+        }
+        .spans(table);
+
+        // 超出文档末尾的行：换行后补齐到起始列（末尾的空行不追加）
+        let mut tail = String::new();
+        let appended = spans.len()..row_count;
+        if let Some(last) = appended.clone().rev().find(|&row| !row_text(row).is_empty()) {
+            let missing_lines = (self.lines.start + spans.len()).saturating_sub(table.line_feed_count() + 1);
+            tail.push_str(&"\n".repeat(missing_lines));
+            for row in appended.start..=last {
+                tail.push('\n');
+                tail.push_str(&self.padded(0, row_text(row)));
+            }
+        }
+
+        let caret_before = spans.first().map_or(table.total_bytes(), |span| span.bytes.start);
+        let caret_after = spans.first().map_or(table.total_bytes() + tail.len(), |span| {
+            span.bytes.start + self.padded(span.line_width, row_text(0)).len()
+        });
+        let mut transaction = Transaction::new(source, CursorState::at(caret_before))
+            .with_selection_after(CursorState::at(caret_after));
+
+        if !tail.is_empty() {
+            transaction = transaction.insert(table.total_bytes(), tail);
+        }
+        for (row, span) in spans.iter().enumerate().rev() {
+            transaction = transaction
+                .delete(span.bytes.clone(), DeleteDirection::Forward)
+                .insert(span.bytes.start, self.padded(span.line_width, row_text(row)));
+        }
+
+        transaction
+    }
+
+    /// 行宽不足起始列时在文本前补空格（文本为空时不补）
+    fn padded(&self, line_width: usize, text: &str) -> String {
+        if text.is_empty() {
+            return String::new();
+        }
+
+        let padding = self.columns.start.saturating_sub(line_width);
+        format!("{}{}", " ".repeat(padding), text)
+    }
+
+    /// 从起始列开始输入文本占用的列数
+    fn display_width(&self, text: &str) -> usize {
+        let mut column = self.columns.start;
+        for grapheme in text.graphemes(true) {
+            column += if grapheme == "\t" {
+                self.tab_width - column % self.tab_width
+            } else {
+                grapheme.width()
+            };
+        }
+        column - self.columns.start
+    }
+
+    /// 同样的行范围上位于指定列的多行光标
+    fn collapsed_at(&self, column: usize) -> Self {
+        Self {
+            columns: column..column,
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::history::History;
+
+    fn apply(table: &PieceTable, transaction: &Transaction) -> String {
+        transaction.apply(table).unwrap().get_all_text()
+    }
+
+    #[test]
+    fn test_delete_and_type_column() {
+        let table = PieceTable::from_text("abcdef\nab\nabcdef\n");
+        let selection = RectSelection::new(0..3, 2..4);
+        assert_eq!(selection.text(&table), "cd\n\ncd");
+
+        let (delete, collapsed) = selection.delete(&table);
+        assert_eq!(apply(&table, &delete), "abef\nab\nabef\n");
+        assert_eq!(collapsed.columns, 2..2);
+
+        // 多行光标输入：短行用空格补齐
+        let cursor = RectSelection::new(0..3, 4..4);
+        let (typed, after) = cursor.type_text(&table, "|");
+        assert_eq!(apply(&table, &typed), "abcd|ef\nab  |\nabcd|ef\n");
+        assert_eq!(after.columns, 5..5);
+    }
+
+    #[test]
+    fn test_tabs_and_wide_characters() {
+        // 制表符占 1..4 列，"中文" 各占两列
+        let table = PieceTable::from_text("a\tbc\n中文xy\nabcdefg");
+        let selection = RectSelection::new(0..3, 3..5);
+        assert_eq!(selection.text(&table), "\tb\n文x\nde");
+
+        let (replace, after) = selection.replace(&table, "##");
+        assert_eq!(apply(&table, &replace), "a##c\n中##y\nabc##fg");
+        assert_eq!(after.columns, 3..5);
+
+        // 部分覆盖的宽字符整体删除
+        let (delete, _) = RectSelection::new(1..2, 1..2).delete(&table);
+        assert_eq!(apply(&table, &delete), "a\tbc\n文xy\nabcdefg");
+
+        // 制表符宽度影响列的计算
+        let narrow = RectSelection::new(0..1, 2..3).with_tab_width(2);
+        assert_eq!(narrow.text(&table), "b");
+    }
+
+    #[test]
+    fn test_paste_rows_extend_past_document_end() {
+        let table = PieceTable::from_text("one\ntwo");
+        let (paste, after) = RectSelection::new(0..1, 1..1).paste(&table, "AA\nBB\nCC\n");
+
+        assert_eq!(apply(&table, &paste), "oAAne\ntBBwo\n CC");
+        assert_eq!(after.lines, 0..3);
+        assert_eq!(after.columns, 3..3);
+
+        // 单行剪贴板在每行粘贴相同内容
+        let (paste, _) = RectSelection::new(0..2, 0..1).paste(&table, "x");
+        assert_eq!(apply(&table, &paste), "xne\nxwo");
+    }
+
+    #[test]
+    fn test_rect_edit_is_one_undo_step() {
+        let table = PieceTable::from_text("alpha\nbeta\ngamma\n");
+        let mut history = History::new(table.clone(), CursorState::at(0));
+
+        let selection = RectSelection::from_corners(Position::new(2, 1), Position::new(0, 3));
+        assert_eq!(selection, RectSelection::new(0..3, 1..3));
+        let (transaction, _) = selection.type_text(&table, "_");
+        assert_eq!(transaction.selection_after(), &CursorState::at(2));
+        transaction.commit(&mut history).unwrap();
+        assert_eq!(history.current_table().get_all_text(), "a_ha\nb_a\ng_ma\n");
+
+        let (undone, cursor) = history.undo().unwrap();
+        assert_eq!(undone.get_all_text(), "alpha\nbeta\ngamma\n");
+        assert_eq!(cursor, CursorState::at(1));
+        assert!(!history.can_undo());
+    }
+}
@@ -0,0 +1,221 @@
+// 事务边界检测
+//
+// 职责：判断一次新的编辑是否应合并到上一个事务（连续输入合并）
+
+use std::time::{Duration, Instant};
+
+use crate::core::history::CursorState;
+use super::operation::{AtomicEdit, DeleteDirection};
+use super::DEFAULT_COALESCE_WINDOW_MS;
+
+/// 操作上下文
+#[derive(Debug, Clone)]
+pub struct OperationContext {
+    pub timestamp: Instant,
+    pub cursor_before: CursorState,
+    pub cursor_after: CursorState,
+    pub source: InputSource,
+}
+
+impl OperationContext {
+    /// 以当前时间创建上下文
+    pub fn now(source: InputSource, cursor_before: CursorState, cursor_after: CursorState) -> Self {
+        Self {
+            timestamp: Instant::now(),
+            cursor_before,
+            cursor_after,
+            source,
+        }
+    }
+}
+
+/// 输入来源
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputSource {
+    Keyboard,        // 键盘输入
+    ImeComposing,    // 输入法组合中
+    ImeCommit,       // 输入法提交
+    Paste,           // 粘贴
+    UndoRedo,        // 撤销/重做
+    Script,          // 脚本/宏
+    Formatting,      // 自动格式化
+}
+
+impl InputSource {
+    /// 该来源的编辑是否可与相邻编辑合并
+    pub fn is_coalescable(&self) -> bool {
+        matches!(self, InputSource::Keyboard | InputSource::ImeComposing)
+    }
+}
+
+/// 上一次被接受的编辑
+#[derive(Debug, Clone)]
+struct LastEdit {
+    edit: AtomicEdit,
+    timestamp: Instant,
+    source: InputSource,
+    cursor_after: CursorState,
+}
+
+/// 事务边界检测器
+#[derive(Debug, Clone)]
+pub struct BoundaryDetector {
+    /// 合并时间窗口
+    window: Duration,
+    last: Option<LastEdit>,
+}
+
+impl BoundaryDetector {
+    pub fn new() -> Self {
+        Self::with_window(Duration::from_millis(DEFAULT_COALESCE_WINDOW_MS))
+    }
+
+    pub fn with_window(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// 判断编辑是否需要开启新事务，并记录为最近一次编辑
+    pub fn should_start_new(&mut self, edit: &AtomicEdit, context: &OperationContext) -> bool {
+        let new = match &self.last {
+            Some(last) => !self.can_coalesce(last, edit, context),
+            None => true,
+        };
+
+        self.last = Some(LastEdit {
+            edit: edit.clone(),
+            timestamp: context.timestamp,
+            source: context.source,
+            cursor_after: context.cursor_after.clone(),
+        });
+
+        new
+    }
+
+    /// 强制下一次编辑开启新事务（光标移动、保存等）
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    fn can_coalesce(&self, last: &LastEdit, edit: &AtomicEdit, context: &OperationContext) -> bool {
+        // 1. 来源相同且可合并
+        if last.source != context.source || !context.source.is_coalescable() {
+            return false;
+        }
+
+        // 2. 时间间隔在窗口内
+        if context.timestamp.saturating_duration_since(last.timestamp) > self.window {
+            return false;
+        }
+
+        // 3. 操作类型相同（删除还需方向相同）
+        if !last.edit.can_merge_type(edit) {
+            return false;
+        }
+
+        // 4. 光标连续，且没有选区替换
+        if context.cursor_before != last.cursor_after || context.cursor_before.selection.is_some() {
+            return false;
+        }
+
+        // 5. 位置相邻，且没有跨越单词边界
+        match (&last.edit, edit) {
+            (
+                AtomicEdit::Insert { offset: a, text: prev },
+                AtomicEdit::Insert { offset: b, text },
+            ) => *b == *a + prev.len() && !is_word_boundary(prev, text),
+            (
+                AtomicEdit::Delete { offset: a, direction: DeleteDirection::Backward, .. },
+                AtomicEdit::Delete { offset: b, length, .. },
+            ) => *b + *length == *a,
+            (
+                AtomicEdit::Delete { offset: a, direction: DeleteDirection::Forward, .. },
+                AtomicEdit::Delete { offset: b, .. },
+            ) => *b == *a,
+            _ => false,
+        }
+    }
+}
+
+impl Default for BoundaryDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 输入从分隔符切换到单词字符时视为单词边界；换行总是边界
+fn is_word_boundary(prev: &str, next: &str) -> bool {
+    let (Some(last), Some(first)) = (prev.chars().last(), next.chars().next()) else {
+        return false;
+    };
+
+    if next.contains('\n') || last == '\n' {
+        return true;
+    }
+
+    !is_word_char(last) && is_word_char(first)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(detector: &mut BoundaryDetector, offset: usize, text: &str, at: Instant) -> bool {
+        let context = OperationContext {
+            timestamp: at,
+            cursor_before: CursorState::at(offset),
+            cursor_after: CursorState::at(offset + text.len()),
+            source: InputSource::Keyboard,
+        };
+        detector.should_start_new(&AtomicEdit::insert(offset, text), &context)
+    }
+
+    #[test]
+    fn test_typing_coalesces_until_word_boundary() {
+        let mut detector = BoundaryDetector::new();
+        let t = Instant::now();
+
+        assert!(typed(&mut detector, 0, "h", t));
+        assert!(!typed(&mut detector, 1, "i", t));
+        assert!(!typed(&mut detector, 2, " ", t));
+        assert!(typed(&mut detector, 3, "y", t));
+        assert!(!typed(&mut detector, 4, "o", t));
+        assert!(typed(&mut detector, 5, "\n", t));
+    }
+
+    #[test]
+    fn test_pause_and_jump_start_new_transaction() {
+        let mut detector = BoundaryDetector::with_window(Duration::from_millis(100));
+        let t = Instant::now();
+
+        assert!(typed(&mut detector, 0, "a", t));
+        assert!(typed(&mut detector, 1, "b", t + Duration::from_millis(500)));
+        assert!(typed(&mut detector, 10, "c", t + Duration::from_millis(510)));
+    }
+
+    #[test]
+    fn test_backspace_run_coalesces() {
+        let mut detector = BoundaryDetector::new();
+        let t = Instant::now();
+        let mut backspace = |end: usize| {
+            let context = OperationContext {
+                timestamp: t,
+                cursor_before: CursorState::at(end),
+                cursor_after: CursorState::at(end - 1),
+                source: InputSource::Keyboard,
+            };
+            detector.should_start_new(&AtomicEdit::delete(end - 1..end, DeleteDirection::Backward), &context)
+        };
+
+        assert!(backspace(5));
+        assert!(!backspace(4));
+        assert!(!backspace(3));
+    }
+}
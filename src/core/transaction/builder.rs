@@ -0,0 +1,144 @@
+// 事务构建器
+//
+// 职责：接收单个编辑，按边界规则把连续输入合并为同一个撤销单元
+
+use std::time::Duration;
+
+use crate::core::history::{History, RevisionId};
+use super::boundary::{BoundaryDetector, OperationContext};
+use super::edit::Transaction;
+use super::error::Result;
+use super::operation::AtomicEdit;
+
+/// 事务构建器（连续输入合并）
+#[derive(Debug, Clone, Default)]
+pub struct TransactionBuilder {
+    detector: BoundaryDetector,
+    /// 正在合并的版本（仍是历史当前版本时才可继续合并）
+    pending: Option<RevisionId>,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 指定合并时间窗口
+    pub fn with_window(window: Duration) -> Self {
+        Self {
+            detector: BoundaryDetector::with_window(window),
+            pending: None,
+        }
+    }
+
+    /// 应用单个编辑并记录到历史，可合并时并入上一个撤销单元
+    pub fn apply(
+        &mut self,
+        history: &mut History,
+        edit: AtomicEdit,
+        context: OperationContext,
+    ) -> Result<RevisionId> {
+        let starts_new = self.detector.should_start_new(&edit, &context);
+
+        let mut transaction = Transaction::new(context.source, context.cursor_before.clone());
+        transaction.push(edit);
+        let table = match transaction.apply(history.current_table()) {
+            Ok(table) => table,
+            Err(e) => {
+                self.break_coalescing();
+                return Err(e);
+            }
+        };
+
+        let can_amend = !starts_new && self.pending == Some(history.current_id());
+        if can_amend && history.amend_current(table.clone(), context.cursor_after.clone()) {
+            return Ok(history.current_id());
+        }
+
+        let id = history.record(table, context.cursor_before, context.cursor_after);
+        self.pending = Some(id);
+        Ok(id)
+    }
+
+    /// 结束当前合并，下一次编辑开启新的撤销单元
+    pub fn break_coalescing(&mut self) {
+        self.detector.reset();
+        self.pending = None;
+    }
+
+    /// 是否有正在合并的撤销单元
+    pub fn is_coalescing(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::buffer::PieceTable;
+    use crate::core::history::CursorState;
+    use crate::core::transaction::{DeleteDirection, InputSource};
+
+    fn type_str(builder: &mut TransactionBuilder, history: &mut History, text: &str) {
+        for c in text.chars() {
+            let offset = history.current_table().total_bytes();
+            let s = c.to_string();
+            let context = OperationContext::now(
+                InputSource::Keyboard,
+                CursorState::at(offset),
+                CursorState::at(offset + s.len()),
+            );
+            builder.apply(history, AtomicEdit::insert(offset, s), context).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_words_become_separate_undo_steps() {
+        let mut history = History::new(PieceTable::new(), CursorState::at(0));
+        let mut builder = TransactionBuilder::new();
+
+        type_str(&mut builder, &mut history, "hello world");
+        assert_eq!(history.undo_depth(), 2);
+
+        let (table, cursor) = history.undo().unwrap();
+        assert_eq!(table.get_all_text(), "hello ");
+        assert_eq!(cursor, CursorState::at(6));
+
+        let (table, _) = history.undo().unwrap();
+        assert_eq!(table.get_all_text(), "");
+    }
+
+    #[test]
+    fn test_undo_breaks_coalescing() {
+        let mut history = History::new(PieceTable::new(), CursorState::at(0));
+        let mut builder = TransactionBuilder::new();
+
+        type_str(&mut builder, &mut history, "ab");
+        history.undo();
+        type_str(&mut builder, &mut history, "cd");
+
+        // 撤销后的输入成为新分支，不会改写已撤销的版本
+        assert_eq!(history.current_table().get_all_text(), "cd");
+        assert_eq!(history.undo_depth(), 1);
+        assert_eq!(history.len(), 3);
+    }
+
+    #[test]
+    fn test_backspace_after_typing_is_new_step() {
+        let mut history = History::new(PieceTable::new(), CursorState::at(0));
+        let mut builder = TransactionBuilder::new();
+
+        type_str(&mut builder, &mut history, "abc");
+        let context = OperationContext::now(
+            InputSource::Keyboard,
+            CursorState::at(3),
+            CursorState::at(2),
+        );
+        builder
+            .apply(&mut history, AtomicEdit::delete(2..3, DeleteDirection::Backward), context)
+            .unwrap();
+
+        assert_eq!(history.undo_depth(), 2);
+        assert_eq!(history.undo().unwrap().0.get_all_text(), "abc");
+    }
+}
@@ -0,0 +1,188 @@
+// 编辑事务
+//
+// 职责：一组按顺序执行的原子编辑，整体应用、整体撤销
+
+use std::time::Instant;
+
+use crate::core::buffer::PieceTable;
+use crate::core::history::{CursorState, History, RevisionId};
+use super::boundary::InputSource;
+use super::error::{Result, TransactionError};
+use super::operation::{AtomicEdit, DeleteDirection};
+
+/// 编辑事务（一个撤销单元）
+///
+/// 每个编辑的偏移相对于前一个编辑执行后的文档
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    edits: Vec<AtomicEdit>,
+    selection_before: CursorState,
+    selection_after: CursorState,
+    source: InputSource,
+    timestamp: Instant,
+}
+
+impl Transaction {
+    pub fn new(source: InputSource, selection_before: CursorState) -> Self {
+        Self {
+            edits: Vec::new(),
+            selection_after: selection_before.clone(),
+            selection_before,
+            source,
+            timestamp: Instant::now(),
+        }
+    }
+
+    /// 追加插入操作
+    pub fn insert(mut self, offset: usize, text: impl Into<String>) -> Self {
+        self.push(AtomicEdit::insert(offset, text));
+        self
+    }
+
+    /// 追加删除操作
+    pub fn delete(mut self, range: std::ops::Range<usize>, direction: DeleteDirection) -> Self {
+        self.push(AtomicEdit::delete(range, direction));
+        self
+    }
+
+    /// 设置事务完成后的光标/选区
+    pub fn with_selection_after(mut self, selection: CursorState) -> Self {
+        self.selection_after = selection;
+        self
+    }
+
+    /// 追加原子编辑（忽略空操作）
+    pub fn push(&mut self, edit: AtomicEdit) {
+        if !edit.is_empty() {
+            self.edits.push(edit);
+        }
+    }
+
+    pub fn edits(&self) -> &[AtomicEdit] {
+        &self.edits
+    }
+
+    pub fn selection_before(&self) -> &CursorState {
+        &self.selection_before
+    }
+
+    pub fn selection_after(&self) -> &CursorState {
+        &self.selection_after
+    }
+
+    pub fn set_selection_after(&mut self, selection: CursorState) {
+        self.selection_after = selection;
+    }
+
+    pub fn source(&self) -> InputSource {
+        self.source
+    }
+
+    pub fn timestamp(&self) -> Instant {
+        self.timestamp
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+}
+
+// ========== 应用与提交 ==========
+
+impl Transaction {
+    /// 在快照上依次应用所有编辑，返回新文档
+    ///
+    /// 任一编辑非法时返回错误，原文档保持不变（全部成功或全部失败）
+    pub fn apply(&self, table: &PieceTable) -> Result<PieceTable> {
+        // 第一个编辑直接作用在快照上（未完成的空闲整理进度随编辑移交给新文档）
+        let mut result: Option<PieceTable> = None;
+
+        for edit in &self.edits {
+            result = Some(Self::apply_edit(result.as_ref().unwrap_or(table), edit)?);
+        }
+
+        Ok(result.unwrap_or_else(|| table.clone()))
+    }
+
+    /// 应用事务并作为一个撤销单元记录到历史
+    pub fn commit(&self, history: &mut History) -> Result<RevisionId> {
+        if self.is_empty() {
+            return Err(TransactionError::EmptyTransaction);
+        }
+
+        let table = self.apply(history.current_table())?;
+        Ok(history.record(table, self.selection_before.clone(), self.selection_after.clone()))
+    }
+
+    fn apply_edit(table: &PieceTable, edit: &AtomicEdit) -> Result<PieceTable> {
+        let range = match edit {
+            AtomicEdit::Insert { offset, .. } => *offset..*offset,
+            AtomicEdit::Delete { .. } => edit.affected_range(),
+        };
+        Self::validate_offset(table, range.start)?;
+        Self::validate_offset(table, range.end)?;
+
+        let (result, _) = match edit {
+            AtomicEdit::Insert { offset, text } => table.insert_char_safe(*offset, text),
+            AtomicEdit::Delete { .. } => table.delete_char_safe(range),
+        };
+        Ok(result)
+    }
+
+    fn validate_offset(table: &PieceTable, offset: usize) -> Result<()> {
+        let len = table.total_bytes();
+        if offset > len {
+            return Err(TransactionError::OutOfBounds { offset, len });
+        }
+        if !table.is_char_boundary(offset) {
+            return Err(TransactionError::NotCharBoundary(offset));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_edit_transaction_is_one_undo_step() {
+        let mut history = History::new(PieceTable::from_text("a b c"), CursorState::at(0));
+
+        let transaction = Transaction::new(InputSource::Formatting, CursorState::at(0))
+            .insert(0, "(")
+            .insert(6, ")")
+            .delete(2..3, DeleteDirection::Forward)
+            .with_selection_after(CursorState::with_selection(0, 0..6));
+        transaction.commit(&mut history).unwrap();
+
+        assert_eq!(history.current_table().get_all_text(), "(ab c)");
+        assert_eq!(history.undo_depth(), 1);
+
+        let (table, cursor) = history.undo().unwrap();
+        assert_eq!(table.get_all_text(), "a b c");
+        assert_eq!(cursor, CursorState::at(0));
+
+        let (_, cursor) = history.redo().unwrap();
+        assert_eq!(cursor.selection, Some(0..6));
+    }
+
+    #[test]
+    fn test_invalid_edit_leaves_document_untouched() {
+        let mut history = History::new(PieceTable::from_text("你好"), CursorState::at(0));
+
+        let out_of_bounds = Transaction::new(InputSource::Script, CursorState::at(0))
+            .insert(0, "x")
+            .insert(100, "y");
+        assert_eq!(
+            out_of_bounds.commit(&mut history),
+            Err(TransactionError::OutOfBounds { offset: 100, len: 7 })
+        );
+
+        let split_char = Transaction::new(InputSource::Script, CursorState::at(0)).insert(1, "x");
+        assert_eq!(split_char.commit(&mut history), Err(TransactionError::NotCharBoundary(1)));
+
+        assert_eq!(history.current_table().get_all_text(), "你好");
+        assert!(!history.can_undo());
+    }
+}
@@ -0,0 +1,17 @@
+// 事务错误类型
+
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum TransactionError {
+    #[error("位置越界: {offset} > {len}")]
+    OutOfBounds { offset: usize, len: usize },
+
+    #[error("位置不在UTF-8字符边界: {0}")]
+    NotCharBoundary(usize),
+
+    #[error("空事务")]
+    EmptyTransaction,
+}
+
+pub type Result<T> = std::result::Result<T, TransactionError>;
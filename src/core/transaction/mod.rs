@@ -0,0 +1,19 @@
+// Edit Transaction - 编辑事务系统
+//
+// 职责：把多个原子编辑组合为一个可撤销单元，
+//       原子地应用到 PieceTable，并按时间/单词边界合并连续输入
+
+mod operation;
+mod edit;
+mod boundary;
+mod builder;
+mod error;
+
+pub use self::operation::{AtomicEdit, DeleteDirection};
+pub use self::edit::Transaction;
+pub use self::boundary::{BoundaryDetector, OperationContext, InputSource};
+pub use self::builder::TransactionBuilder;
+pub use self::error::{TransactionError, Result};
+
+/// 连续输入合并的默认时间窗口（毫秒）
+pub const DEFAULT_COALESCE_WINDOW_MS: u64 = 1000;
@@ -0,0 +1,71 @@
+// 原子编辑操作
+//
+// 职责：描述纯语义的插入/删除，偏移相对于执行该操作时的文档
+
+use std::ops::Range;
+
+/// 原子编辑操作（纯语义）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AtomicEdit {
+    /// 插入文本
+    Insert {
+        offset: usize,
+        text: String,
+    },
+
+    /// 删除文本
+    Delete {
+        offset: usize,
+        length: usize,
+        direction: DeleteDirection,
+    },
+}
+
+/// 删除方向（影响合并规则）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeleteDirection {
+    Backward,  // Backspace（向左删除）
+    Forward,   // Delete（向右删除）
+}
+
+impl AtomicEdit {
+    pub fn insert(offset: usize, text: impl Into<String>) -> Self {
+        AtomicEdit::Insert {
+            offset,
+            text: text.into(),
+        }
+    }
+
+    pub fn delete(range: Range<usize>, direction: DeleteDirection) -> Self {
+        AtomicEdit::Delete {
+            offset: range.start,
+            length: range.len(),
+            direction,
+        }
+    }
+
+    /// 获取操作影响的范围（插入为新文本的范围，删除为被删除的范围）
+    pub fn affected_range(&self) -> Range<usize> {
+        match self {
+            AtomicEdit::Insert { offset, text } => *offset..*offset + text.len(),
+            AtomicEdit::Delete { offset, length, .. } => *offset..*offset + *length,
+        }
+    }
+
+    /// 检查操作类型是否可合并（同为插入，或同方向的删除）
+    pub fn can_merge_type(&self, other: &AtomicEdit) -> bool {
+        match (self, other) {
+            (AtomicEdit::Insert { .. }, AtomicEdit::Insert { .. }) => true,
+            (
+                AtomicEdit::Delete { direction: a, .. },
+                AtomicEdit::Delete { direction: b, .. },
+            ) => a == b,
+            _ => false,
+        }
+    }
+
+    /// 是否为空操作
+    pub fn is_empty(&self) -> bool {
+        self.affected_range().is_empty()
+    }
+}
@@ -5,10 +5,10 @@ use zedit::core::buffer::PieceTable;
 #[test]
 fn test_edit_workflow() {
     // 模拟真实编辑场景
-    let mut table = PieceTable::from_text("fn main() {\n    println!(\"Hello\");\n}");
+    let table = PieceTable::from_text("fn main() {\n    println!(\"Hello\");\n}");
     
     // 在函数内添加代码
-    let (table, _) = table.insert_char_safe(26, "    println!(\"World\");\n");
+    let (table, _) = table.insert_char_safe(35, "    println!(\"World\");\n");
     
     let text = table.get_text_range(0..table.total_bytes());
     assert!(text.contains("println!(\"Hello\")"));
@@ -18,7 +18,7 @@ fn test_edit_workflow() {
 #[test]
 fn test_complex_undo_redo() {
     // 模拟多次编辑和撤销
-    let mut table = PieceTable::from_text("Hello");
+    let table = PieceTable::from_text("Hello");
     
     // 编辑序列
     let (t1, _) = table.insert_char_safe(5, " world");
@@ -36,7 +36,7 @@ fn test_complex_undo_redo() {
 #[test]
 fn test_multiline_edit() {
     let text = "Line 1\nLine 2\nLine 3";
-    let mut table = PieceTable::from_text(text);
+    let table = PieceTable::from_text(text);
     
     // 在第二行插入
     let (table, _) = table.insert_char_safe(13, " inserted");
@@ -54,9 +54,294 @@ fn test_large_text_performance() {
     // 验证可以正常操作
     assert_eq!(table.total_bytes(), 1_000_000);
     
-    let mut table = table;
+    let table = table;
     let (table, _) = table.insert_char_safe(500_000, "INSERT");
     
     assert_eq!(table.total_bytes(), 1_000_006);
     assert!(table.get_text_range(500_000..500_006).contains("INSERT"));
 }
+
+#[test]
+fn test_line_index_covers_whole_mapped_file() {
+    // 超过10MB的文件使用内存映射，行索引必须覆盖全部内容
+    let path = std::env::temp_dir().join(format!("zedit_lines_{}.log", std::process::id()));
+    let line = "0123456789abcdef0123456789abcdef0123456789abcdef012345678\n"; // 58字节
+    let count = 12 * 1024 * 1024 / line.len() + 1;
+    std::fs::write(&path, line.repeat(count)).unwrap();
+
+    let mut table = PieceTable::from_file(&path).unwrap();
+    let task = table.spawn_line_indexing();
+    let index = task.wait().unwrap();
+    assert!(table.install_line_index(index));
+
+    let lines = table.lines().unwrap();
+    assert_eq!(lines.total_lines(), count);
+    assert_eq!(lines.find_line_by_offset(table.total_bytes() - 1), Some(count - 1));
+    assert_eq!(table.get_line(count - 1).as_deref(), Some(line.trim_end()));
+
+    drop(table);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_open_and_write_back_non_utf8_file() {
+    // 含一个 Latin-1 字节的小文件可以打开，未编辑时原样写回
+    let path = std::env::temp_dir().join(format!("zedit_latin1_{}.txt", std::process::id()));
+    let content = b"na\xEFve text\n".to_vec();
+    std::fs::write(&path, &content).unwrap();
+
+    let table = PieceTable::from_file(&path).unwrap();
+    assert_eq!(table.invalid_utf8_spans(0..table.total_bytes()), vec![2..3]);
+
+    let mut written = Vec::new();
+    table.write_to(&mut written).unwrap();
+    assert_eq!(written, content);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_history_survives_truncated_mapped_file() {
+    use zedit::core::buffer::DiskStatus;
+    use zedit::core::history::{CursorState, History};
+
+    // 映射的日志文件被外部截断前建立私有副本，所有版本仍可读取
+    let path = std::env::temp_dir().join(format!("zedit_truncated_{}.log", std::process::id()));
+    let line = "2024-01-01 00:00:00 INFO request handled in 12ms\n";
+    let count = 11 * 1024 * 1024 / line.len();
+    std::fs::write(&path, line.repeat(count)).unwrap();
+
+    let table = PieceTable::from_file(&path).unwrap();
+    let original_head = table.get_text_range(0..line.len() * 2);
+    let mut history = History::new(table.clone(), CursorState::at(0));
+    let (edited, _) = table.delete_char_safe(line.len()..table.total_bytes() - line.len());
+    let (edited, _) = edited.insert_char_safe(0, "# edited\n");
+    history.record(edited, CursorState::at(0), CursorState::at(9));
+
+    std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(10).unwrap();
+    assert_eq!(history.current_table().check_original(), DiskStatus::Modified);
+    // 根版本仍引用整个文件，已被截断的范围无法复制
+    assert!(history.detach_original().is_err());
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::write(&path, line.repeat(count)).unwrap();
+    let table = PieceTable::from_file(&path).unwrap();
+    let mut history = History::new(table.clone(), CursorState::at(0));
+    let (edited, _) = table.delete_char_safe(line.len()..table.total_bytes() - line.len());
+    history.record(edited, CursorState::at(0), CursorState::at(0));
+
+    assert!(table.watch_original(std::time::Duration::from_secs(1)).is_some());
+    history.detach_original().unwrap();
+    std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(10).unwrap();
+    let current = history.current_table();
+    assert_eq!(current.get_text_range(0..current.total_bytes()), line.repeat(2));
+    let (undone, _) = history.undo().unwrap();
+    assert_eq!(undone.get_text_range(0..line.len() * 2), original_head);
+    assert_eq!(undone.original_disk_status(), DiskStatus::Unchanged);
+    assert_eq!(undone.check_original(), DiskStatus::Modified);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_edit_windowed_mapping() {
+    use zedit::core::buffer::{CursorUnit, MmapBuffer, WindowConfig};
+
+    // 滑动窗口映射的文件：编辑、读取和游标移动跨越窗口边缘时保持正确
+    let path = std::env::temp_dir().join(format!("zedit_windowed_{}.log", std::process::id()));
+    let line = "窗口 window 边缘 edge\n";
+    let content = line.repeat(40_000);
+    std::fs::write(&path, &content).unwrap();
+
+    let config = WindowConfig { window_size: 64 * 1024, budget: 2 * 64 * 1024 };
+    let table = PieceTable::from_mapped(MmapBuffer::from_file_windowed(&path, config).unwrap());
+    assert_eq!(table.line_feed_count(), 40_000);
+
+    let edge = 3 * 64 * 1024;
+    let around = (edge / line.len() - 3) * line.len()..(edge / line.len() + 3) * line.len();
+    assert_eq!(table.get_text_range(around.clone()), content[around]);
+
+    let (edited, _) = table.insert_char_safe(edge - 1, "插入");
+    let deleted = line.len() * 2 + 8..line.len() * 3000 + 8;
+    let (edited, _) = edited.delete_char_safe(deleted.clone());
+    let mut expected = content.clone();
+    let insert_at = (0..=edge - 1).rev().find(|&i| content.is_char_boundary(i)).unwrap();
+    expected.insert_str(insert_at, "插入");
+    expected.replace_range(deleted, "");
+    assert_eq!(edited.get_text_range(0..edited.total_bytes()), expected);
+
+    let mut cursor = edited.cursor_at(0);
+    let mut chars = 0;
+    while cursor.move_forward(CursorUnit::Char) {
+        chars += 1;
+    }
+    assert_eq!(chars, expected.chars().count());
+
+    let mut written = Vec::new();
+    table.write_to(&mut written).unwrap();
+    assert_eq!(written, content.as_bytes());
+
+    drop((table, edited));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_undo_across_post_save_rebase() {
+    use zedit::core::buffer::SaveOptions;
+    use zedit::core::history::{CursorState, History};
+
+    // 保存后压缩当前版本，撤销和重做仍然得到正确的内容
+    let dir = std::env::temp_dir().join(format!("zedit_rebase_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("story.txt");
+
+    let table = PieceTable::from_text("once upon a time\n");
+    let mut history = History::new(table.clone(), CursorState::at(0));
+    let (first, _) = table.insert_char_safe(17, "the end\n");
+    history.record(first.clone(), CursorState::at(17), CursorState::at(25));
+    let (second, _) = first.delete_char_safe(0..5);
+    history.record(second.clone(), CursorState::at(5), CursorState::at(0));
+
+    let rebased = second.save_and_rebase(&path, SaveOptions::default()).unwrap();
+    assert_eq!(rebased.piece_count(), 1);
+    let memory_before = history.memory_used();
+    assert!(history.rebase_current(rebased));
+    assert!(history.memory_used() <= memory_before);
+
+    let (undone, cursor) = history.undo().unwrap();
+    assert_eq!(undone.get_text_range(0..undone.total_bytes()), "once upon a time\nthe end\n");
+    assert_eq!(cursor, CursorState::at(5));
+    let (redone, _) = history.redo().unwrap();
+    assert_eq!(redone.piece_count(), 1);
+    assert_eq!(redone.get_text_range(0..redone.total_bytes()), "upon a time\nthe end\n");
+
+    assert!(!history.rebase_current(PieceTable::from_text("short")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_restore_large_deletion_without_copying() {
+    use std::time::Duration;
+
+    // 内存映射的大文件中删除10MB，恢复时只拼接Piece引用
+    let path = std::env::temp_dir().join(format!("zedit_restore_{}.log", std::process::id()));
+    let line = "restore me from the mapped original file, please!\n"; // 50字节
+    let count = 12 * 1024 * 1024 / line.len();
+    std::fs::write(&path, line.repeat(count)).unwrap();
+
+    let mut table = PieceTable::from_file(&path).unwrap();
+    let index = table.spawn_line_indexing().wait().unwrap();
+    assert!(table.install_line_index(index));
+    let memory_before = table.estimated_memory();
+
+    let start = 1000 * line.len();
+    let end = start + 10 * 1024 * 1024 / line.len() * line.len();
+    let (deleted, info) = table.delete_lazy(start..end);
+    assert_eq!(deleted.total_bytes(), table.total_bytes() - (end - start));
+
+    let mut restored = deleted.restore_deletion(&info).unwrap();
+    assert_eq!(restored.total_bytes(), table.total_bytes());
+    assert!(restored.estimated_memory() < memory_before + 64 * 1024);
+    assert_eq!(restored.get_text_range(end - 10..end + 10), table.get_text_range(end - 10..end + 10));
+
+    // 多行内容的逐行索引留给空闲整理补建
+    while restored.needs_maintenance() {
+        restored.idle_tick(Duration::from_millis(50));
+    }
+    assert_eq!(restored.lines().unwrap().total_lines(), count);
+    assert_eq!(restored.get_line(count / 2).as_deref(), Some(line.trim_end()));
+
+    drop((table, deleted, restored));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_search_windowed_mapping() {
+    use zedit::core::buffer::{MmapBuffer, SearchDirection, WindowConfig};
+
+    // 滑动窗口映射的文件中搜索：匹配跨越窗口边缘和编辑产生的Piece边界
+    let path = std::env::temp_dir().join(format!("zedit_search_{}.log", std::process::id()));
+    let window_size = 64 * 1024;
+    let mut content = vec![b'.'; 4 * window_size];
+    for edge in 1..4 {
+        content[edge * window_size - 3..edge * window_size + 3].copy_from_slice(b"marker");
+    }
+    std::fs::write(&path, &content).unwrap();
+
+    let config = WindowConfig { window_size, budget: 2 * window_size };
+    let table = PieceTable::from_mapped(MmapBuffer::from_file_windowed(&path, config).unwrap());
+    let (table, _) = table.insert_char_safe(100, "mar");
+    let (table, _) = table.insert_char_safe(103, "ker");
+
+    let forward: Vec<_> = table.search_literal(b"marker", 0, SearchDirection::Forward).collect();
+    let expected: Vec<_> = [100, window_size + 3, 2 * window_size + 3, 3 * window_size + 3]
+        .into_iter()
+        .map(|start| start..start + 6)
+        .collect();
+    assert_eq!(forward, expected);
+
+    let backward: Vec<_> = table
+        .search_literal(b"marker", table.total_bytes(), SearchDirection::Backward)
+        .take(2)
+        .collect();
+    assert_eq!(backward, vec![expected[3].clone(), expected[2].clone()]);
+    assert_eq!(table.find_literal(b"marker", 101, SearchDirection::Backward), None);
+
+    drop(table);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_regex_search_mapped_log() {
+    use regex::bytes::Regex;
+    use zedit::core::buffer::SearchDirection;
+
+    // 在内存映射的日志文件中按块运行正则，结果与整段匹配一致
+    let path = std::env::temp_dir().join(format!("zedit_regex_{}.log", std::process::id()));
+    let content: String = (0..20_000)
+        .map(|i| format!("{:05} [{}] request took {}ms\n", i, if i % 7 == 0 { "WARN" } else { "INFO" }, i % 1000))
+        .collect();
+    std::fs::write(&path, &content).unwrap();
+
+    let table = PieceTable::from_file(&path).unwrap();
+    let regex = Regex::new(r"(?m)^(\d+) \[WARN\] request took (\d{3})ms$").unwrap();
+    let expected: Vec<_> = regex.find_iter(content.as_bytes()).map(|m| m.range()).collect();
+
+    let found: Vec<_> = table.search_regex(&regex, 0, SearchDirection::Forward).collect();
+    assert_eq!(found.iter().map(|m| m.range.clone()).collect::<Vec<_>>(), expected);
+    let first = &found[0];
+    assert_eq!(&content[first.group(1).unwrap()], "00105");
+    assert_eq!(&content[first.group(2).unwrap()], "105");
+
+    let last = table.find_regex(&regex, table.total_bytes(), SearchDirection::Backward).unwrap();
+    assert_eq!(Some(&last.range), expected.last());
+
+    drop(table);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_replace_all_in_one_pass() {
+    use zedit::core::buffer::{SearchDirection, TextEdit};
+
+    // 20万处全部替换一次完成，撤销恢复原文
+    let line = "let value = old_name + old_name;\n";
+    let mut table = PieceTable::from_text(&line.repeat(100_000));
+    table.get_or_build_lines();
+
+    let edits: Vec<TextEdit> = table
+        .search_literal(b"old_name", 0, SearchDirection::Forward)
+        .map(|range| (range, "renamed".to_string()))
+        .collect();
+    assert_eq!(edits.len(), 200_000);
+
+    let (replaced, inverse) = table.apply_edits(edits).unwrap();
+    let expected = line.replace("old_name", "renamed");
+    assert_eq!(replaced.total_bytes(), expected.len() * 100_000);
+    assert_eq!(replaced.get_line(99_999).as_deref(), Some(expected.trim_end()));
+    assert_eq!(replaced.lines().unwrap().total_lines(), 100_000);
+    assert!(replaced.piece_count() <= 400_001);
+
+    let (restored, _) = replaced.apply_edits(inverse).unwrap();
+    assert_eq!(restored.get_text_range(0..restored.total_bytes()), line.repeat(100_000));
+}
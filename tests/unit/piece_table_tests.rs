@@ -20,7 +20,7 @@ fn test_from_text() {
 
 #[test]
 fn test_basic_insert() {
-    let mut table = PieceTable::from_text("Hello");
+    let table = PieceTable::from_text("Hello");
     let (table, _) = table.insert_char_safe(5, " world");
     assert_eq!(table.get_text_range(0..11), "Hello world");
     assert_eq!(table.total_bytes(), 11);
@@ -28,7 +28,7 @@ fn test_basic_insert() {
 
 #[test]
 fn test_basic_delete() {
-    let mut table = PieceTable::from_text("Hello world");
+    let table = PieceTable::from_text("Hello world");
     let (table, deleted) = table.delete_char_safe(5..6);
     assert_eq!(deleted, " ");
     assert_eq!(table.get_text_range(0..10), "Helloworld");
@@ -36,21 +36,21 @@ fn test_basic_delete() {
 
 #[test]
 fn test_insert_at_beginning() {
-    let mut table = PieceTable::from_text("world");
+    let table = PieceTable::from_text("world");
     let (table, _) = table.insert_char_safe(0, "Hello ");
     assert_eq!(table.get_text_range(0..11), "Hello world");
 }
 
 #[test]
 fn test_insert_at_end() {
-    let mut table = PieceTable::from_text("Hello");
+    let table = PieceTable::from_text("Hello");
     let (table, _) = table.insert_char_safe(5, " world");
     assert_eq!(table.get_text_range(0..11), "Hello world");
 }
 
 #[test]
 fn test_multiple_inserts() {
-    let mut table = PieceTable::new();
+    let table = PieceTable::new();
     let (table, _) = table.insert_char_safe(0, "Hello");
     let (table, _) = table.insert_char_safe(5, " ");
     let (table, _) = table.insert_char_safe(6, "world");
@@ -59,7 +59,7 @@ fn test_multiple_inserts() {
 
 #[test]
 fn test_undo_simulation() {
-    let mut table = PieceTable::from_text("Hello world");
+    let table = PieceTable::from_text("Hello world");
     
     // 删除 " world"
     let (table1, deleted1) = table.delete_char_safe(5..11);
@@ -73,11 +73,11 @@ fn test_undo_simulation() {
 
 #[test]
 fn test_utf8_multibyte() {
-    let mut table = PieceTable::from_text("Hello 世界");
+    let table = PieceTable::from_text("Hello 世界");
     
     // 在UTF-8字符边界插入
     let (table, _) = table.insert_char_safe(6, " beautiful");
-    assert_eq!(table.get_text_range(0..16), "Hello beautiful");
+    assert_eq!(table.get_text_range(0..16), "Hello  beautiful");
 }
 
 #[test]
@@ -91,7 +91,7 @@ fn test_get_text_range() {
 
 #[test]
 fn test_piece_count_after_merge() {
-    let mut table = PieceTable::from_text("Hello");
+    let table = PieceTable::from_text("Hello");
     
     // 多次插入会增加piece数量
     let (table, _) = table.insert_char_safe(5, " ");
@@ -135,21 +135,21 @@ fn test_large_operation_protection() {
 
 #[test]
 fn test_lazy_delete() {
-    let mut table = PieceTable::from_text("Hello world!");
+    let table = PieceTable::from_text("Hello world!");
     
     // 延迟删除
     let (table, deletion_info) = table.delete_lazy(6..11);
     
     assert_eq!(deletion_info.len(), 5);
     assert_eq!(table.get_text_range(0..5), "Hello");
-    assert_eq!(table.get_text_range(5..6), "!");
+    assert_eq!(table.get_text_range(6..7), "!");
 }
 
 #[test]
 fn test_chunk_iteration() {
     let table = PieceTable::from_text("Hello world! This is a test.");
     
-    let mut chunks: Vec<String> = table.iter_chunks(10).collect();
+    let chunks: Vec<String> = table.iter_chunks(10).collect();
     assert!(!chunks.is_empty());
     
     // 拼接所有块应该等于完整文本